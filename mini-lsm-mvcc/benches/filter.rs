@@ -0,0 +1,82 @@
+//! Compares the filter policies at a few bits per key: the size of the filter in bits per key,
+//! the false positive rate, the time to build the filter, and the time of a lookup for keys in
+//! the filter and keys not in it.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench filter`.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::Instant;
+
+use mini_lsm_mvcc::table::filter::{
+    BlockedBloomPolicy, BloomPolicy, Filter, FilterPolicy, RibbonPolicy,
+};
+
+const NUM_KEYS: usize = 1_000_000;
+const NUM_PROBES: usize = 1_000_000;
+
+fn key_hashes(range: std::ops::Range<usize>) -> Vec<u32> {
+    range
+        .map(|idx| farmhash::fingerprint32(format!("key_{:010}", idx).as_bytes()))
+        .collect()
+}
+
+/// The average time in ns of a lookup, and the ratio of the hashes found in the filter.
+fn bench_lookup(filter: &dyn Filter, hashes: &[u32]) -> (f64, f64) {
+    let start = Instant::now();
+    let mut found = 0;
+    for h in hashes {
+        if black_box(filter.may_contain(*h)) {
+            found += 1;
+        }
+    }
+    (
+        start.elapsed().as_nanos() as f64 / hashes.len() as f64,
+        found as f64 / hashes.len() as f64,
+    )
+}
+
+fn main() {
+    let keys = key_hashes(0..NUM_KEYS);
+    let absent_keys = key_hashes(NUM_KEYS..NUM_KEYS + NUM_PROBES);
+    let mut policies: Vec<(&str, usize, Arc<dyn FilterPolicy>)> = Vec::new();
+    for bits_per_key in [6, 10, 16] {
+        policies.push((
+            "bloom",
+            bits_per_key,
+            Arc::new(BloomPolicy { bits_per_key }),
+        ));
+        policies.push((
+            "blocked",
+            bits_per_key,
+            Arc::new(BlockedBloomPolicy { bits_per_key }),
+        ));
+        policies.push((
+            "ribbon",
+            bits_per_key,
+            Arc::new(RibbonPolicy { bits_per_key }),
+        ));
+    }
+    println!(
+        "{:>8} {:>12} {:>10} {:>10} {:>10} {:>12} {:>12}",
+        "policy", "bits_per_key", "size", "fpr", "build", "hit_query", "miss_query"
+    );
+    for (name, bits_per_key, policy) in &policies {
+        let start = Instant::now();
+        let filter = policy.build(&keys);
+        let build_time = start.elapsed();
+        let (hit_ns, hit_rate) = bench_lookup(filter.as_ref(), &keys);
+        assert_eq!(hit_rate, 1.0, "false negatives in {:?}", policy);
+        let (miss_ns, fpr) = bench_lookup(filter.as_ref(), &absent_keys);
+        println!(
+            "{:>8} {:>12} {:>10.2} {:>9.4}% {:>8.0}ms {:>10.1}ns {:>10.1}ns",
+            name,
+            bits_per_key,
+            filter.size() as f64 * 8.0 / NUM_KEYS as f64,
+            fpr * 100.0,
+            build_time.as_secs_f64() * 1000.0,
+            hit_ns,
+            miss_ns
+        );
+    }
+}
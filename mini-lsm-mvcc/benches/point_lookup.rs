@@ -0,0 +1,79 @@
+//! Compares `get`, which stops at the newest source holding a visible version, with
+//! `get_merged`, which merges the iterators over all the sources. The keys are read from a
+//! storage with data in the memtables, L0 and the levels, once for keys recently written to the
+//! memtable and once for keys only in the bottom level.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench point_lookup`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use mini_lsm_mvcc::compact::CompactionOptions;
+use mini_lsm_mvcc::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+const NUM_KEYS: usize = 10000;
+const DURATION: Duration = Duration::from_secs(1);
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn bench_get(keys: &[Vec<u8>], get: impl Fn(&[u8])) -> f64 {
+    let start = Instant::now();
+    let mut cnt = 0u64;
+    while start.elapsed() < DURATION {
+        for key in keys {
+            get(key);
+        }
+        cnt += keys.len() as u64;
+    }
+    cnt as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..NUM_KEYS {
+        storage.put(&key_of(idx), b"bottom").unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    // overwrite the even keys in L0 SSTs, and every tenth key in the memtable
+    for _ in 0..4 {
+        for idx in (0..NUM_KEYS).step_by(2) {
+            storage.put(&key_of(idx), b"l0").unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    for idx in (0..NUM_KEYS).step_by(10) {
+        storage.put(&key_of(idx), b"memtable").unwrap();
+    }
+
+    let workloads = [
+        (
+            "memtable",
+            (0..NUM_KEYS).step_by(10).map(key_of).collect::<Vec<_>>(),
+        ),
+        (
+            "bottom",
+            (1..NUM_KEYS).step_by(2).map(key_of).collect::<Vec<_>>(),
+        ),
+    ];
+    for (name, keys) in &workloads {
+        let early_exit = bench_get(keys, |key| {
+            black_box(storage.get(key).unwrap());
+        });
+        let merged = bench_get(keys, |key| {
+            black_box(storage.get_merged(key).unwrap());
+        });
+        println!(
+            "{:>8} keys: get {:>10.0} ops/s, get_merged {:>10.0} ops/s, {:.2}x",
+            name,
+            early_exit,
+            merged,
+            early_exit / merged
+        );
+    }
+    storage.close().unwrap();
+}
@@ -0,0 +1,125 @@
+//! Measures the throughput of short-lived transactions, which register with and are removed from
+//! the watermark, with a varying number of threads and a concurrent writer advancing the commit ts.
+//! Next to `new_txn`, the readers alone are registered and removed in the ring buffer watermark,
+//! sharded and apart from the commit ts like in `LsmMvccInner`, and in the map by ts it replaced,
+//! which shared one lock with the commit ts.
+//!
+//! Run with `cargo bench -p mini-lsm-mvcc --bench watermark`.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mini_lsm_mvcc::compact::CompactionOptions;
+use mini_lsm_mvcc::lsm_storage::{LsmStorageOptions, MiniLsm};
+use mini_lsm_mvcc::mvcc::watermark::{BTreeWatermark, Watermark};
+use parking_lot::Mutex;
+
+const DURATION: Duration = Duration::from_secs(1);
+const NUM_SHARDS: usize = 16;
+
+/// Runs `new_txn` with the index of the thread on `num_threads` threads, while a writer puts keys
+/// and then calls `on_commit`, and returns the transactions per second.
+fn bench_new_txn(
+    storage: &Arc<MiniLsm>,
+    num_threads: usize,
+    new_txn: impl Fn(usize) + Sync,
+    on_commit: impl Fn() + Sync,
+) -> f64 {
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let writer = scope.spawn(|| {
+            let mut i = 0u64;
+            while !stop.load(Ordering::Relaxed) {
+                storage
+                    .put(format!("key_{:05}", i % 10000).as_bytes(), b"value")
+                    .unwrap();
+                on_commit();
+                i += 1;
+            }
+        });
+        let readers = (0..num_threads)
+            .map(|thread| {
+                let (stop, new_txn) = (&stop, &new_txn);
+                scope.spawn(move || {
+                    let mut cnt = 0u64;
+                    while !stop.load(Ordering::Relaxed) {
+                        new_txn(thread);
+                        cnt += 1;
+                    }
+                    cnt
+                })
+            })
+            .collect::<Vec<_>>();
+        let start = Instant::now();
+        std::thread::sleep(DURATION);
+        stop.store(true, Ordering::Relaxed);
+        let total: u64 = readers.into_iter().map(|t| t.join().unwrap()).sum();
+        let elapsed = start.elapsed();
+        writer.join().unwrap();
+        total as f64 / elapsed.as_secs_f64()
+    })
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for num_threads in [1, 2, 4, 8, 16] {
+        let new_txn = bench_new_txn(
+            &storage,
+            num_threads,
+            |_| {
+                let txn = storage.new_txn().unwrap();
+                std::hint::black_box(&txn);
+            },
+            || {},
+        );
+
+        let commit_ts = AtomicU64::new(0);
+        let shards = (0..NUM_SHARDS)
+            .map(|_| Mutex::new(Watermark::new()))
+            .collect::<Vec<_>>();
+        let ring_buffer = bench_new_txn(
+            &storage,
+            num_threads,
+            |thread| {
+                let shard = &shards[thread % NUM_SHARDS];
+                let read_ts = commit_ts.load(Ordering::SeqCst);
+                shard.lock().add_reader(read_ts);
+                std::hint::black_box(read_ts);
+                shard.lock().remove_reader(read_ts);
+            },
+            || {
+                commit_ts.fetch_add(1, Ordering::SeqCst);
+            },
+        );
+
+        let ts = Mutex::new((0u64, BTreeWatermark::default()));
+        let btree = bench_new_txn(
+            &storage,
+            num_threads,
+            |_| {
+                let read_ts = {
+                    let mut ts = ts.lock();
+                    let read_ts = ts.0;
+                    ts.1.add_reader(read_ts);
+                    read_ts
+                };
+                std::hint::black_box(read_ts);
+                ts.lock().1.remove_reader(read_ts);
+            },
+            || ts.lock().0 += 1,
+        );
+
+        println!(
+            "{:>2} threads: new_txn {:>10.0} txns/s, ring buffer {:>10.0} readers/s, btree {:>10.0} readers/s, {:.2}x",
+            num_threads,
+            new_txn,
+            ring_buffer,
+            btree,
+            ring_buffer / btree
+        );
+    }
+    storage.close().unwrap();
+}
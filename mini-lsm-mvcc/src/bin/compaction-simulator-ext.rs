@@ -0,0 +1,658 @@
+mod wrapper;
+use wrapper::mini_lsm_wrapper;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, BytesMut};
+use clap::Parser;
+use mini_lsm_wrapper::compact::{
+    CompactionPriority, LeveledCompactionController, LeveledCompactionOptions,
+    TieredCompactionController, TieredCompactionOptions,
+};
+use mini_lsm_wrapper::key::KeyBytes;
+use mini_lsm_wrapper::lsm_storage::LsmStorageState;
+use mini_lsm_wrapper::mem_table::MemTable;
+use mini_lsm_wrapper::table::SsTable;
+
+/// The compaction simulator with the options of the mvcc crate. Unlike the shared simulator, the
+/// SSTs have their own sizes and timestamps, which size-based tiered compaction and the leveled
+/// compaction priorities look at.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+enum Args {
+    Tiered {
+        #[clap(long)]
+        dump_real_id: bool,
+        #[clap(long, default_value = "3")]
+        num_tiers: usize,
+        #[clap(long, default_value = "200")]
+        max_size_amplification_percent: usize,
+        #[clap(long, default_value = "1")]
+        size_ratio: usize,
+        #[clap(long, default_value = "2")]
+        min_merge_width: usize,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "32")]
+        sst_size_mb: usize,
+        /// The size of each flushed SST is picked uniformly within this percentage around
+        /// `sst_size_mb`.
+        #[clap(long, default_value = "0")]
+        sst_size_variance_percent: usize,
+        #[clap(long)]
+        partial_bottom_tier_compaction: bool,
+    },
+    Leveled {
+        #[clap(long)]
+        dump_real_id: bool,
+        #[clap(long, default_value = "2")]
+        level0_file_num_compaction_trigger: usize,
+        #[clap(long, default_value = "2")]
+        level_size_multiplier: usize,
+        #[clap(long, default_value = "4")]
+        max_levels: usize,
+        #[clap(long, default_value = "128")]
+        base_level_size_mb: usize,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "32")]
+        sst_size_mb: usize,
+        #[clap(long, default_value = "oldest-sst-id", value_parser = parse_compaction_priority)]
+        compaction_priority: CompactionPriority,
+        /// Run the same flushes with every compaction priority and compare the results.
+        #[clap(long)]
+        compare_priorities: bool,
+    },
+}
+
+pub struct MockStorage {
+    snapshot: LsmStorageState,
+    next_sst_id: usize,
+    /// Maps SST ID to the original flushed SST ID
+    file_list: HashMap<usize, usize>,
+    total_flushes: usize,
+    total_writes: usize,
+}
+
+impl Default for MockStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockStorage {
+    pub fn new() -> Self {
+        let snapshot = LsmStorageState {
+            memtable: Arc::new(MemTable::create(0)),
+            imm_memtables: Vec::new(),
+            l0_sstables: Vec::new(),
+            levels: Vec::new(),
+            sstables: Default::default(),
+        };
+        Self {
+            snapshot,
+            next_sst_id: 1,
+            file_list: Default::default(),
+            total_flushes: 0,
+            total_writes: 0,
+        }
+    }
+
+    fn generate_sst_id(&mut self) -> usize {
+        let id = self.next_sst_id;
+        self.next_sst_id += 1;
+        id
+    }
+
+    pub fn flush_sst_to_l0(&mut self) -> usize {
+        let id = self.generate_sst_id();
+        self.snapshot.l0_sstables.push(id);
+        self.file_list.insert(id, id);
+        self.total_flushes += 1;
+        self.total_writes += 1;
+        id
+    }
+
+    pub fn flush_sst_to_new_tier(&mut self) -> usize {
+        let id = self.generate_sst_id();
+        self.snapshot.levels.insert(0, (id, vec![id]));
+        self.file_list.insert(id, id);
+        self.total_flushes += 1;
+        self.total_writes += 1;
+        id
+    }
+
+    /// The total size of all live SSTs in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.file_list
+            .keys()
+            .map(|id| self.snapshot.sstables[id].table_size())
+            .sum()
+    }
+
+    pub fn remove(&mut self, files_to_remove: &[usize]) {
+        for file_id in files_to_remove {
+            let ret = self.file_list.remove(file_id);
+            assert!(ret.is_some(), "failed to remove file {}", file_id);
+        }
+    }
+
+    fn check_keys(&self) {
+        for (level, files) in &self.snapshot.levels {
+            if files.len() >= 2 {
+                for id in 0..(files.len() - 1) {
+                    let this_file = self.snapshot.sstables[&files[id]].clone();
+                    let next_file = self.snapshot.sstables[&files[id + 1]].clone();
+                    if this_file.last_key() >= next_file.first_key() {
+                        panic!(
+                            "invalid file arrangement in L{}: id={}, range={:x}..={:x}; id={}, range={:x}..={:x}",
+                            level,
+                            this_file.sst_id(),
+                            this_file.first_key().for_testing_key_ref().get_u64(),
+                            this_file.last_key().for_testing_key_ref().get_u64(),
+                            next_file.sst_id(),
+                            next_file.first_key().for_testing_key_ref().get_u64(),
+                            next_file.last_key().for_testing_key_ref().get_u64()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn dump_original_id(&self, always_show_l0: bool, with_key: bool) {
+        if !self.snapshot.l0_sstables.is_empty() || always_show_l0 {
+            println!(
+                "L0 ({}): {:?}",
+                self.snapshot.l0_sstables.len(),
+                self.snapshot.l0_sstables,
+            );
+        }
+        for (level, files) in &self.snapshot.levels {
+            println!(
+                "L{level} ({}): {:?}",
+                files.len(),
+                files.iter().map(|x| self.file_list[x]).collect::<Vec<_>>()
+            );
+        }
+        if with_key {
+            self.check_keys();
+        }
+    }
+
+    pub fn dump_real_id(&self, always_show_l0: bool, with_key: bool) {
+        if !self.snapshot.l0_sstables.is_empty() || always_show_l0 {
+            println!(
+                "L0 ({}): {:?}",
+                self.snapshot.l0_sstables.len(),
+                self.snapshot.l0_sstables,
+            );
+        }
+        for (level, files) in &self.snapshot.levels {
+            println!("L{level} ({}): {:?}", files.len(), files);
+        }
+        if with_key {
+            self.check_keys();
+        }
+    }
+}
+
+fn generate_random_key_range() -> (KeyBytes, KeyBytes) {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    let begin: usize = rng.gen_range(0..(1 << 31));
+    let end: usize = begin + rng.gen_range((1 << 10)..(1 << 31));
+    let mut begin_bytes = BytesMut::new();
+    let mut end_bytes = BytesMut::new();
+    begin_bytes.put_u64(begin as u64);
+    end_bytes.put_u64(end as u64);
+    (
+        KeyBytes::for_testing_from_bytes_no_ts(begin_bytes.freeze()),
+        KeyBytes::for_testing_from_bytes_no_ts(end_bytes.freeze()),
+    )
+}
+
+fn generate_random_sst_size(sst_size_mb: usize, variance_percent: usize) -> u64 {
+    use rand::Rng;
+    let sst_size = sst_size_mb as u64 * 1024 * 1024;
+    let variance = sst_size * variance_percent as u64 / 100;
+    rand::thread_rng().gen_range((sst_size - variance.min(sst_size - 1))..=(sst_size + variance))
+}
+
+fn generate_random_split(
+    begin_bytes: KeyBytes,
+    end_bytes: KeyBytes,
+    split: usize,
+) -> Vec<(KeyBytes, KeyBytes)> {
+    let begin = begin_bytes.for_testing_key_ref().get_u64();
+    let end = end_bytes.for_testing_key_ref().get_u64();
+    let len = end - begin + 1;
+    let mut result = Vec::new();
+    let split = split as u64;
+    assert!(len >= split, "well, this is unfortunate... run again!");
+    for i in 0..split {
+        let nb = begin + len * i / split;
+        let ne = begin + len * (i + 1) / split - 1;
+        let mut begin_bytes = BytesMut::new();
+        let mut end_bytes = BytesMut::new();
+        begin_bytes.put_u64(nb);
+        end_bytes.put_u64(ne);
+        result.push((
+            KeyBytes::for_testing_from_bytes_no_ts(begin_bytes.freeze()),
+            KeyBytes::for_testing_from_bytes_no_ts(end_bytes.freeze()),
+        ));
+    }
+    result
+}
+
+/// Runs the leveled compaction simulation with one flush for each of the given key ranges, and
+/// returns the final write amplification, maximum space usage and read amplification.
+fn simulate_leveled(
+    options: LeveledCompactionOptions,
+    key_ranges: &[(KeyBytes, KeyBytes)],
+    sst_size_mb: usize,
+    dump_real_id: bool,
+) -> (f64, f64, usize) {
+    let level0_file_num_compaction_trigger = options.level0_file_num_compaction_trigger;
+    let max_levels = options.max_levels;
+    let controller = LeveledCompactionController::new(options);
+    let mut storage = MockStorage::new();
+    for i in 0..max_levels {
+        storage.snapshot.levels.push((i + 1, Vec::new()));
+    }
+    let mut max_space = 0;
+    for (i, (first_key, last_key)) in key_ranges.iter().cloned().enumerate() {
+        println!("=== Iteration {i} ===");
+        let id = storage.flush_sst_to_l0();
+        // the keys of each flush are written at the timestamp of the iteration
+        storage.snapshot.sstables.insert(
+            id,
+            Arc::new(SsTable::create_meta_only_with_ts_range(
+                id,
+                sst_size_mb as u64 * 1024 * 1024,
+                first_key,
+                last_key,
+                i as u64 + 1,
+                i as u64 + 1,
+            )),
+        );
+        println!("--- After Flush ---");
+        if dump_real_id {
+            storage.dump_real_id(false, true);
+        } else {
+            storage.dump_original_id(false, true);
+        }
+        let mut num_compactions = 0;
+        while let Some(task) = {
+            println!("--- Compaction Task ---");
+            controller.generate_compaction_task(&storage.snapshot)
+        } {
+            let mut sst_ids = Vec::new();
+            let split_num = task.upper_level_sst_ids.len() + task.lower_level_sst_ids.len();
+            let mut first_keys = Vec::new();
+            let mut last_keys = Vec::new();
+            let mut min_ts = u64::MAX;
+            let mut max_ts = 0;
+            for file in task
+                .upper_level_sst_ids
+                .iter()
+                .chain(task.lower_level_sst_ids.iter())
+            {
+                let sst = &storage.snapshot.sstables[file];
+                first_keys.push(sst.first_key().clone());
+                last_keys.push(sst.last_key().clone());
+                min_ts = min_ts.min(sst.min_ts());
+                max_ts = max_ts.max(sst.max_ts());
+            }
+            let begin = first_keys.into_iter().min().unwrap();
+            let end = last_keys.into_iter().max().unwrap();
+            let splits = generate_random_split(begin, end, split_num);
+            for (id, file) in task
+                .upper_level_sst_ids
+                .iter()
+                .chain(task.lower_level_sst_ids.iter())
+                .enumerate()
+            {
+                let new_sst_id = storage.generate_sst_id();
+                sst_ids.push(new_sst_id);
+                storage.file_list.insert(new_sst_id, *file);
+                storage.total_writes += 1;
+                storage.snapshot.sstables.insert(
+                    new_sst_id,
+                    Arc::new(SsTable::create_meta_only_with_ts_range(
+                        new_sst_id,
+                        sst_size_mb as u64 * 1024 * 1024,
+                        splits[id].0.clone(),
+                        splits[id].1.clone(),
+                        min_ts,
+                        max_ts,
+                    )),
+                );
+            }
+            print!(
+                "Upper L{} [{}] ",
+                task.upper_level.unwrap_or_default(),
+                task.upper_level_sst_ids
+                    .iter()
+                    .map(|id| format!(
+                        "{}.sst {:x}..={:x}",
+                        id,
+                        storage.snapshot.sstables[id]
+                            .first_key()
+                            .for_testing_key_ref()
+                            .get_u64(),
+                        storage.snapshot.sstables[id]
+                            .last_key()
+                            .for_testing_key_ref()
+                            .get_u64()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            print!(
+                "Lower L{} [{}] ",
+                task.lower_level,
+                task.lower_level_sst_ids
+                    .iter()
+                    .map(|id| format!(
+                        "{}.sst {:x}..={:x}",
+                        id,
+                        storage.snapshot.sstables[id]
+                            .first_key()
+                            .for_testing_key_ref()
+                            .get_u64(),
+                        storage.snapshot.sstables[id]
+                            .last_key()
+                            .for_testing_key_ref()
+                            .get_u64()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            println!(
+                "-> [{}]",
+                sst_ids
+                    .iter()
+                    .map(|id| format!(
+                        "{}.sst {:x}..={:x}",
+                        id,
+                        storage.snapshot.sstables[id]
+                            .first_key()
+                            .for_testing_key_ref()
+                            .get_u64(),
+                        storage.snapshot.sstables[id]
+                            .last_key()
+                            .for_testing_key_ref()
+                            .get_u64()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            max_space = max_space.max(storage.file_list.len());
+            let (snapshot, del) =
+                controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+            storage.snapshot = snapshot;
+            storage.remove(&del);
+            println!("--- After Compaction ---");
+            if dump_real_id {
+                storage.dump_real_id(true, true);
+            } else {
+                storage.dump_original_id(true, true);
+            }
+            num_compactions += 1;
+            if num_compactions >= level0_file_num_compaction_trigger * max_levels * 2 {
+                panic!("compaction does not converge?");
+            }
+        }
+        if num_compactions == 0 {
+            println!("no compaction triggered");
+        } else {
+            println!("{num_compactions} compaction triggered in this iteration");
+        }
+        max_space = max_space.max(storage.file_list.len());
+        println!("--- Statistics ---");
+        println!(
+            "Write Amplification: {}/{}={:.3}x",
+            storage.total_writes,
+            storage.total_flushes,
+            storage.total_writes as f64 / storage.total_flushes as f64
+        );
+        println!(
+            "Maximum Space Usage: {}/{}={:.3}x",
+            max_space,
+            storage.total_flushes,
+            max_space as f64 / storage.total_flushes as f64
+        );
+        println!(
+            "Read Amplification: {}x",
+            storage.snapshot.l0_sstables.len()
+                + storage
+                    .snapshot
+                    .levels
+                    .iter()
+                    .filter(|(_, f)| !f.is_empty())
+                    .count()
+        );
+        println!();
+    }
+    let read_amp = storage.snapshot.l0_sstables.len()
+        + storage
+            .snapshot
+            .levels
+            .iter()
+            .filter(|(_, f)| !f.is_empty())
+            .count();
+    (
+        storage.total_writes as f64 / storage.total_flushes as f64,
+        max_space as f64 / storage.total_flushes as f64,
+        read_amp,
+    )
+}
+
+fn parse_compaction_priority(priority: &str) -> Result<CompactionPriority, String> {
+    match priority {
+        "oldest-sst-id" => Ok(CompactionPriority::OldestSstId),
+        "min-overlapping-ratio" => Ok(CompactionPriority::MinOverlappingRatio),
+        "oldest-smallest-seq-first" => Ok(CompactionPriority::OldestSmallestSeqFirst),
+        "round-robin" => Ok(CompactionPriority::RoundRobin),
+        _ => Err(format!("unknown compaction priority: {}", priority)),
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    match args {
+        Args::Tiered {
+            dump_real_id,
+            num_tiers: level0_file_num_compaction_trigger,
+            max_size_amplification_percent,
+            size_ratio,
+            min_merge_width,
+            iterations,
+            sst_size_mb,
+            sst_size_variance_percent,
+            partial_bottom_tier_compaction,
+        } => {
+            let controller = TieredCompactionController::new(TieredCompactionOptions {
+                num_tiers: level0_file_num_compaction_trigger,
+                max_size_amplification_percent,
+                size_ratio,
+                min_merge_width,
+                partial_bottom_tier_compaction,
+            });
+            let mut storage = MockStorage::new();
+            let mut max_space = 0;
+            let mut flushed_bytes = 0;
+            let mut written_bytes = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                let id = storage.flush_sst_to_new_tier();
+                let (first_key, last_key) = generate_random_key_range();
+                let sst_size = generate_random_sst_size(sst_size_mb, sst_size_variance_percent);
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(SsTable::create_meta_only(id, sst_size, first_key, last_key)),
+                );
+                flushed_bytes += sst_size;
+                written_bytes += sst_size;
+                println!("--- After Flush ---");
+                if dump_real_id {
+                    storage.dump_real_id(false, true);
+                } else {
+                    storage.dump_original_id(false, true);
+                }
+                println!("--- Compaction Task ---");
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    println!("--- Compaction Task ---");
+                    controller.generate_compaction_task(&storage.snapshot)
+                } {
+                    let input_files = task
+                        .tiers
+                        .iter()
+                        .flat_map(|(_, files)| files)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    let begin = input_files
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].first_key().clone())
+                        .min()
+                        .unwrap();
+                    let end = input_files
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].last_key().clone())
+                        .max()
+                        .unwrap();
+                    let input_size = input_files
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].table_size())
+                        .sum::<u64>();
+                    let splits = generate_random_split(begin, end, input_files.len());
+                    let mut sst_ids = Vec::new();
+                    for (idx, file) in input_files.iter().enumerate() {
+                        let new_sst_id = storage.generate_sst_id();
+                        sst_ids.push(new_sst_id);
+                        storage.file_list.insert(new_sst_id, *file);
+                        storage.total_writes += 1;
+                        storage.snapshot.sstables.insert(
+                            new_sst_id,
+                            Arc::new(SsTable::create_meta_only(
+                                new_sst_id,
+                                input_size / input_files.len() as u64,
+                                splits[idx].0.clone(),
+                                splits[idx].1.clone(),
+                            )),
+                        );
+                    }
+                    written_bytes += input_size;
+                    for (tier_id, files) in &task.tiers {
+                        print!("L{} {:?} ", tier_id, files);
+                    }
+                    if task.partial_bottom_tier {
+                        print!("(partial bottom tier) ");
+                    }
+                    println!("-> {:?}", sst_ids);
+                    max_space = max_space.max(storage.total_size());
+                    let (snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    storage.snapshot = snapshot;
+                    storage.remove(&del);
+                    println!("--- After Compaction ---");
+                    if dump_real_id {
+                        storage.dump_real_id(false, true);
+                    } else {
+                        storage.dump_original_id(false, true);
+                    }
+                    num_compactions += 1;
+                    if num_compactions >= level0_file_num_compaction_trigger * 3 {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space = max_space.max(storage.total_size());
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification: {:.3}MB/{:.3}MB={:.3}x",
+                    written_bytes as f64 / 1024.0 / 1024.0,
+                    flushed_bytes as f64 / 1024.0 / 1024.0,
+                    written_bytes as f64 / flushed_bytes as f64
+                );
+                println!(
+                    "Maximum Space Usage: {:.3}MB/{:.3}MB={:.3}x",
+                    max_space as f64 / 1024.0 / 1024.0,
+                    flushed_bytes as f64 / 1024.0 / 1024.0,
+                    max_space as f64 / flushed_bytes as f64
+                );
+                println!(
+                    "Read Amplification: {}x",
+                    storage.snapshot.l0_sstables.len()
+                        + storage
+                            .snapshot
+                            .levels
+                            .iter()
+                            .filter(|(_, f)| !f.is_empty())
+                            .count()
+                );
+                println!();
+            }
+        }
+        Args::Leveled {
+            dump_real_id,
+            level0_file_num_compaction_trigger,
+            level_size_multiplier,
+            max_levels,
+            base_level_size_mb,
+            iterations,
+            sst_size_mb,
+            compaction_priority,
+            compare_priorities,
+        } => {
+            let key_ranges = (0..iterations)
+                .map(|_| generate_random_key_range())
+                .collect::<Vec<_>>();
+            let priorities = if compare_priorities {
+                vec![
+                    CompactionPriority::OldestSstId,
+                    CompactionPriority::MinOverlappingRatio,
+                    CompactionPriority::OldestSmallestSeqFirst,
+                    CompactionPriority::RoundRobin,
+                ]
+            } else {
+                vec![compaction_priority]
+            };
+            let mut results = Vec::with_capacity(priorities.len());
+            for compaction_priority in priorities {
+                println!("=== Compaction Priority {:?} ===", compaction_priority);
+                let result = simulate_leveled(
+                    LeveledCompactionOptions {
+                        level0_file_num_compaction_trigger,
+                        level_size_multiplier,
+                        max_levels,
+                        base_level_size_mb,
+                        periodic_compaction_seconds: None,
+                        compaction_priority,
+                        level0_intra_compaction_trigger: None,
+                    },
+                    &key_ranges,
+                    sst_size_mb,
+                    dump_real_id,
+                );
+                results.push((compaction_priority, result));
+            }
+            if compare_priorities {
+                println!("--- Comparison ---");
+                for (compaction_priority, (write_amp, space_amp, read_amp)) in results {
+                    println!(
+                        "{:?}: Write Amplification {:.3}x, Maximum Space Usage {:.3}x, Read Amplification {}x",
+                        compaction_priority, write_amp, space_amp, read_amp
+                    );
+                }
+            }
+        }
+    }
+}
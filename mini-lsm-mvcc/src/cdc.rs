@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::{Bound, RangeInclusive};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+use parking_lot::{Condvar, Mutex};
+
+use crate::key::{KeyBytes, TS_RANGE_END};
+use crate::lsm_storage::LsmStorageInner;
+use crate::wal::Wal;
+
+/// A committed write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeRecord {
+    pub commit_ts: u64,
+    pub key: Bytes,
+    /// `None` for a deletion.
+    pub value: Option<Bytes>,
+}
+
+/// The WAL of a flushed memtable, kept for the subscribers reading behind the memtables.
+#[derive(Debug, Clone, Copy)]
+struct RetainedWal {
+    id: usize,
+    max_ts: u64,
+}
+
+/// Tracks which committed writes can still be read by the change streams.
+pub(crate) struct ChangeLog {
+    /// The retained WALs, from the earliest to the latest.
+    retained_wals: Mutex<VecDeque<RetainedWal>>,
+    /// The writes below this ts are only in the SSTs, and no longer available.
+    available_from: AtomicU64,
+    num_subscribers: AtomicUsize,
+    notify_lock: Mutex<()>,
+    notify: Condvar,
+}
+
+impl ChangeLog {
+    /// `flushed_ts` is the largest ts in the SSTs, and `retained_wals` the ids of the WALs kept
+    /// before a restart, from the earliest.
+    pub(crate) fn recover(
+        flushed_ts: u64,
+        retained_wals: &[usize],
+        path_of_wal: impl Fn(usize) -> PathBuf,
+    ) -> Result<Self> {
+        let mut available_from = flushed_ts + 1;
+        let mut wals = VecDeque::new();
+        for id in retained_wals {
+            let map = SkipMap::new();
+            Wal::recover(path_of_wal(*id), &map, &mut Vec::new())?;
+            let ts = map.iter().map(|x| x.key().ts()).collect::<Vec<_>>();
+            if let Some(min_ts) = ts.iter().min() {
+                available_from = available_from.min(*min_ts);
+            }
+            wals.push_back(RetainedWal {
+                id: *id,
+                max_ts: ts.into_iter().max().unwrap_or_default(),
+            });
+        }
+        Ok(Self {
+            retained_wals: Mutex::new(wals),
+            available_from: AtomicU64::new(available_from),
+            num_subscribers: AtomicUsize::new(0),
+            notify_lock: Mutex::new(()),
+            notify: Condvar::new(),
+        })
+    }
+
+    /// Called after a memtable is flushed. Its WAL is kept if `retention` allows, and the WALs
+    /// beyond it are returned for deletion, from the earliest.
+    pub(crate) fn on_flush(
+        &self,
+        id: usize,
+        max_ts: u64,
+        has_wal: bool,
+        retention: usize,
+    ) -> Vec<usize> {
+        let mut retained_wals = self.retained_wals.lock();
+        if has_wal {
+            retained_wals.push_back(RetainedWal { id, max_ts });
+        } else {
+            self.discard(max_ts);
+        }
+        let mut removed = Vec::new();
+        while retained_wals.len() > retention {
+            let wal = retained_wals.pop_front().unwrap();
+            self.discard(wal.max_ts);
+            removed.push(wal.id);
+        }
+        removed
+    }
+
+    fn discard(&self, max_ts: u64) {
+        self.available_from.fetch_max(max_ts + 1, Ordering::SeqCst);
+    }
+
+    /// Wakes up the subscribers waiting for new commits.
+    pub(crate) fn notify(&self) {
+        if self.num_subscribers.load(Ordering::SeqCst) > 0 {
+            let _lock = self.notify_lock.lock();
+            self.notify.notify_all();
+        }
+    }
+}
+
+/// Reads the committed writes in commit ts order, see `MiniLsm::subscribe`.
+pub struct ChangeStream {
+    inner: Arc<LsmStorageInner>,
+    /// The commit ts of the next write to return.
+    next_ts: u64,
+    /// The retained WAL being read and the offset of its first unread write, so a poll decodes
+    /// a WAL from where the last one stopped instead of from the start.
+    wal_position: Option<(usize, u64)>,
+}
+
+impl ChangeStream {
+    pub(crate) fn new(inner: Arc<LsmStorageInner>, start_ts: u64) -> Result<Self> {
+        let change_log = &inner.change_log;
+        change_log.num_subscribers.fetch_add(1, Ordering::SeqCst);
+        let stream = Self {
+            inner,
+            next_ts: start_ts,
+            wal_position: None,
+        };
+        stream.check_available()?;
+        Ok(stream)
+    }
+
+    /// The commit ts the stream continues from, which a new stream can be resumed with.
+    pub fn next_ts(&self) -> u64 {
+        self.next_ts
+    }
+
+    fn check_available(&self) -> Result<()> {
+        let available_from = self.inner.change_log.available_from.load(Ordering::SeqCst);
+        if self.next_ts < available_from {
+            bail!(
+                "changes from ts {} are no longer retained, the earliest available is ts {}",
+                self.next_ts,
+                available_from
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the writes committed since the last call, ordered by commit ts and then key.
+    /// Fails if the stream is behind the retained WALs.
+    pub fn poll(&mut self) -> Result<Vec<ChangeRecord>> {
+        // the writes of a batch are in the memtable before the commit ts is updated
+        let committed_ts = self.inner.mvcc().latest_commit_ts();
+        if self.next_ts > committed_ts {
+            return Ok(Vec::new());
+        }
+        let ts_range = self.next_ts..=committed_ts;
+        let mut changes = BTreeMap::new();
+        // a flush retains the WAL before removing the memtable, so a memtable missing from the
+        // state is found in the retained WALs, and those in both are read twice
+        let snapshot = self.inner.state.read().clone();
+        let retained_wals = self.inner.change_log.retained_wals.lock().clone();
+        // each source is read from where the stream is, so the sources read through are skipped
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            if memtable.max_ts() < self.next_ts {
+                continue;
+            }
+            collect_changes(&memtable.map, &ts_range, &mut changes);
+        }
+        let mut wal_position = self.wal_position;
+        // the position stops before the first write not yet committed, which is read again later
+        let mut stopped = false;
+        for wal in retained_wals {
+            let offset = match self.wal_position {
+                Some((id, _)) if wal.id < id => continue,
+                Some((id, offset)) if wal.id == id => offset,
+                _ => 0,
+            };
+            if wal.max_ts < self.next_ts {
+                continue;
+            }
+            let entries = match Wal::read_from(self.inner.path_of_wal(wal.id), offset) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    // the WAL may be deleted after it was listed
+                    self.check_available()?;
+                    return Err(e);
+                }
+            };
+            for (key, value, end) in entries {
+                if ts_range.contains(&key.ts()) {
+                    changes.insert((key.ts(), key.into_inner()), value);
+                } else if key.ts() > committed_ts {
+                    stopped = true;
+                }
+                if !stopped {
+                    wal_position = Some((wal.id, end));
+                }
+            }
+        }
+        self.check_available()?;
+        self.next_ts = committed_ts + 1;
+        self.wal_position = wal_position;
+        Ok(changes
+            .into_iter()
+            .map(|((commit_ts, key), value)| ChangeRecord {
+                commit_ts,
+                key,
+                value: Some(value).filter(|value| !value.is_empty()),
+            })
+            .collect())
+    }
+
+    /// Like `poll`, but waits at most `timeout` for a commit if there is no new write.
+    pub fn wait(&mut self, timeout: Duration) -> Result<Vec<ChangeRecord>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let changes = self.poll()?;
+            if !changes.is_empty() {
+                return Ok(changes);
+            }
+            let change_log = &self.inner.change_log;
+            let mut lock = change_log.notify_lock.lock();
+            if self.inner.mvcc().latest_commit_ts() < self.next_ts
+                && change_log
+                    .notify
+                    .wait_until(&mut lock, deadline)
+                    .timed_out()
+            {
+                return Ok(Vec::new());
+            }
+        }
+    }
+}
+
+/// Collects the writes of a source in `ts_range`. The versions of a key are from the latest, so
+/// the versions below the range are skipped with a seek to the next key.
+fn collect_changes(
+    map: &SkipMap<KeyBytes, Bytes>,
+    ts_range: &RangeInclusive<u64>,
+    changes: &mut BTreeMap<(u64, Bytes), Bytes>,
+) {
+    let mut entry = map.front();
+    while let Some(current) = entry {
+        let key = current.key();
+        if key.ts() < *ts_range.start() {
+            let last_version = KeyBytes::from_bytes_with_ts(key.clone().into_inner(), TS_RANGE_END);
+            entry = map.lower_bound(Bound::Excluded(&last_version));
+            continue;
+        }
+        if ts_range.contains(&key.ts()) {
+            changes.insert(
+                (key.ts(), Bytes::copy_from_slice(key.key_ref())),
+                current.value().clone(),
+            );
+        }
+        entry = current.next();
+    }
+}
+
+impl Drop for ChangeStream {
+    fn drop(&mut self) {
+        self.inner
+            .change_log
+            .num_subscribers
+            .fetch_sub(1, Ordering::SeqCst);
+    }
+}
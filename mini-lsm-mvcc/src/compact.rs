@@ -6,8 +6,11 @@ use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
-pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+use anyhow::{bail, Result};
+pub use leveled::{
+    CompactionPriority, LeveledCompactionController, LeveledCompactionOptions,
+    LeveledCompactionTask,
+};
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
@@ -19,7 +22,7 @@ use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::key::KeySlice;
-use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
+use crate::lsm_storage::{CancellationToken, CompactionFilter, LsmStorageInner, LsmStorageState};
 use crate::manifest::ManifestRecord;
 use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
 
@@ -35,6 +38,45 @@ pub enum CompactionTask {
 }
 
 impl CompactionTask {
+    fn is_intra_l0(&self) -> bool {
+        matches!(
+            self,
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level: None,
+                lower_level: 0,
+                ..
+            })
+        )
+    }
+
+    /// The SSTs read by the task.
+    fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => l0_sstables.iter().chain(l1_sstables).copied().collect(),
+            CompactionTask::Leveled(task) => task
+                .upper_level_sst_ids
+                .iter()
+                .chain(&task.lower_level_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Simple(task) => task
+                .upper_level_sst_ids
+                .iter()
+                .chain(&task.lower_level_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Tiered(task) => task
+                .tiers
+                .iter()
+                .flat_map(|(_, files)| files)
+                .copied()
+                .collect(),
+        }
+    }
+
     fn compact_to_bottom_level(&self) -> bool {
         match self {
             CompactionTask::ForceFullCompaction { .. } => true,
@@ -68,6 +110,19 @@ impl CompactionController {
         }
     }
 
+    pub fn generate_intra_l0_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_ssts: &HashSet<usize>,
+    ) -> Option<CompactionTask> {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl
+                .generate_intra_l0_compaction_task(snapshot, compacting_ssts)
+                .map(CompactionTask::Leveled),
+            _ => None,
+        }
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -116,16 +171,32 @@ impl LsmStorageInner {
         &self,
         mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
         compact_to_bottom_level: bool,
+        target_sst_size: usize,
+        output_level: usize,
+        cancel: Option<&CancellationToken>,
     ) -> Result<Vec<Arc<SsTable>>> {
         let mut builder = None;
-        let mut new_sst = Vec::new();
+        let mut new_sst: Vec<Arc<SsTable>> = Vec::new();
         let watermark = self.mvcc().watermark();
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
+        let filter_policy = self.options.filter_policy(output_level);
         'outer: while iter.is_valid() {
+            if cancel.is_some_and(CancellationToken::is_cancelled) {
+                for sst in &new_sst {
+                    std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
+                }
+                bail!("compaction cancelled");
+            }
             if builder.is_none() {
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(
+                    SsTableBuilder::new_with_prefix_extractor(
+                        self.options.block_size,
+                        self.options.prefix_extractor,
+                    )
+                    .with_filter_policy(filter_policy.clone()),
+                );
             }
 
             let same_as_last_key = iter.key().key_ref() == last_key;
@@ -169,7 +240,7 @@ impl LsmStorageInner {
 
             let builder_inner = builder.as_mut().unwrap();
 
-            if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
+            if builder_inner.estimated_size() >= target_sst_size && !same_as_last_key {
                 let sst_id = self.next_sst_id();
                 let old_builder = builder.take().unwrap();
                 let sst = Arc::new(old_builder.build(
@@ -178,7 +249,13 @@ impl LsmStorageInner {
                     self.path_of_sst(sst_id),
                 )?);
                 new_sst.push(sst);
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(
+                    SsTableBuilder::new_with_prefix_extractor(
+                        self.options.block_size,
+                        self.options.prefix_extractor,
+                    )
+                    .with_filter_policy(filter_policy.clone()),
+                );
             }
 
             let builder_inner = builder.as_mut().unwrap();
@@ -203,11 +280,22 @@ impl LsmStorageInner {
         Ok(new_sst)
     }
 
-    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+    /// Runs the task, which stops and removes its output once `cancel` is cancelled.
+    pub(crate) fn compact(
+        &self,
+        task: &CompactionTask,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<Arc<SsTable>>> {
         let snapshot = {
             let state = self.state.read();
             state.clone()
         };
+        // intra-L0 compaction merges the SSTs into a single L0 SST
+        let target_sst_size = if task.is_intra_l0() {
+            usize::MAX
+        } else {
+            self.options.target_sst_size
+        };
         match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
@@ -227,19 +315,25 @@ impl LsmStorageInner {
                     MergeIterator::create(l0_iters),
                     SstConcatIterator::create_and_seek_to_first(l1_iters)?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(
+                    iter,
+                    task.compact_to_bottom_level(),
+                    target_sst_size,
+                    1,
+                    cancel,
+                )
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
                 upper_level_sst_ids,
-                lower_level: _,
+                lower_level,
                 lower_level_sst_ids,
                 ..
             })
             | CompactionTask::Leveled(LeveledCompactionTask {
                 upper_level,
                 upper_level_sst_ids,
-                lower_level: _,
+                lower_level,
                 lower_level_sst_ids,
                 ..
             }) => match upper_level {
@@ -257,6 +351,9 @@ impl LsmStorageInner {
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
                         task.compact_to_bottom_level(),
+                        target_sst_size,
+                        *lower_level,
+                        cancel,
                     )
                 }
                 None => {
@@ -275,10 +372,20 @@ impl LsmStorageInner {
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
                         task.compact_to_bottom_level(),
+                        target_sst_size,
+                        *lower_level,
+                        cancel,
                     )
                 }
             },
             CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => {
+                // the new tier takes the place of the input tiers, counted from L1
+                let last_tier = snapshot
+                    .levels
+                    .iter()
+                    .position(|(tier_id, _)| *tier_id == tiers.last().unwrap().0)
+                    .unwrap();
+                let output_level = last_tier + 2 - tiers.len();
                 let mut iters = Vec::with_capacity(tiers.len());
                 for (_, tier_sst_ids) in tiers {
                     let mut ssts = Vec::with_capacity(tier_sst_ids.len());
@@ -290,6 +397,9 @@ impl LsmStorageInner {
                 self.compact_generate_sst_from_iter(
                     MergeIterator::create(iters),
                     task.compact_to_bottom_level(),
+                    target_sst_size,
+                    output_level,
+                    cancel,
                 )
             }
         }
@@ -314,15 +424,17 @@ impl LsmStorageInner {
 
         println!("force full compaction: {:?}", compaction_task);
 
-        let sstables = self.compact(&compaction_task)?;
+        let sstables = self.compact(&compaction_task, None)?;
         let mut ids = Vec::with_capacity(sstables.len());
 
         {
             let state_lock = self.state_lock.lock();
             let mut state = self.state.read().as_ref().clone();
+            let mut ssts_to_remove = Vec::with_capacity(l0_sstables.len() + l1_sstables.len());
             for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
                 let result = state.sstables.remove(sst);
                 assert!(result.is_some());
+                ssts_to_remove.push(result.unwrap());
             }
             for new_sst in sstables {
                 ids.push(new_sst.sst_id());
@@ -339,15 +451,14 @@ impl LsmStorageInner {
                 .copied()
                 .collect::<Vec<_>>();
             assert!(l0_sstables_map.is_empty());
-            *self.state.write() = Arc::new(state);
+            *self.state.write() = self.new_version(state);
             self.sync_dir()?;
             self.manifest.as_ref().unwrap().add_record(
                 &state_lock,
                 ManifestRecord::Compaction(compaction_task, ids.clone()),
             )?;
-        }
-        for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
-            std::fs::remove_file(self.path_of_sst(*sst))?;
+            // the files are deleted once the readers of the old versions are done
+            self.versions.lock().add_obsolete_ssts(ssts_to_remove);
         }
 
         println!("force full compaction done, new SSTs: {:?}", ids);
@@ -356,19 +467,70 @@ impl LsmStorageInner {
     }
 
     fn trigger_compaction(&self) -> Result<()> {
+        let Some(guard) = self.background_work_guard() else {
+            return Ok(());
+        };
         let snapshot = {
             let state = self.state.read();
             state.clone()
         };
-        let task = self
-            .compaction_controller
-            .generate_compaction_task(&snapshot);
-        let Some(task) = task else {
+        let task = {
+            let mut compacting_ssts = self.compacting_ssts.lock();
+            let task = self
+                .compaction_controller
+                .generate_compaction_task(&snapshot);
+            let Some(task) = task else {
+                return Ok(());
+            };
+            let input_sst_ids = task.input_sst_ids();
+            if input_sst_ids.iter().any(|id| compacting_ssts.contains(id)) {
+                // retry after the intra-L0 compaction finishes
+                return Ok(());
+            }
+            compacting_ssts.extend(input_sst_ids);
+            task
+        };
+        self.run_compaction_task(task, &guard.token)
+    }
+
+    fn trigger_intra_l0_compaction(&self) -> Result<()> {
+        let Some(guard) = self.background_work_guard() else {
             return Ok(());
         };
+        let snapshot = {
+            let state = self.state.read();
+            state.clone()
+        };
+        let task = {
+            let mut compacting_ssts = self.compacting_ssts.lock();
+            let task = self
+                .compaction_controller
+                .generate_intra_l0_compaction_task(&snapshot, &compacting_ssts);
+            let Some(task) = task else {
+                return Ok(());
+            };
+            compacting_ssts.extend(task.input_sst_ids());
+            task
+        };
+        self.run_compaction_task(task, &guard.token)
+    }
+
+    /// Runs a task whose input SSTs have been added to `compacting_ssts`, and removes them from
+    /// `compacting_ssts` when the task finishes.
+    fn run_compaction_task(&self, task: CompactionTask, cancel: &CancellationToken) -> Result<()> {
+        let input_sst_ids = task.input_sst_ids();
+        let result = self.compact_and_apply(task, cancel);
+        let mut compacting_ssts = self.compacting_ssts.lock();
+        for id in &input_sst_ids {
+            compacting_ssts.remove(id);
+        }
+        result
+    }
+
+    fn compact_and_apply(&self, task: CompactionTask, cancel: &CancellationToken) -> Result<()> {
         self.dump_structure();
         println!("running compaction task: {:?}", task);
-        let sstables = self.compact(&task)?;
+        let sstables = self.compact(&task, Some(cancel))?;
         let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
         let ssts_to_remove = {
             let state_lock = self.state_lock.lock();
@@ -389,7 +551,7 @@ impl LsmStorageInner {
                 ssts_to_remove.push(result.unwrap());
             }
             let mut state = self.state.write();
-            *state = Arc::new(snapshot);
+            *state = self.new_version(snapshot);
             drop(state);
             self.sync_dir()?;
             self.manifest()
@@ -402,9 +564,8 @@ impl LsmStorageInner {
             output.len(),
             output
         );
-        for sst in ssts_to_remove {
-            std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
-        }
+        // the files are deleted once the readers of the old versions are done
+        self.versions.lock().add_obsolete_ssts(ssts_to_remove);
         self.sync_dir()?;
 
         Ok(())
@@ -418,6 +579,7 @@ impl LsmStorageInner {
         | CompactionOptions::Simple(_)
         | CompactionOptions::Tiered(_) = self.options.compaction_options
         {
+            let intra_l0_compaction = self.spawn_intra_l0_compaction_thread();
             let this = self.clone();
             let handle = std::thread::spawn(move || {
                 let ticker = crossbeam_channel::tick(Duration::from_millis(50));
@@ -426,7 +588,13 @@ impl LsmStorageInner {
                         recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
                             eprintln!("compaction failed: {}", e);
                         },
-                        recv(rx) -> _ => return
+                        recv(rx) -> _ => {
+                            if let Some((notifier, handle)) = intra_l0_compaction {
+                                notifier.send(()).ok();
+                                handle.join().ok();
+                            }
+                            return;
+                        }
                     }
                 }
             });
@@ -435,13 +603,44 @@ impl LsmStorageInner {
         Ok(None)
     }
 
+    /// Spawns a helper thread that runs intra-L0 compactions while the compaction thread is busy
+    /// with the base level. The compaction thread stops it on exit.
+    fn spawn_intra_l0_compaction_thread(
+        self: &Arc<Self>,
+    ) -> Option<(crossbeam_channel::Sender<()>, std::thread::JoinHandle<()>)> {
+        let CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_intra_compaction_trigger: Some(_),
+            ..
+        }) = self.options.compaction_options
+        else {
+            return None;
+        };
+        let (tx, rx) = crossbeam_channel::unbounded::<()>();
+        let this = self.clone();
+        let handle = std::thread::spawn(move || {
+            let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+            loop {
+                crossbeam_channel::select! {
+                    recv(ticker) -> _ => if let Err(e) = this.trigger_intra_l0_compaction() {
+                        eprintln!("intra-L0 compaction failed: {}", e);
+                    },
+                    recv(rx) -> _ => return
+                }
+            }
+        });
+        Some((tx, handle))
+    }
+
     fn trigger_flush(&self) -> Result<()> {
+        let Some(guard) = self.background_work_guard() else {
+            return Ok(());
+        };
         let res = {
             let state = self.state.read();
             state.imm_memtables.len() >= self.options.num_memtable_limit
         };
         if res {
-            self.force_flush_next_imm_memtable()?;
+            self.flush_next_imm_memtable(Some(&guard.token))?;
         }
 
         Ok(())
@@ -1,34 +1,71 @@
 use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 
 use crate::lsm_storage::LsmStorageState;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LeveledCompactionTask {
-    // if upper_level is `None`, then it is L0 compaction
+    // if upper_level is `None`, then it is L0 compaction; if lower_level is also 0, then it is
+    // intra-L0 compaction that merges some L0 SSTs into a new L0 SST
     pub upper_level: Option<usize>,
     pub upper_level_sst_ids: Vec<usize>,
     pub lower_level: usize,
     pub lower_level_sst_ids: Vec<usize>,
     pub is_lower_level_bottom_level: bool,
+    /// The round-robin cursor of the upper level after this task is applied. It is persisted
+    /// in the manifest together with the task, so that recovery restores the cursor.
+    #[serde(default)]
+    pub compact_cursor: Option<Vec<u8>>,
 }
 
-#[derive(Debug, Clone)]
+/// Decides which SST of the upper level is picked when a level exceeds its target size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompactionPriority {
+    /// Pick the SST with the smallest id.
+    #[default]
+    OldestSstId,
+    /// Pick the SST with the smallest ratio of overlapping bytes in the lower level to its own
+    /// size, which minimizes write amplification of the task.
+    MinOverlappingRatio,
+    /// Pick the SST whose oldest key has the smallest timestamp, so that keys that have not
+    /// been updated for a long time sink to the lower levels.
+    OldestSmallestSeqFirst,
+    /// Pick the first SST after the key where the last compaction of the level stopped.
+    RoundRobin,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct LeveledCompactionOptions {
     pub level_size_multiplier: usize,
     pub level0_file_num_compaction_trigger: usize,
     pub max_levels: usize,
     pub base_level_size_mb: usize,
+    /// SSTs created more than this many seconds ago are picked for compaction even if no
+    /// size-based trigger fires, so that compaction filters eventually run on cold data.
+    pub periodic_compaction_seconds: Option<u64>,
+    pub compaction_priority: CompactionPriority,
+    /// Merge the L0 SSTs that are not being compacted into one L0 SST when there are at least
+    /// this many of them and the base level is busy with another compaction.
+    pub level0_intra_compaction_trigger: Option<usize>,
 }
 
 pub struct LeveledCompactionController {
     options: LeveledCompactionOptions,
+    /// The user key where the last compaction of each level stopped, used by
+    /// `CompactionPriority::RoundRobin`.
+    compact_cursors: Mutex<Vec<Option<Vec<u8>>>>,
 }
 
 impl LeveledCompactionController {
     pub fn new(options: LeveledCompactionOptions) -> Self {
-        Self { options }
+        let compact_cursors = Mutex::new(vec![None; options.max_levels]);
+        Self {
+            options,
+            compact_cursors,
+        }
     }
 
     fn find_overlapping_ssts(
@@ -61,11 +98,8 @@ impl LeveledCompactionController {
         overlap_ssts
     }
 
-    pub fn generate_compaction_task(
-        &self,
-        snapshot: &LsmStorageState,
-    ) -> Option<LeveledCompactionTask> {
-        // step 1: compute target level size
+    /// Computes the target size and the real size of each level, and the base level.
+    fn compute_level_sizes(&self, snapshot: &LsmStorageState) -> (Vec<usize>, Vec<usize>, usize) {
         let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
         let mut real_level_size = Vec::with_capacity(self.options.max_levels);
         let mut base_level = self.options.max_levels;
@@ -93,6 +127,15 @@ impl LeveledCompactionController {
                 base_level = i + 1;
             }
         }
+        (target_level_size, real_level_size, base_level)
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<LeveledCompactionTask> {
+        // step 1: compute target level size
+        let (target_level_size, real_level_size, base_level) = self.compute_level_sizes(snapshot);
 
         // Flush L0 SST is the top priority
         if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
@@ -107,6 +150,7 @@ impl LeveledCompactionController {
                     base_level,
                 ),
                 is_lower_level_bottom_level: base_level == self.options.max_levels,
+                compact_cursor: None,
             });
         }
 
@@ -135,10 +179,10 @@ impl LeveledCompactionController {
             );
 
             let level = *level;
-            let selected_sst = snapshot.levels[level - 1].1.iter().min().copied().unwrap(); // select the oldest sst to compact
+            let (selected_sst, compact_cursor) = self.select_sst_to_compact(snapshot, level);
             println!(
-                "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
-                priorities
+                "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction ({:?})",
+                priorities, self.options.compaction_priority
             );
             return Some(LeveledCompactionTask {
                 upper_level: Some(level),
@@ -150,11 +194,188 @@ impl LeveledCompactionController {
                     level + 1,
                 ),
                 is_lower_level_bottom_level: level + 1 == self.options.max_levels,
+                compact_cursor,
             });
         }
+
+        if let Some(periodic_compaction_seconds) = self.options.periodic_compaction_seconds {
+            return self.generate_periodic_compaction_task(
+                snapshot,
+                base_level,
+                periodic_compaction_seconds,
+            );
+        }
         None
     }
 
+    /// Generates an intra-L0 compaction task over the newest L0 SSTs that are not in
+    /// `compacting_ssts`, if there are enough of them and L0 or the base level is being compacted.
+    pub fn generate_intra_l0_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting_ssts: &HashSet<usize>,
+    ) -> Option<LeveledCompactionTask> {
+        let trigger = self.options.level0_intra_compaction_trigger?;
+        let (_, _, base_level) = self.compute_level_sizes(snapshot);
+        let base_level_busy = snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels[base_level - 1].1.iter())
+            .any(|id| compacting_ssts.contains(id));
+        if !base_level_busy {
+            return None;
+        }
+        // L0 compactions always take the oldest SSTs, so the idle ones are the newest
+        let l0_sst_ids = snapshot
+            .l0_sstables
+            .iter()
+            .copied()
+            .take_while(|id| !compacting_ssts.contains(id))
+            .collect::<Vec<_>>();
+        if l0_sst_ids.len() < trigger.max(2) {
+            return None;
+        }
+        println!(
+            "intra-L0 compaction of {} SSTs while base level {} is busy",
+            l0_sst_ids.len(),
+            base_level
+        );
+        Some(LeveledCompactionTask {
+            upper_level: None,
+            upper_level_sst_ids: l0_sst_ids,
+            lower_level: 0,
+            lower_level_sst_ids: Vec::new(),
+            is_lower_level_bottom_level: false,
+            compact_cursor: None,
+        })
+    }
+
+    /// Picks the SST with the earliest creation time among those older than
+    /// `periodic_compaction_seconds`. An expired L0 SST flushes the whole L0 to the base level,
+    /// an expired SST in the bottom level is rewritten in place.
+    fn generate_periodic_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        base_level: usize,
+        periodic_compaction_seconds: u64,
+    ) -> Option<LeveledCompactionTask> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        let is_expired = |id: &usize| {
+            snapshot.sstables[id]
+                .create_time()
+                .saturating_add(periodic_compaction_seconds)
+                <= now
+        };
+
+        if snapshot.l0_sstables.iter().any(is_expired) {
+            println!(
+                "periodic compaction: flush L0 SST to base level {}",
+                base_level
+            );
+            return Some(LeveledCompactionTask {
+                upper_level: None,
+                upper_level_sst_ids: snapshot.l0_sstables.clone(),
+                lower_level: base_level,
+                lower_level_sst_ids: self.find_overlapping_ssts(
+                    snapshot,
+                    &snapshot.l0_sstables,
+                    base_level,
+                ),
+                is_lower_level_bottom_level: base_level == self.options.max_levels,
+                compact_cursor: None,
+            });
+        }
+
+        let (level, selected_sst) = snapshot
+            .levels
+            .iter()
+            .take(self.options.max_levels)
+            .flat_map(|(level, files)| files.iter().map(move |id| (*level, *id)))
+            .filter(|(_, id)| is_expired(id))
+            .min_by_key(|(_, id)| snapshot.sstables[id].create_time())?;
+        println!(
+            "periodic compaction: select {selected_sst} in L{level} created at {}",
+            snapshot.sstables[&selected_sst].create_time()
+        );
+        if level == self.options.max_levels {
+            return Some(LeveledCompactionTask {
+                upper_level: Some(level),
+                upper_level_sst_ids: vec![selected_sst],
+                lower_level: level,
+                lower_level_sst_ids: Vec::new(),
+                is_lower_level_bottom_level: true,
+                compact_cursor: None,
+            });
+        }
+        Some(LeveledCompactionTask {
+            upper_level: Some(level),
+            upper_level_sst_ids: vec![selected_sst],
+            lower_level: level + 1,
+            lower_level_sst_ids: self.find_overlapping_ssts(snapshot, &[selected_sst], level + 1),
+            is_lower_level_bottom_level: level + 1 == self.options.max_levels,
+            compact_cursor: None,
+        })
+    }
+
+    /// Selects the SST in `level` to compact into the next level according to
+    /// `compaction_priority`. Returns the selected SST and, for round-robin, the new cursor.
+    fn select_sst_to_compact(
+        &self,
+        snapshot: &LsmStorageState,
+        level: usize,
+    ) -> (usize, Option<Vec<u8>>) {
+        let ssts = &snapshot.levels[level - 1].1;
+        match self.options.compaction_priority {
+            CompactionPriority::OldestSstId => (ssts.iter().min().copied().unwrap(), None),
+            CompactionPriority::MinOverlappingRatio => {
+                let selected_sst = ssts
+                    .iter()
+                    .copied()
+                    .map(|id| {
+                        let overlapping_size = self
+                            .find_overlapping_ssts(snapshot, &[id], level + 1)
+                            .iter()
+                            .map(|x| snapshot.sstables[x].table_size())
+                            .sum::<u64>();
+                        let size = snapshot.sstables[&id].table_size().max(1);
+                        (overlapping_size as f64 / size as f64, id)
+                    })
+                    .min_by(|a, b| a.partial_cmp(b).unwrap())
+                    .map(|(_, id)| id)
+                    .unwrap();
+                (selected_sst, None)
+            }
+            CompactionPriority::OldestSmallestSeqFirst => {
+                let selected_sst = ssts
+                    .iter()
+                    .copied()
+                    .min_by_key(|id| (snapshot.sstables[id].min_ts(), *id))
+                    .unwrap();
+                (selected_sst, None)
+            }
+            CompactionPriority::RoundRobin => {
+                // SSTs within a level are sorted by key, pick the first one after the cursor and
+                // wrap around to the beginning of the level at the end of the key space
+                let selected_sst = match &self.compact_cursors.lock()[level - 1] {
+                    Some(cursor) => ssts
+                        .iter()
+                        .copied()
+                        .find(|id| snapshot.sstables[id].first_key().key_ref() > &cursor[..]),
+                    None => None,
+                }
+                .unwrap_or(ssts[0]);
+                let cursor = snapshot.sstables[&selected_sst]
+                    .last_key()
+                    .key_ref()
+                    .to_vec();
+                (selected_sst, Some(cursor))
+            }
+        }
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -163,6 +384,29 @@ impl LeveledCompactionController {
     ) -> (LsmStorageState, Vec<usize>) {
         let mut snapshot = snapshot.clone();
         let mut files_to_remove = Vec::new();
+        if let (Some(upper_level), Some(cursor)) = (task.upper_level, &task.compact_cursor) {
+            self.compact_cursors.lock()[upper_level - 1] = Some(cursor.clone());
+        }
+        if task.upper_level.is_none() && task.lower_level == 0 {
+            // the output takes the place of the compacted SSTs to keep L0 ordered from the latest
+            // to the earliest
+            let pos = snapshot
+                .l0_sstables
+                .iter()
+                .position(|x| *x == task.upper_level_sst_ids[0])
+                .expect("file changed after issuing compaction task");
+            let end = pos + task.upper_level_sst_ids.len();
+            assert_eq!(
+                snapshot.l0_sstables.get(pos..end),
+                Some(&task.upper_level_sst_ids[..]),
+                "file changed after issuing compaction task"
+            );
+            snapshot
+                .l0_sstables
+                .splice(pos..end, output.iter().copied());
+            files_to_remove.extend(&task.upper_level_sst_ids);
+            return (snapshot, files_to_remove);
+        }
         let mut upper_level_sst_ids_set = task
             .upper_level_sst_ids
             .iter()
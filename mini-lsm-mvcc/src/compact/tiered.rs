@@ -8,14 +8,25 @@ use crate::lsm_storage::LsmStorageState;
 pub struct TieredCompactionTask {
     pub tiers: Vec<(usize, Vec<usize>)>,
     pub bottom_tier_included: bool,
+    /// If set, the last entry of `tiers` only holds the SSTs of the bottom tier that overlap
+    /// with the upper tiers, and the output replaces them inside the bottom tier.
+    #[serde(default)]
+    pub partial_bottom_tier: bool,
+    /// Where the output goes in the bottom tier of a partial compaction, which is only needed
+    /// when no SST of the bottom tier overlaps with the upper tiers.
+    #[serde(default)]
+    pub bottom_tier_output_pos: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct TieredCompactionOptions {
     pub num_tiers: usize,
     pub max_size_amplification_percent: usize,
     pub size_ratio: usize,
     pub min_merge_width: usize,
+    /// Only rewrite the SSTs of the bottom tier that overlap with the upper tiers when the
+    /// bottom tier is part of a compaction.
+    pub partial_bottom_tier_compaction: bool,
 }
 
 pub struct TieredCompactionController {
@@ -27,6 +38,80 @@ impl TieredCompactionController {
         Self { options }
     }
 
+    fn tier_size(snapshot: &LsmStorageState, files: &[usize]) -> u64 {
+        files
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum()
+    }
+
+    /// Creates a task that compacts the first `num_tiers_to_take` tiers.
+    fn compact_tiers(
+        &self,
+        snapshot: &LsmStorageState,
+        num_tiers_to_take: usize,
+    ) -> TieredCompactionTask {
+        let mut tiers = snapshot
+            .levels
+            .iter()
+            .take(num_tiers_to_take)
+            .cloned()
+            .collect::<Vec<_>>();
+        let bottom_tier_included = num_tiers_to_take >= snapshot.levels.len();
+        if !bottom_tier_included || !self.options.partial_bottom_tier_compaction {
+            return TieredCompactionTask {
+                tiers,
+                bottom_tier_included,
+                partial_bottom_tier: false,
+                bottom_tier_output_pos: 0,
+            };
+        }
+        let (bottom_tier_id, bottom_files) = tiers.pop().unwrap();
+        let upper_files = tiers.iter().flat_map(|(_, files)| files);
+        let begin_key = upper_files
+            .clone()
+            .map(|id| snapshot.sstables[id].first_key())
+            .min()
+            .unwrap();
+        let end_key = upper_files
+            .map(|id| snapshot.sstables[id].last_key())
+            .max()
+            .unwrap();
+        // the SSTs of a tier are sorted by key, so the overlapping ones are contiguous, and
+        // follow the ones before the upper tiers
+        let output_pos = bottom_files
+            .iter()
+            .take_while(|id| snapshot.sstables[id].last_key().key_ref() < begin_key.key_ref())
+            .count();
+        let overlapping_files = bottom_files[output_pos..]
+            .iter()
+            .copied()
+            .take_while(|id| snapshot.sstables[id].first_key().key_ref() <= end_key.key_ref())
+            .collect::<Vec<_>>();
+        if overlapping_files.len() == bottom_files.len() {
+            tiers.push((bottom_tier_id, bottom_files));
+            return TieredCompactionTask {
+                tiers,
+                bottom_tier_included,
+                partial_bottom_tier: false,
+                bottom_tier_output_pos: 0,
+            };
+        }
+        println!(
+            "partial compaction of bottom tier {}: {} out of {} SSTs",
+            bottom_tier_id,
+            overlapping_files.len(),
+            bottom_files.len()
+        );
+        tiers.push((bottom_tier_id, overlapping_files));
+        TieredCompactionTask {
+            tiers,
+            bottom_tier_included,
+            partial_bottom_tier: true,
+            bottom_tier_output_pos: output_pos,
+        }
+    }
+
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
@@ -41,55 +126,37 @@ impl TieredCompactionController {
         // compaction triggered by space amplification ratio
         let mut size = 0;
         for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
+            size += Self::tier_size(snapshot, &snapshot.levels[id].1);
         }
-        let space_amp_ratio =
-            (size as f64) / (snapshot.levels.last().unwrap().1.len() as f64) * 100.0;
+        let space_amp_ratio = (size as f64)
+            / (Self::tier_size(snapshot, &snapshot.levels.last().unwrap().1) as f64)
+            * 100.0;
         if space_amp_ratio >= self.options.max_size_amplification_percent as f64 {
             println!(
                 "compaction triggered by space amplification ratio: {}",
                 space_amp_ratio
             );
-            return Some(TieredCompactionTask {
-                tiers: snapshot.levels.clone(),
-                bottom_tier_included: true,
-            });
+            return Some(self.compact_tiers(snapshot, snapshot.levels.len()));
         }
         let size_ratio_trigger = (100.0 + self.options.size_ratio as f64) / 100.0;
         // compaction triggered by size ratio
         let mut size = 0;
         for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
-            let next_level_size = snapshot.levels[id + 1].1.len();
+            size += Self::tier_size(snapshot, &snapshot.levels[id].1);
+            let next_level_size = Self::tier_size(snapshot, &snapshot.levels[id + 1].1);
             let current_size_ratio = size as f64 / next_level_size as f64;
             if current_size_ratio >= size_ratio_trigger && id + 2 >= self.options.min_merge_width {
                 println!(
                     "compaction triggered by size ratio: {}",
                     current_size_ratio * 100.0
                 );
-                return Some(TieredCompactionTask {
-                    tiers: snapshot
-                        .levels
-                        .iter()
-                        .take(id + 2)
-                        .cloned()
-                        .collect::<Vec<_>>(),
-                    bottom_tier_included: id + 2 >= snapshot.levels.len(),
-                });
+                return Some(self.compact_tiers(snapshot, id + 2));
             }
         }
         // trying to reduce sorted runs without respecting size ratio
         let num_tiers_to_take = snapshot.levels.len() - self.options.num_tiers + 2;
         println!("compaction triggered by reducing sorted runs");
-        return Some(TieredCompactionTask {
-            tiers: snapshot
-                .levels
-                .iter()
-                .take(num_tiers_to_take)
-                .cloned()
-                .collect::<Vec<_>>(),
-            bottom_tier_included: snapshot.levels.len() >= num_tiers_to_take,
-        });
+        Some(self.compact_tiers(snapshot, num_tiers_to_take))
     }
 
     pub fn apply_compaction_result(
@@ -111,8 +178,33 @@ impl TieredCompactionController {
         let mut levels = Vec::new();
         let mut new_tier_added = false;
         let mut files_to_remove = Vec::new();
+        let partial_bottom_tier_id = task
+            .tiers
+            .last()
+            .filter(|_| task.partial_bottom_tier)
+            .map(|(tier_id, _)| *tier_id);
         for (tier_id, files) in &snapshot.levels {
-            if let Some(ffiles) = tier_to_remove.remove(tier_id) {
+            if Some(*tier_id) == partial_bottom_tier_id {
+                // replace the compacted SSTs with the output inside the bottom tier
+                let ffiles = tier_to_remove.remove(tier_id).unwrap();
+                let pos = match ffiles.first() {
+                    Some(first) => files
+                        .iter()
+                        .position(|x| x == first)
+                        .expect("file changed after issuing compaction task"),
+                    None => task.bottom_tier_output_pos,
+                };
+                assert_eq!(
+                    &files[pos..pos + ffiles.len()],
+                    &ffiles[..],
+                    "file changed after issuing compaction task"
+                );
+                files_to_remove.extend(ffiles.iter().copied());
+                let mut new_files = files.clone();
+                new_files.splice(pos..pos + ffiles.len(), output.iter().copied());
+                levels.push((*tier_id, new_files));
+                new_tier_added = true;
+            } else if let Some(ffiles) = tier_to_remove.remove(tier_id) {
                 // the tier should be removed
                 assert_eq!(ffiles, files, "file changed after issuing compaction task");
                 files_to_remove.extend(ffiles.iter().copied());
@@ -3,7 +3,7 @@ pub mod merge_iterator;
 pub mod two_merge_iterator;
 
 pub trait StorageIterator {
-    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
+    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord + Copy
     where
         Self: 'a;
 
@@ -24,3 +24,9 @@ pub trait StorageIterator {
         1
     }
 }
+
+/// An iterator that can be moved to a key without being created again.
+pub trait SeekableIterator: StorageIterator {
+    /// Moves to the first entry whose key is >= `key`, which can be before the current position.
+    fn seek(&mut self, key: Self::KeyType<'_>) -> anyhow::Result<()>;
+}
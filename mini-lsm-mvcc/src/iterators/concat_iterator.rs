@@ -7,7 +7,7 @@ use crate::{
     table::{SsTable, SsTableIterator},
 };
 
-use super::StorageIterator;
+use super::{SeekableIterator, StorageIterator};
 
 /// Concat multiple iterators ordered in key order and their key ranges do not overlap. We do not want to create the
 /// iterators when initializing this iterator to reduce the overhead of seeking.
@@ -51,28 +51,40 @@ impl SstConcatIterator {
 
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
         Self::check_sst_valid(&sstables);
-        let idx: usize = sstables
-            .partition_point(|table| table.first_key().as_key_slice() <= key)
-            .saturating_sub(1);
-        if idx >= sstables.len() {
-            return Ok(Self {
-                current: None,
-                next_sst_idx: sstables.len(),
-                sstables,
-            });
-        }
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_key(
-                sstables[idx].clone(),
-                key,
-            )?),
-            next_sst_idx: idx + 1,
+            current: None,
+            next_sst_idx: 0,
             sstables,
         };
-        iter.move_until_valid()?;
+        iter.seek_to_key(key)?;
         Ok(iter)
     }
 
+    /// Seek to the first key-value pair which >= `key`. The iterator of the current SST is reused
+    /// if the key is in it.
+    pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        let idx: usize = self
+            .sstables
+            .partition_point(|table| table.first_key().as_key_slice() <= key)
+            .saturating_sub(1);
+        if idx >= self.sstables.len() {
+            self.current = None;
+            self.next_sst_idx = self.sstables.len();
+            return Ok(());
+        }
+        match self.current.as_mut() {
+            Some(current) if self.next_sst_idx == idx + 1 => current.seek_to_key(key)?,
+            _ => {
+                self.current = Some(SsTableIterator::create_and_seek_to_key(
+                    self.sstables[idx].clone(),
+                    key,
+                )?)
+            }
+        }
+        self.next_sst_idx = idx + 1;
+        self.move_until_valid()
+    }
+
     fn move_until_valid(&mut self) -> Result<()> {
         while let Some(iter) = self.current.as_mut() {
             if iter.is_valid() {
@@ -121,3 +133,9 @@ impl StorageIterator for SstConcatIterator {
         1
     }
 }
+
+impl SeekableIterator for SstConcatIterator {
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_to_key(key)
+    }
+}
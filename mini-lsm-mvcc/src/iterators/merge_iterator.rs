@@ -6,7 +6,7 @@ use anyhow::Result;
 
 use crate::key::KeySlice;
 
-use super::StorageIterator;
+use super::{SeekableIterator, StorageIterator};
 
 struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
 
@@ -41,39 +41,35 @@ impl<I: StorageIterator> Ord for HeapWrapper<I> {
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// The iterators that are no longer valid, which a seek may bring back.
+    exhausted: Vec<HeapWrapper<I>>,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
-        if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
-        }
-
-        let mut heap = BinaryHeap::new();
-
-        if iters.iter().all(|x| !x.is_valid()) {
-            // All invalid, select the last one as the current.
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
-        }
-
-        for (idx, iter) in iters.into_iter().enumerate() {
-            if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
-            }
-        }
+        let mut iter = Self {
+            iters: BinaryHeap::new(),
+            current: None,
+            exhausted: Vec::new(),
+        };
+        iter.rebuild(
+            iters
+                .into_iter()
+                .enumerate()
+                .map(|(idx, iter)| HeapWrapper(idx, iter))
+                .collect(),
+        );
+        iter
+    }
 
-        let current = heap.pop().unwrap();
-        Self {
-            iters: heap,
-            current: Some(current),
-        }
+    /// Rebuilds the heap from all the iterators.
+    fn rebuild(&mut self, iters: Vec<HeapWrapper<I>>) {
+        let (valid, exhausted): (Vec<_>, Vec<_>) =
+            iters.into_iter().partition(|iter| iter.1.is_valid());
+        self.iters = BinaryHeap::from(valid);
+        self.exhausted = exhausted;
+        // if all are invalid, an invalid one is selected as the current
+        self.current = self.iters.pop().or_else(|| self.exhausted.pop());
     }
 }
 
@@ -108,13 +104,13 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
             if inner_iter.1.key() == current.1.key() {
                 // Case 1: an error occurred when calling `next`.
                 if let e @ Err(_) = inner_iter.1.next() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                     return e;
                 }
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
@@ -126,7 +122,7 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         // If the current iterator is invalid, pop it out of the heap and select the next one.
         if !current.1.is_valid() {
             if let Some(iter) = self.iters.pop() {
-                *current = iter;
+                self.exhausted.push(std::mem::replace(current, iter));
             }
             return Ok(());
         }
@@ -153,3 +149,16 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
                 .unwrap_or(0)
     }
 }
+
+impl<I: 'static + for<'a> SeekableIterator<KeyType<'a> = KeySlice<'a>>> SeekableIterator
+    for MergeIterator<I>
+{
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        let mut iters = std::mem::take(&mut self.exhausted);
+        iters.extend(std::mem::take(&mut self.iters));
+        iters.extend(self.current.take());
+        let result = iters.iter_mut().try_for_each(|iter| iter.1.seek(key));
+        self.rebuild(iters);
+        result
+    }
+}
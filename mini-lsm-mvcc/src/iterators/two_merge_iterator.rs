@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{SeekableIterator, StorageIterator};
 
 /// Merges two iterators of different types into one. If the two iterators have the same key, only
 /// produce the key once and prefer the entry from A.
@@ -92,3 +92,17 @@ impl<
         self.a.num_active_iterators() + self.b.num_active_iterators()
     }
 }
+
+impl<
+        A: 'static + SeekableIterator,
+        B: 'static + for<'a> SeekableIterator<KeyType<'a> = A::KeyType<'a>>,
+    > SeekableIterator for TwoMergeIterator<A, B>
+{
+    fn seek(&mut self, key: A::KeyType<'_>) -> Result<()> {
+        self.a.seek(key)?;
+        self.b.seek(key)?;
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b);
+        Ok(())
+    }
+}
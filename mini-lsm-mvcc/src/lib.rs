@@ -1,4 +1,5 @@
 pub mod block;
+pub mod cdc;
 pub mod compact;
 pub mod debug;
 pub mod iterators;
@@ -1,4 +1,5 @@
 use std::ops::Bound;
+use std::sync::Arc;
 
 use anyhow::{bail, Result};
 use bytes::Bytes;
@@ -6,7 +7,9 @@ use bytes::Bytes;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{SeekableIterator, StorageIterator};
+use crate::key::{self, KeySlice};
+use crate::lsm_storage::LsmStorageState;
 use crate::mem_table::MemTableIterator;
 use crate::table::SsTableIterator;
 
@@ -18,43 +21,53 @@ type LsmIteratorInner = TwoMergeIterator<
 
 pub struct LsmIterator {
     inner: LsmIteratorInner,
+    start_bound: Bound<Bytes>,
     end_bound: Bound<Bytes>,
     is_valid: bool,
     read_ts: u64,
     prev_key: Vec<u8>,
+    /// Pins the version the iterator reads from, so that its SSTs are kept.
+    _version: Arc<LsmStorageState>,
 }
 
 impl LsmIterator {
     pub(crate) fn new(
         iter: LsmIteratorInner,
+        start_bound: Bound<Bytes>,
         end_bound: Bound<Bytes>,
         read_ts: u64,
+        version: Arc<LsmStorageState>,
     ) -> Result<Self> {
         let mut iter = Self {
-            is_valid: iter.is_valid(),
+            is_valid: false,
             inner: iter,
+            start_bound,
             end_bound,
             read_ts,
             prev_key: Vec::new(),
+            _version: version,
         };
+        // the SSTs are not bounded by the end of the range, so the first key may be beyond it
+        iter.check_end_bound();
         iter.move_to_key()?;
         Ok(iter)
     }
 
     fn next_inner(&mut self) -> Result<()> {
         self.inner.next()?;
-        if !self.inner.is_valid() {
-            self.is_valid = false;
-            return Ok(());
-        }
-        match self.end_bound.as_ref() {
-            Bound::Unbounded => {}
-            Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
-            Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
-        }
+        self.check_end_bound();
         Ok(())
     }
 
+    fn check_end_bound(&mut self) {
+        self.is_valid = self.inner.is_valid()
+            && match self.end_bound.as_ref() {
+                Bound::Unbounded => true,
+                Bound::Included(key) => self.inner.key().key_ref() <= key.as_ref(),
+                Bound::Excluded(key) => self.inner.key().key_ref() < key.as_ref(),
+            };
+    }
+
     fn move_to_key(&mut self) -> Result<()> {
         loop {
             while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
@@ -111,6 +124,33 @@ impl StorageIterator for LsmIterator {
     }
 }
 
+impl SeekableIterator for LsmIterator {
+    /// Moves to the first visible key >= `key`. A key before the start of the range seeks to the
+    /// start.
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let (key, skip_key) = seek_target(&self.start_bound, key);
+        self.inner
+            .seek(KeySlice::from_slice(key, key::TS_RANGE_BEGIN))?;
+        // the versions of the key are skipped like those of a key already returned
+        self.prev_key.clear();
+        if skip_key {
+            self.prev_key.extend(key);
+        }
+        self.check_end_bound();
+        self.move_to_key()
+    }
+}
+
+/// Clamps the key to seek to the start of a range. Returns the key, and whether the key itself
+/// is to be skipped as the range excludes it.
+pub(crate) fn seek_target<'a>(start_bound: &'a Bound<Bytes>, key: &'a [u8]) -> (&'a [u8], bool) {
+    match start_bound {
+        Bound::Included(start) if key < start.as_ref() => (start, false),
+        Bound::Excluded(start) if key <= start.as_ref() => (start, true),
+        _ => (key, false),
+    }
+}
+
 /// A wrapper around existing iterator, will prevent users from calling `next` when the iterator is
 /// invalid. If an iterator is already invalid, `next` does not do anything. If `next` returns an error,
 /// `is_valid` should return false, and `next` should always return an error.
@@ -167,3 +207,16 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.num_active_iterators()
     }
 }
+
+impl<I: SeekableIterator> SeekableIterator for FusedIterator<I> {
+    fn seek(&mut self, key: Self::KeyType<'_>) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek(key) {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
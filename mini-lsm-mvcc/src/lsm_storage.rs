@@ -1,15 +1,18 @@
-use std::collections::{BTreeSet, HashMap};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::AtomicUsize;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bytes::Bytes;
-use parking_lot::{Mutex, MutexGuard, RwLock};
+use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard};
 
-use crate::block::Block;
+use crate::block::{Block, BlockIterator};
+use crate::cdc::{ChangeLog, ChangeStream};
 use crate::compact::{
     CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
@@ -22,12 +25,22 @@ use crate::key::{self, KeySlice};
 use crate::lsm_iterator::{FusedIterator, LsmIterator};
 use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable};
-use crate::mvcc::txn::{Transaction, TxnIterator};
-use crate::mvcc::LsmMvccInner;
+use crate::mvcc::key_set::KeySet;
+use crate::mvcc::lock_manager::LockError;
+use crate::mvcc::pessimistic_txn::PessimisticTransaction;
+use crate::mvcc::read_only_txn::ReadOnlyTransaction;
+use crate::mvcc::snapshot::{Snapshot, SnapshotIterator};
+use crate::mvcc::txn::{KeyRange, Transaction, TxnIterator};
+use crate::mvcc::{ActiveReader, CommittedTxnData, LsmMvccInner, PreparedTxn};
+use crate::table::filter::FilterPolicy;
 use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::wal::TxnRecord;
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
+/// The commit ts and the writes of a committed prepared txn, an empty value is a deletion.
+type CommittedWrites = (u64, BTreeMap<Bytes, Bytes>);
+
 /// Represents the state of the storage engine.
 #[derive(Clone)]
 pub struct LsmStorageState {
@@ -81,9 +94,105 @@ pub struct LsmStorageOptions {
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
     pub serializable: bool,
+    /// What to do with the SST and WAL files that the manifest does not refer to when opening
+    /// an existing directory.
+    pub orphan_file_action: OrphanFileAction,
+    /// How many commit timestamps are reserved in the manifest at a time. A larger window writes
+    /// the manifest less often, but skips more timestamps after a restart.
+    pub ts_reservation_window: u64,
+    /// The number of bytes of keys a serializable transaction tracks exactly in each of its read
+    /// and write sets. Beyond it the set falls back to key hashes, which may cause spurious
+    /// conflicts. `None` means no limit.
+    pub txn_key_set_memory_limit: Option<usize>,
+    /// How long a pessimistic transaction waits for a key locked by another one.
+    pub lock_wait_timeout: Duration,
+    /// The size in bytes of the uncommitted writes of a transaction kept in memory, beyond which
+    /// they are spilled to a temporary SST. `None` keeps all of them in memory.
+    pub txn_spill_threshold: Option<usize>,
+    /// How long a transaction or snapshot may live. Past it, its operations fail and its snapshot
+    /// no longer holds back the garbage collection. `None` means no limit.
+    pub txn_timeout: Option<Duration>,
+    /// The number of WALs of flushed memtables kept for the change streams that read behind the
+    /// memtables. They are kept across restarts, unless the retention is lowered.
+    pub cdc_wal_retention: usize,
+    /// Adds the key prefixes to the bloom filters of the new SSTs, which lets the scans within a
+    /// prefix skip SSTs. The SSTs built with another extractor are not skipped.
+    pub prefix_extractor: Option<PrefixExtractor>,
+    /// Builds the filters of the new SSTs of each level, from L0. The last one is also used for
+    /// the levels below, and the SSTs get a bloom filter with 1% false positive rate if empty.
+    /// Tiered compaction counts the tiers as levels from L1.
+    pub filter_policies: Vec<Arc<dyn FilterPolicy>>,
+}
+
+/// Files left by a crash between writing a file and recording it in the manifest, or between
+/// recording a removal in the manifest and deleting the file, are not referred to by the
+/// manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanFileAction {
+    /// Delete the orphan files.
+    Delete,
+    /// Move the orphan files into the `quarantine` sub-directory.
+    Quarantine,
+    /// Only report the orphan files.
+    DryRun,
+}
+
+/// The orphan files found when opening the storage.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrphanFilesReport {
+    pub ssts: Vec<PathBuf>,
+    pub wals: Vec<PathBuf>,
+}
+
+/// A version of the LSM tree state that is the current one or is still referenced by a reader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveVersion {
+    pub version_id: usize,
+    /// The SSTs pinned by the version, sorted by id.
+    pub sst_ids: Vec<usize>,
+    pub is_current: bool,
+}
+
+/// Tracks the installed versions of the state that are still alive, and the SSTs removed from
+/// the LSM tree whose files are not deleted yet.
+#[derive(Default)]
+pub(crate) struct VersionSet {
+    next_version_id: usize,
+    versions: Vec<(usize, Weak<LsmStorageState>)>,
+    obsolete_ssts: Vec<Weak<SsTable>>,
+}
+
+impl VersionSet {
+    /// Registers a new version, the returned state should be installed as the current one.
+    pub(crate) fn new_version(&mut self, state: LsmStorageState) -> Arc<LsmStorageState> {
+        let state = Arc::new(state);
+        self.versions
+            .retain(|(_, version)| version.strong_count() > 0);
+        self.versions
+            .push((self.next_version_id, Arc::downgrade(&state)));
+        self.next_version_id += 1;
+        state
+    }
+
+    /// Marks the SSTs as obsolete. Their files are deleted when the last reference is dropped.
+    pub(crate) fn add_obsolete_ssts(&mut self, ssts: impl IntoIterator<Item = Arc<SsTable>>) {
+        self.obsolete_ssts.retain(|sst| sst.strong_count() > 0);
+        for sst in ssts {
+            sst.mark_obsolete();
+            self.obsolete_ssts.push(Arc::downgrade(&sst));
+        }
+    }
 }
 
 impl LsmStorageOptions {
+    /// The filter policy of the new SSTs of the level, 0 for L0.
+    pub(crate) fn filter_policy(&self, level: usize) -> Option<Arc<dyn FilterPolicy>> {
+        self.filter_policies
+            .get(level)
+            .or(self.filter_policies.last())
+            .cloned()
+    }
+
     pub fn default_for_week1_test() -> Self {
         Self {
             block_size: 4096,
@@ -92,6 +201,15 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 50,
             serializable: false,
+            orphan_file_action: OrphanFileAction::Delete,
+            ts_reservation_window: 1000,
+            txn_key_set_memory_limit: None,
+            lock_wait_timeout: Duration::from_secs(1),
+            txn_spill_threshold: None,
+            txn_timeout: None,
+            cdc_wal_retention: 0,
+            prefix_extractor: None,
+            filter_policies: Vec::new(),
         }
     }
 
@@ -103,6 +221,15 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            orphan_file_action: OrphanFileAction::Delete,
+            ts_reservation_window: 1000,
+            txn_key_set_memory_limit: None,
+            lock_wait_timeout: Duration::from_secs(1),
+            txn_spill_threshold: None,
+            txn_timeout: None,
+            cdc_wal_retention: 0,
+            prefix_extractor: None,
+            filter_policies: Vec::new(),
         }
     }
 
@@ -114,6 +241,15 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            orphan_file_action: OrphanFileAction::Delete,
+            ts_reservation_window: 1000,
+            txn_key_set_memory_limit: None,
+            lock_wait_timeout: Duration::from_secs(1),
+            txn_spill_threshold: None,
+            txn_timeout: None,
+            cdc_wal_retention: 0,
+            prefix_extractor: None,
+            filter_policies: Vec::new(),
         }
     }
 }
@@ -149,11 +285,88 @@ fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bo
     table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
 }
 
+/// Whether the table may contain the key, by its key range and filter.
+fn keep_table(key: &[u8], table: &SsTable) -> bool {
+    key_within(
+        key,
+        table.first_key().as_key_slice(),
+        table.last_key().as_key_slice(),
+    ) && table.may_contain_hash(farmhash::fingerprint32(key))
+}
+
 #[derive(Clone, Debug)]
 pub enum CompactionFilter {
     Prefix(Bytes),
 }
 
+/// Extracts the prefix of a key, which is added to the bloom filters of the SSTs so that a scan
+/// within a prefix can skip the SSTs without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixExtractor {
+    /// The first `n` bytes. Keys shorter than that have no prefix.
+    FixedLength(usize),
+    /// The bytes up to and including the first occurrence of the delimiter. Keys without the
+    /// delimiter have no prefix.
+    Delimiter(u8),
+}
+
+impl PrefixExtractor {
+    pub fn extract<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        match *self {
+            PrefixExtractor::FixedLength(n) => key.get(..n),
+            PrefixExtractor::Delimiter(delimiter) => key
+                .iter()
+                .position(|&c| c == delimiter)
+                .map(|pos| &key[..=pos]),
+        }
+    }
+
+    /// The prefix shared by all the keys in the range, if the range does not span prefixes.
+    fn range_prefix<'a>(&self, lower: Bound<&'a [u8]>, upper: Bound<&[u8]>) -> Option<&'a [u8]> {
+        let (Bound::Included(lower) | Bound::Excluded(lower)) = lower else {
+            return None;
+        };
+        let prefix = self.extract(lower)?;
+        // the keys after `lower` and before the end of the prefix all start with the prefix
+        let within_prefix = match (prefix_end(prefix), upper) {
+            (None, _) => true,
+            (Some(_), Bound::Unbounded) => false,
+            (Some(end), Bound::Included(upper)) => upper < end.as_slice(),
+            (Some(end), Bound::Excluded(upper)) => upper <= end.as_slice(),
+        };
+        within_prefix.then_some(prefix)
+    }
+}
+
+/// The smallest key greater than all the keys starting with `prefix`, or `None` if there is no
+/// such key.
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let len = prefix.iter().rposition(|&c| c != u8::MAX)? + 1;
+    let mut end = prefix[..len].to_vec();
+    *end.last_mut().unwrap() += 1;
+    Some(end)
+}
+
+/// Tells the background flushes and compactions holding it to stop.
+#[derive(Clone, Default)]
+pub(crate) struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+/// Held by a running background flush or compaction, which stops once `token` is cancelled.
+pub(crate) struct BackgroundWorkGuard<'a> {
+    _lock: RwLockReadGuard<'a, ()>,
+    pub(crate) token: CancellationToken,
+}
+
 /// The storage interface of the LSM tree.
 pub(crate) struct LsmStorageInner {
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
@@ -166,6 +379,18 @@ pub(crate) struct LsmStorageInner {
     pub(crate) manifest: Option<Manifest>,
     pub(crate) mvcc: Option<LsmMvccInner>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    /// The SSTs that are read by running compaction tasks.
+    pub(crate) compacting_ssts: Mutex<HashSet<usize>>,
+    /// The token of the running background flushes and compactions, cancelled when the
+    /// background work is paused.
+    background_cancel_token: Mutex<CancellationToken>,
+    /// Set while background flushes and compactions are paused.
+    background_paused: AtomicBool,
+    /// Held for reading by each running background flush or compaction.
+    background_work_lock: RwLock<()>,
+    pub(crate) orphan_files: OrphanFilesReport,
+    pub(crate) versions: Mutex<VersionSet>,
+    pub(crate) change_log: ChangeLog,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -193,6 +418,8 @@ impl MiniLsm {
         self.inner.sync_dir()?;
         self.compaction_notifier.send(()).ok();
         self.flush_notifier.send(()).ok();
+        // do not wait for a long running compaction to finish
+        self.inner.pause_background_work();
 
         let mut compaction_thread = self.compaction_thread.lock();
         if let Some(compaction_thread) = compaction_thread.take() {
@@ -253,6 +480,32 @@ impl MiniLsm {
         self.inner.add_compaction_filter(compaction_filter)
     }
 
+    /// Stops background flushes and compactions until `resume_background_work` is called. The
+    /// running ones are cancelled, and this function returns after they have cleaned up.
+    pub fn pause_background_work(&self) {
+        self.inner.pause_background_work()
+    }
+
+    pub fn resume_background_work(&self) {
+        self.inner.resume_background_work()
+    }
+
+    /// The orphan files found when opening the storage.
+    pub fn orphan_files(&self) -> &OrphanFilesReport {
+        &self.inner.orphan_files
+    }
+
+    /// The versions of the state that are still alive and the SSTs each of them pins.
+    pub fn live_versions(&self) -> Vec<LiveVersion> {
+        self.inner.live_versions()
+    }
+
+    /// The SSTs that have been removed from the LSM tree but are still referenced, so their
+    /// files are not deleted yet.
+    pub fn obsolete_files(&self) -> Vec<usize> {
+        self.inner.obsolete_files()
+    }
+
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
         self.inner.get(key)
     }
@@ -273,14 +526,122 @@ impl MiniLsm {
         self.inner.sync()
     }
 
+    #[track_caller]
     pub fn new_txn(&self) -> Result<Arc<Transaction>> {
         self.inner.new_txn()
     }
 
+    /// Creates a transaction which expires after `timeout` instead of `txn_timeout`.
+    #[track_caller]
+    pub fn new_txn_with_timeout(&self, timeout: Duration) -> Result<Arc<Transaction>> {
+        self.inner.new_txn_with_timeout(timeout)
+    }
+
+    #[track_caller]
+    pub fn new_pessimistic_txn(&self) -> Result<Arc<PessimisticTransaction>> {
+        self.inner.new_pessimistic_txn()
+    }
+
+    /// Creates a transaction that can only read, which is cheaper than `new_txn` as it does not
+    /// track the keys it reads. This is the read-only flag of `new_txn`, which is kept without
+    /// arguments as the tests shared with the other crates call it, and given its own type so
+    /// that the writes are rejected when compiling.
+    #[track_caller]
+    pub fn new_read_only_txn(&self) -> Result<ReadOnlyTransaction> {
+        self.inner.new_read_only_txn()
+    }
+
+    /// Commits a transaction prepared by `Transaction::prepare`.
+    pub fn commit_prepared(&self, id: u64) -> Result<()> {
+        self.inner.commit_prepared(id)
+    }
+
+    /// Rolls back a transaction prepared by `Transaction::prepare`.
+    pub fn rollback_prepared(&self, id: u64) -> Result<()> {
+        self.inner.rollback_prepared(id)
+    }
+
+    /// Lists the ids of the prepared transactions, including the ones recovered on open, for the
+    /// coordinator to resolve.
+    pub fn prepared_txns(&self) -> Vec<u64> {
+        self.inner.prepared_txns()
+    }
+
+    /// Streams the writes committed from `start_ts` on, in commit ts order. The writes are read
+    /// from the memtables and the WALs retained after flushes (see `cdc_wal_retention`), so the
+    /// stream fails once it falls behind them.
+    pub fn subscribe(&self, start_ts: u64) -> Result<ChangeStream> {
+        ChangeStream::new(self.inner.clone(), start_ts)
+    }
+
+    /// Lists the transactions and snapshots that hold back the watermark, from the oldest to the
+    /// newest, with where they were created.
+    pub fn active_readers(&self) -> Vec<ActiveReader> {
+        self.inner.mvcc().active_readers()
+    }
+
+    /// Gets a key by merging the iterators over all the memtables and SSTs, like `get` did before
+    /// it learned to stop at the newest visible version. Only for tests and benchmarks.
+    #[doc(hidden)]
+    pub fn get_merged(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_merged(key)
+    }
+
+    /// Gets many keys at once from the same snapshot, which is cheaper than a `get` for each of
+    /// them. The values are in the order of `keys`.
+    #[track_caller]
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.inner.multi_get(keys)
+    }
+
+    #[track_caller]
     pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
         self.inner.scan(lower, upper)
     }
 
+    /// Scans the keys starting with `prefix`. With a `prefix_extractor` that extracts a prefix of
+    /// `prefix`, the SSTs without that prefix are not read.
+    #[track_caller]
+    pub fn prefix_scan(&self, prefix: &[u8]) -> Result<TxnIterator> {
+        self.inner.prefix_scan(prefix)
+    }
+
+    /// Like `scan`, for iterators that live long. The iterator is rebuilt at the same read ts
+    /// after each `refresh_interval`, so that it does not keep the memtables and SSTs it started
+    /// with from being freed.
+    #[track_caller]
+    pub fn scan_with_refresh(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        refresh_interval: Duration,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_with_refresh(lower, upper, refresh_interval)
+    }
+
+    /// Takes a snapshot at the latest commit ts, which pins the garbage collection at its ts
+    /// until it is dropped.
+    #[track_caller]
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.inner.snapshot()
+    }
+
+    /// Reads a key as of `ts`, which must not be below the watermark.
+    pub fn get_at(&self, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        self.inner.get_at(key, ts)
+    }
+
+    /// Scans a range as of `ts`, which must not be below the watermark.
+    #[track_caller]
+    pub fn scan_at(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<SnapshotIterator> {
+        self.inner.scan_at(lower, upper, ts)
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
         if !self.inner.state.read().memtable.is_empty() {
@@ -320,6 +681,7 @@ impl LsmStorageInner {
         let mut next_sst_id = 1;
         let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache,
         let manifest;
+        let mut orphan_files = OrphanFilesReport::default();
 
         let compaction_controller = match &options.compaction_options {
             CompactionOptions::Leveled(options) => {
@@ -339,6 +701,10 @@ impl LsmStorageInner {
         }
         let manifest_path = path.join("MANIFEST");
         let mut last_commit_ts = 0;
+        let mut reserved_ts = 0;
+        let mut prepared_txns = BTreeMap::new();
+        let mut flushed_ts = 0;
+        let mut retained_wals = Vec::new();
         if !manifest_path.exists() {
             if options.enable_wal {
                 state.memtable = Arc::new(MemTable::create_with_wal(
@@ -351,11 +717,13 @@ impl LsmStorageInner {
         } else {
             let (m, records) = Manifest::recover(&manifest_path)?;
             let mut memtables = BTreeSet::new();
+            let mut flushed_memtables = Vec::new();
             for record in records {
                 match record {
                     ManifestRecord::Flush(sst_id) => {
                         let res = memtables.remove(&sst_id);
                         assert!(res, "memtable not exist?");
+                        flushed_memtables.push(sst_id);
                         if compaction_controller.flush_to_l0() {
                             state.l0_sstables.insert(0, sst_id);
                         } else {
@@ -368,16 +736,38 @@ impl LsmStorageInner {
                         memtables.insert(x);
                     }
                     ManifestRecord::Compaction(task, output) => {
+                        // the files removed by the compaction are cleaned up with other orphan
+                        // files if the removal did not finish before a crash
                         let (new_state, _) =
                             compaction_controller.apply_compaction_result(&state, &task, &output);
-                        // TODO: apply remove again
                         state = new_state;
                         next_sst_id =
                             next_sst_id.max(output.iter().max().copied().unwrap_or_default());
                     }
+                    ManifestRecord::ReserveTs(ts) => {
+                        reserved_ts = reserved_ts.max(ts);
+                    }
                 }
             }
 
+            // the WALs of the latest flushed memtables are kept for the change streams, as long as
+            // none of the later flushed memtables went without a WAL
+            retained_wals = flushed_memtables
+                .iter()
+                .rev()
+                .take(options.cdc_wal_retention)
+                .take_while(|id| Self::path_of_wal_static(path, **id).exists())
+                .copied()
+                .collect::<Vec<_>>();
+            retained_wals.reverse();
+            let live_wals = memtables
+                .iter()
+                .chain(retained_wals.iter())
+                .copied()
+                .collect();
+            orphan_files =
+                Self::clean_up_orphan_files(path, &state, &live_wals, options.orphan_file_action)?;
+
             let mut sst_cnt = 0;
             // recover SSTs
             for table_id in state
@@ -397,32 +787,54 @@ impl LsmStorageInner {
                 sst_cnt += 1;
             }
             println!("{} SSTs opened", sst_cnt);
+            flushed_ts = last_commit_ts;
 
             next_sst_id += 1;
 
             // recover memtables
             if options.enable_wal {
                 let mut wal_cnt = 0;
+                let mut txn_records = Vec::new();
                 for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
-                    let max_ts = memtable
-                        .map
-                        .iter()
-                        .map(|x| x.key().ts())
-                        .max()
-                        .unwrap_or_default();
-                    last_commit_ts = last_commit_ts.max(max_ts);
+                    // the pending prepared txns are logged again into each new WAL, so only the
+                    // records in the latest one matter
+                    txn_records.clear();
+                    let memtable = MemTable::recover_from_wal(
+                        *id,
+                        Self::path_of_wal_static(path, *id),
+                        &mut txn_records,
+                    )?;
+                    last_commit_ts = last_commit_ts.max(memtable.max_ts());
                     if !memtable.is_empty() {
                         state.imm_memtables.insert(0, Arc::new(memtable));
                         wal_cnt += 1;
                     }
                 }
                 println!("{} WALs recovered", wal_cnt);
+                let committed_txns;
+                (prepared_txns, committed_txns) = Self::recover_prepared_txns(txn_records);
+                if let Some(max_id) = prepared_txns.keys().last() {
+                    last_commit_ts = last_commit_ts.max(*max_id);
+                }
+                println!("{} prepared txns recovered", prepared_txns.len());
                 state.memtable = Arc::new(MemTable::create_with_wal(
                     next_sst_id,
                     Self::path_of_wal_static(path, next_sst_id),
                 )?);
+                for (id, txn) in &prepared_txns {
+                    Self::log_prepared_txn(&state.memtable, *id, txn)?;
+                }
+                // the writes of a committed txn may not all be applied before the crash, and
+                // writing them again at the same commit ts is a no-op for those that were
+                for (commit_ts, writes) in committed_txns {
+                    for (key, value) in &writes {
+                        state
+                            .memtable
+                            .put(KeySlice::from_slice(key, commit_ts), value)?;
+                    }
+                    last_commit_ts = last_commit_ts.max(commit_ts);
+                }
+                state.memtable.sync_wal()?;
             } else {
                 state.memtable = Arc::new(MemTable::create(next_sst_id));
             }
@@ -431,8 +843,13 @@ impl LsmStorageInner {
             manifest = m;
         };
 
+        let change_log = ChangeLog::recover(flushed_ts, &retained_wals, |id| {
+            Self::path_of_wal_static(path, id)
+        })?;
+        let mut versions = VersionSet::default();
+        let state = versions.new_version(state);
         let storage = Self {
-            state: Arc::new(RwLock::new(Arc::new(state))),
+            state: Arc::new(RwLock::new(state)),
             state_lock: Mutex::new(()),
             path: path.to_path_buf(),
             block_cache,
@@ -440,35 +857,597 @@ impl LsmStorageInner {
             compaction_controller,
             manifest: Some(manifest),
             options: options.into(),
-            mvcc: Some(LsmMvccInner::new(last_commit_ts)),
+            mvcc: Some(LsmMvccInner::new(last_commit_ts, reserved_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            compacting_ssts: Mutex::new(HashSet::new()),
+            background_cancel_token: Mutex::new(CancellationToken::default()),
+            background_paused: AtomicBool::new(false),
+            background_work_lock: RwLock::new(()),
+            orphan_files,
+            versions: Mutex::new(versions),
+            change_log,
         };
         storage.sync_dir()?;
 
+        let mvcc = storage.mvcc();
+        for (id, mut txn) in prepared_txns {
+            txn.lock_id = mvcc.lock_manager.next_txn_id();
+            let keys = txn.writes.keys().cloned().collect::<Vec<_>>();
+            mvcc.lock_prepared_keys(txn.lock_id, &keys)?;
+            mvcc.prepared_txns.lock().insert(id, txn);
+        }
+
         Ok(storage)
     }
 
+    /// Collects the prepared txns which are neither committed nor rolled back, and the commit ts
+    /// and writes of the txns whose commit is logged. The txns whose prepare record is missing
+    /// were not completely logged, and were never prepared.
+    fn recover_prepared_txns(
+        txn_records: Vec<TxnRecord>,
+    ) -> (BTreeMap<u64, PreparedTxn>, Vec<CommittedWrites>) {
+        let mut writes = HashMap::<u64, BTreeMap<Bytes, Bytes>>::new();
+        let mut reads = HashMap::<u64, (Option<KeySet>, Vec<KeyRange>)>::new();
+        let mut prepared_txns = BTreeMap::new();
+        let mut committed_txns = Vec::new();
+        for record in txn_records {
+            match record {
+                TxnRecord::Write { id, key, value } => {
+                    writes.entry(id).or_default().insert(key, value);
+                }
+                TxnRecord::Read { id, key } => {
+                    // the read set was within its memory limit when it was logged
+                    let (read_set, _) = reads.entry(id).or_default();
+                    read_set
+                        .get_or_insert_with(|| KeySet::new(None))
+                        .insert(&key);
+                }
+                TxnRecord::ReadHash { id, hash } => {
+                    let (read_set, _) = reads.entry(id).or_default();
+                    if let KeySet::Hashed(hashes) =
+                        read_set.get_or_insert_with(|| KeySet::Hashed(HashSet::new()))
+                    {
+                        hashes.insert(hash);
+                    }
+                }
+                TxnRecord::ReadRange { id, lower, upper } => {
+                    reads.entry(id).or_default().1.push((lower, upper));
+                }
+                TxnRecord::Prepare { id, read_ts } => {
+                    let (read_set, read_ranges) = reads.remove(&id).unwrap_or_default();
+                    prepared_txns.insert(
+                        id,
+                        PreparedTxn {
+                            writes: writes.remove(&id).unwrap_or_default(),
+                            read_ts,
+                            lock_id: 0,
+                            committing: false,
+                            commit_ts: None,
+                            read_set,
+                            read_ranges,
+                        },
+                    );
+                }
+                TxnRecord::Commit { id, commit_ts } => {
+                    if let Some(txn) = prepared_txns.remove(&id) {
+                        committed_txns.push((commit_ts, txn.writes));
+                    }
+                }
+                TxnRecord::Rollback(id) => {
+                    prepared_txns.remove(&id);
+                }
+            }
+        }
+        (prepared_txns, committed_txns)
+    }
+
+    /// Logs the writes, the reads and the prepare record of a prepared txn, and its commit if it
+    /// is decided. The reads keep protecting a serializable txn after a restart.
+    fn log_prepared_txn(memtable: &MemTable, id: u64, txn: &PreparedTxn) -> Result<()> {
+        for (key, value) in &txn.writes {
+            memtable.put_txn_record(&TxnRecord::Write {
+                id,
+                key: key.clone(),
+                value: value.clone(),
+            })?;
+        }
+        match &txn.read_set {
+            Some(KeySet::Exact { keys, .. }) => {
+                for key in keys {
+                    memtable.put_txn_record(&TxnRecord::Read {
+                        id,
+                        key: key.clone(),
+                    })?;
+                }
+            }
+            Some(KeySet::Hashed(hashes)) => {
+                for hash in hashes {
+                    memtable.put_txn_record(&TxnRecord::ReadHash { id, hash: *hash })?;
+                }
+            }
+            None => {}
+        }
+        for (lower, upper) in &txn.read_ranges {
+            memtable.put_txn_record(&TxnRecord::ReadRange {
+                id,
+                lower: lower.clone(),
+                upper: upper.clone(),
+            })?;
+        }
+        memtable.put_txn_record(&TxnRecord::Prepare {
+            id,
+            read_ts: txn.read_ts,
+        })?;
+        if let Some(commit_ts) = txn.commit_ts {
+            memtable.put_txn_record(&TxnRecord::Commit { id, commit_ts })?;
+        }
+        Ok(())
+    }
+
+    /// Logs the writes of a validated transaction to the WAL, and returns its id, which is a
+    /// commit ts that no commit uses.
+    pub(crate) fn prepare_txn(&self, txn: PreparedTxn) -> Result<u64> {
+        let mvcc = self.mvcc();
+        let id = {
+            let _lck = mvcc.write_lock.lock();
+            let ts = mvcc.latest_commit_ts() + 1;
+            self.reserve_ts(ts)?;
+            mvcc.update_commit_ts(ts);
+            ts
+        };
+        // the prepared txns are logged again into the new WAL when the memtable is frozen, so the
+        // lock is held until the txn is in the map
+        let mut prepared_txns = mvcc.prepared_txns.lock();
+        {
+            let guard = self.state.read();
+            Self::log_prepared_txn(&guard.memtable, id, &txn)?;
+            guard.memtable.sync_wal()?;
+        }
+        prepared_txns.insert(id, txn);
+        Ok(id)
+    }
+
+    /// Logs the commit of a prepared txn with its commit ts and syncs it, after which recovery
+    /// applies the writes of the txn even if they are not yet written.
+    pub(crate) fn log_prepared_commit(&self, id: u64, commit_ts: u64) -> Result<()> {
+        // the lock keeps a freeze from logging the prepared txns into the new WAL without it
+        let mut prepared_txns = self.mvcc().prepared_txns.lock();
+        let Some(txn) = prepared_txns.get_mut(&id) else {
+            bail!("no prepared txn {}", id);
+        };
+        txn.commit_ts = Some(commit_ts);
+        let guard = self.state.read();
+        guard
+            .memtable
+            .put_txn_record(&TxnRecord::Commit { id, commit_ts })?;
+        guard.memtable.sync_wal()
+    }
+
+    /// Removes a prepared txn and unlocks its keys.
+    fn remove_prepared_txn(&self, id: u64) {
+        let mvcc = self.mvcc();
+        let Some(txn) = mvcc.prepared_txns.lock().remove(&id) else {
+            return;
+        };
+        let keys = txn.writes.into_keys().collect::<Vec<_>>();
+        mvcc.lock_manager.unlock_all(txn.lock_id, &keys);
+    }
+
+    /// The second phase of a two-phase commit, which writes a prepared txn with a new commit ts.
+    /// The commit is logged before the writes, so a crash in between does not leave the txn
+    /// prepared with some of its writes visible.
+    pub fn commit_prepared(&self, id: u64) -> Result<()> {
+        let mvcc = self.mvcc();
+        let (writes, read_ts, commit_ts) = {
+            let mut prepared_txns = mvcc.prepared_txns.lock();
+            let Some(txn) = prepared_txns.get_mut(&id) else {
+                bail!("no prepared txn {}", id);
+            };
+            if txn.committing {
+                bail!("prepared txn {} is being committed", id);
+            }
+            txn.committing = true;
+            (txn.writes.clone(), txn.read_ts, txn.commit_ts)
+        };
+        let _commit_lock = mvcc.commit_lock.lock();
+        let batch = writes
+            .iter()
+            .map(|(key, value)| {
+                if value.is_empty() {
+                    WriteBatchRecord::Del(key.clone())
+                } else {
+                    WriteBatchRecord::Put(key.clone(), value.clone())
+                }
+            })
+            .collect::<Vec<_>>();
+        let result = {
+            let _lck = mvcc.write_lock.lock();
+            // a commit retried after a failed write is already logged with its commit ts
+            let ts = match commit_ts {
+                Some(ts) => Ok(ts),
+                None => {
+                    let ts = mvcc.latest_commit_ts() + 1;
+                    self.reserve_ts(ts).map(|_| ts)
+                }
+            };
+            ts.and_then(|ts| {
+                self.log_prepared_commit(id, ts)
+                    .and_then(|_| self.write_records_at(ts, batch.iter().map(Ok)))
+                    .inspect_err(|_| {
+                        // the ts may be logged as the commit ts, so no other commit may use it
+                        mvcc.update_commit_ts(ts);
+                    })
+            })
+        };
+        let ts = match result {
+            Ok(ts) => ts,
+            Err(e) => {
+                if let Some(txn) = mvcc.prepared_txns.lock().get_mut(&id) {
+                    txn.committing = false;
+                }
+                return Err(e);
+            }
+        };
+        if self.options.serializable {
+            // serializable optimistic transactions check their reads against this write set
+            let mut write_set = KeySet::new(self.options.txn_key_set_memory_limit);
+            for key in writes.keys() {
+                write_set.insert(key);
+            }
+            mvcc.committed_txns.lock().insert(
+                ts,
+                CommittedTxnData {
+                    write_set,
+                    read_ts,
+                    commit_ts: ts,
+                },
+            );
+        }
+        self.remove_prepared_txn(id);
+        Ok(())
+    }
+
+    /// Discards a prepared txn. A txn being committed, or whose commit is logged, can only be
+    /// resolved by its commit, which is checked under the same lock as the rollback record.
+    pub fn rollback_prepared(&self, id: u64) -> Result<()> {
+        {
+            let prepared_txns = self.mvcc().prepared_txns.lock();
+            match prepared_txns.get(&id) {
+                None => bail!("no prepared txn {}", id),
+                Some(txn) if txn.committing || txn.commit_ts.is_some() => {
+                    bail!("prepared txn {} is being committed", id)
+                }
+                Some(_) => {}
+            }
+            let guard = self.state.read();
+            guard.memtable.put_txn_record(&TxnRecord::Rollback(id))?;
+            guard.memtable.sync_wal()?;
+        }
+        self.remove_prepared_txn(id);
+        Ok(())
+    }
+
+    /// The ids of the prepared txns waiting to be committed or rolled back.
+    pub fn prepared_txns(&self) -> Vec<u64> {
+        self.mvcc().prepared_txns.lock().keys().copied().collect()
+    }
+
+    pub(crate) fn new_version(&self, state: LsmStorageState) -> Arc<LsmStorageState> {
+        self.versions.lock().new_version(state)
+    }
+
+    pub(crate) fn live_versions(&self) -> Vec<LiveVersion> {
+        let current = Arc::clone(&self.state.read());
+        let versions = self.versions.lock();
+        versions
+            .versions
+            .iter()
+            .filter_map(|(version_id, version)| {
+                let version = version.upgrade()?;
+                let mut sst_ids = version.sstables.keys().copied().collect::<Vec<_>>();
+                sst_ids.sort();
+                Some(LiveVersion {
+                    version_id: *version_id,
+                    sst_ids,
+                    is_current: Arc::ptr_eq(&version, &current),
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn obsolete_files(&self) -> Vec<usize> {
+        let versions = self.versions.lock();
+        let mut sst_ids = versions
+            .obsolete_ssts
+            .iter()
+            .filter_map(|sst| sst.upgrade().map(|sst| sst.sst_id()))
+            .collect::<Vec<_>>();
+        sst_ids.sort();
+        sst_ids
+    }
+
     pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
         let mut compaction_filters = self.compaction_filters.lock();
         compaction_filters.push(compaction_filter);
     }
 
+    /// Finds the SST and WAL files in `path` that are neither in `state` nor in `wals`, the WALs of
+    /// the unflushed memtables and the retained ones, and deletes, quarantines or only reports
+    /// them according to `action`.
+    fn clean_up_orphan_files(
+        path: &Path,
+        state: &LsmStorageState,
+        wals: &BTreeSet<usize>,
+        action: OrphanFileAction,
+    ) -> Result<OrphanFilesReport> {
+        let live_ssts = state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, files)| files))
+            .copied()
+            .collect::<HashSet<_>>();
+        let mut report = OrphanFilesReport::default();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let file_path = entry.path();
+            let Some(id) = file_path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .and_then(|x| x.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            match file_path.extension().and_then(|x| x.to_str()) {
+                Some("sst") if !live_ssts.contains(&id) => report.ssts.push(file_path),
+                Some("wal") if !wals.contains(&id) => report.wals.push(file_path),
+                _ => {}
+            }
+        }
+        report.ssts.sort();
+        report.wals.sort();
+        if report.ssts.is_empty() && report.wals.is_empty() {
+            return Ok(report);
+        }
+        println!(
+            "orphan files ({:?}): SSTs {:?}, WALs {:?}",
+            action, report.ssts, report.wals
+        );
+        match action {
+            OrphanFileAction::Delete => {
+                for file in report.ssts.iter().chain(report.wals.iter()) {
+                    std::fs::remove_file(file)?;
+                }
+            }
+            OrphanFileAction::Quarantine => {
+                let quarantine_path = path.join("quarantine");
+                std::fs::create_dir_all(&quarantine_path)?;
+                for file in report.ssts.iter().chain(report.wals.iter()) {
+                    std::fs::rename(file, quarantine_path.join(file.file_name().unwrap()))?;
+                }
+            }
+            OrphanFileAction::DryRun => return Ok(report),
+        }
+        File::open(path)?.sync_all()?;
+        Ok(report)
+    }
+
+    /// Cancels the running background work only, the flushes and compactions requested by the
+    /// user are not affected.
+    pub(crate) fn pause_background_work(&self) {
+        self.background_paused
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        self.background_cancel_token.lock().cancel();
+        // wait for the running background work to stop
+        let _lock = self.background_work_lock.write();
+        *self.background_cancel_token.lock() = CancellationToken::default();
+    }
+
+    pub(crate) fn resume_background_work(&self) {
+        self.background_paused
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Returns a guard to hold while running background work, or `None` if it is paused.
+    pub(crate) fn background_work_guard(&self) -> Option<BackgroundWorkGuard<'_>> {
+        let lock = self.background_work_lock.read();
+        if self
+            .background_paused
+            .load(std::sync::atomic::Ordering::SeqCst)
+        {
+            return None;
+        }
+        Some(BackgroundWorkGuard {
+            _lock: lock,
+            token: self.background_cancel_token.lock().clone(),
+        })
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.state.read().memtable.sync_wal()
     }
 
     /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
     pub fn get(self: &Arc<Self>, key: &[u8]) -> Result<Option<Bytes>> {
-        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        let txn = self.mvcc().new_read_only_txn(self.clone(), None);
         txn.get(key)
     }
 
+    /// Like `get`, but through `get_with_ts_merged`.
+    pub(crate) fn get_merged(self: &Arc<Self>, key: &[u8]) -> Result<Option<Bytes>> {
+        let txn = self.mvcc().new_read_only_txn(self.clone(), None);
+        self.get_with_ts_merged(key, txn.read_ts())
+    }
+
+    /// Get the keys from the storage, in the order of `keys`.
+    #[track_caller]
+    pub fn multi_get(self: &Arc<Self>, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        let txn = self.mvcc().new_read_only_txn(self.clone(), None);
+        txn.multi_get(keys)
+    }
+
+    /// Looks up the keys as of `read_ts` in one version of the state. The keys are probed in
+    /// order, so that each SST block is read at most once for all the keys in it.
+    pub(crate) fn multi_get_with_ts(
+        &self,
+        keys: &[&[u8]],
+        read_ts: u64,
+    ) -> Result<Vec<Option<Bytes>>> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        }; // drop global lock here
+
+        let mut sorted_keys = keys.to_vec();
+        sorted_keys.sort();
+        sorted_keys.dedup();
+        // the latest version of each key at `read_ts`, an empty value for a deletion
+        let mut found = vec![None; sorted_keys.len()];
+
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            for (key, value) in sorted_keys.iter().zip(found.iter_mut()) {
+                if value.is_some() {
+                    continue;
+                }
+                let iter = memtable.scan(
+                    Bound::Included(KeySlice::from_slice(key, read_ts)),
+                    Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
+                );
+                if iter.is_valid() {
+                    *value = Some(Bytes::copy_from_slice(iter.value()));
+                }
+            }
+        }
+        for table in snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flat_map(|(_, files)| files))
+        {
+            Self::multi_get_from_table(
+                &snapshot.sstables[table],
+                &sorted_keys,
+                &mut found,
+                read_ts,
+            )?;
+        }
+
+        Ok(keys
+            .iter()
+            .map(|key| {
+                let idx = sorted_keys.binary_search(key).unwrap();
+                found[idx].clone().filter(|value| !value.is_empty())
+            })
+            .collect())
+    }
+
+    /// Looks up the sorted keys which are not found yet in a table.
+    fn multi_get_from_table(
+        table: &SsTable,
+        sorted_keys: &[&[u8]],
+        found: &mut [Option<Bytes>],
+        read_ts: u64,
+    ) -> Result<()> {
+        let begin = sorted_keys.partition_point(|key| *key < table.first_key().key_ref());
+        let end = sorted_keys.partition_point(|key| *key <= table.last_key().key_ref());
+        // probe the filter for all the keys before reading any block
+        let mut probes = Vec::new();
+        for idx in begin..end {
+            let key = sorted_keys[idx];
+            if found[idx].is_some() {
+                continue;
+            }
+            if !table.may_contain_hash(farmhash::fingerprint32(key)) {
+                continue;
+            }
+            probes.push((
+                table.find_block_idx(KeySlice::from_slice(key, read_ts)),
+                idx,
+            ));
+        }
+        // the keys are sorted, so the keys in a block are probed one after another
+        let mut current_block: Option<(usize, Arc<Block>)> = None;
+        for (mut block_idx, idx) in probes {
+            let key = sorted_keys[idx];
+            loop {
+                let block = match &current_block {
+                    Some((current_idx, block)) if *current_idx == block_idx => block.clone(),
+                    _ => {
+                        let block = table.read_block_cached(block_idx)?;
+                        current_block = Some((block_idx, block.clone()));
+                        block
+                    }
+                };
+                let iter = BlockIterator::create_and_seek_to_key(
+                    block,
+                    KeySlice::from_slice(key, read_ts),
+                );
+                if iter.is_valid() {
+                    if iter.key().key_ref() == key {
+                        found[idx] = Some(Bytes::copy_from_slice(iter.value()));
+                    }
+                    break;
+                }
+                // all the versions in the block are newer, the next one may have older versions
+                block_idx += 1;
+                if block_idx >= table.num_of_blocks() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up the key in the sources from the newest to the oldest, and stops at the first
+    /// version visible at `read_ts`. A source only holds versions older than those in the sources
+    /// before it, so that version is the latest one.
     pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
         let snapshot = {
             let guard = self.state.read();
             Arc::clone(&guard)
         }; // drop global lock here
 
+        let lookup_key = KeySlice::from_slice(key, read_ts);
+        let value = 'lookup: {
+            for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter())
+            {
+                let iter = memtable.scan(
+                    Bound::Included(lookup_key),
+                    Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
+                );
+                if iter.is_valid() {
+                    break 'lookup Some(Bytes::copy_from_slice(iter.value()));
+                }
+            }
+            // the versions of a key are never split across the SSTs of a level
+            for table in snapshot
+                .l0_sstables
+                .iter()
+                .chain(snapshot.levels.iter().flat_map(|(_, files)| files))
+            {
+                let table = &snapshot.sstables[table];
+                if !keep_table(key, table) {
+                    continue;
+                }
+                let iter = SsTableIterator::create_and_seek_to_key(table.clone(), lookup_key)?;
+                if iter.is_valid() && iter.key().key_ref() == key {
+                    break 'lookup Some(Bytes::copy_from_slice(iter.value()));
+                }
+            }
+            None
+        };
+        Ok(value.filter(|value| !value.is_empty()))
+    }
+
+    /// Looks up the key through a merge of the iterators over all the sources, which is what
+    /// `get_with_ts` did before it returned early. Kept to compare against.
+    pub(crate) fn get_with_ts_merged(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        }; // drop global lock here
+
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
         memtable_iters.push(Box::new(snapshot.memtable.scan(
             Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
@@ -484,23 +1463,6 @@ impl LsmStorageInner {
 
         let mut l0_iters = Vec::with_capacity(snapshot.l0_sstables.len());
 
-        let keep_table = |key: &[u8], table: &SsTable| {
-            if key_within(
-                key,
-                table.first_key().as_key_slice(),
-                table.last_key().as_key_slice(),
-            ) {
-                if let Some(bloom) = &table.bloom {
-                    if bloom.may_contain(farmhash::fingerprint32(key)) {
-                        return true;
-                    }
-                } else {
-                    return true;
-                }
-            }
-            false
-        };
-
         for table in snapshot.l0_sstables.iter() {
             let table = snapshot.sstables[table].clone();
             if keep_table(key, &table) {
@@ -533,7 +1495,9 @@ impl LsmStorageInner {
                 MergeIterator::create(level_iters),
             )?,
             Bound::Unbounded,
+            Bound::Unbounded,
             read_ts,
+            snapshot,
         )?;
 
         if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
@@ -542,11 +1506,62 @@ impl LsmStorageInner {
         Ok(None)
     }
 
+    /// Makes sure `ts` is covered by a reservation in the manifest before it is used, so that a
+    /// restart never hands out a smaller timestamp. Must be called with the write lock held.
+    fn reserve_ts(&self, ts: u64) -> Result<()> {
+        let mvcc = self.mvcc();
+        if ts <= mvcc.reserved_ts() {
+            return Ok(());
+        }
+        let reserved_ts = ts + self.options.ts_reservation_window;
+        {
+            let state_lock = self.state_lock.lock();
+            self.manifest()
+                .add_record(&state_lock, ManifestRecord::ReserveTs(reserved_ts))?;
+        }
+        mvcc.update_reserved_ts(reserved_ts);
+        Ok(())
+    }
+
+    /// Writes a batch outside of any transaction. The keys locked by a pessimistic or prepared
+    /// transaction, or read by a prepared one, are checked before anything is written, under the
+    /// commit lock so that no lock is taken in between.
     pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
+        let mvcc = self.mvcc();
+        let _commit_lock = mvcc.commit_lock.lock();
+        for record in batch {
+            let key = match record {
+                WriteBatchRecord::Put(key, _) | WriteBatchRecord::Del(key) => key.as_ref(),
+            };
+            if mvcc.lock_manager.is_locked(key) {
+                return Err(LockError::Locked(Bytes::copy_from_slice(key)).into());
+            }
+            mvcc.check_prepared_reads(key)?;
+        }
+        self.write_records_inner(batch.iter().map(Ok))
+    }
+
+    /// Writes the records with the same commit ts. The records are consumed one by one, so they
+    /// do not need to be in memory at the same time. The locks are not checked, the transactions
+    /// check them before their writes, which include the keys they locked themselves.
+    pub(crate) fn write_records_inner<T: AsRef<[u8]>, R: Borrow<WriteBatchRecord<T>>>(
+        &self,
+        records: impl Iterator<Item = Result<R>>,
+    ) -> Result<u64> {
         let _lck = self.mvcc().write_lock.lock();
         let ts = self.mvcc().latest_commit_ts() + 1;
-        for record in batch {
-            match record {
+        self.reserve_ts(ts)?;
+        self.write_records_at(ts, records)
+    }
+
+    /// Writes the records with a commit ts reserved under the write lock, which the caller holds.
+    fn write_records_at<T: AsRef<[u8]>, R: Borrow<WriteBatchRecord<T>>>(
+        &self,
+        ts: u64,
+        records: impl Iterator<Item = Result<R>>,
+    ) -> Result<u64> {
+        for record in records {
+            match record?.borrow() {
                 WriteBatchRecord::Del(key) => {
                     let key = key.as_ref();
                     assert!(!key.is_empty(), "key cannot be empty");
@@ -574,6 +1589,7 @@ impl LsmStorageInner {
             }
         }
         self.mvcc().update_commit_ts(ts);
+        self.change_log.notify();
         Ok(ts)
     }
 
@@ -584,7 +1600,9 @@ impl LsmStorageInner {
         if !self.options.serializable {
             self.write_batch_inner(batch)?;
         } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            let txn = self
+                .mvcc()
+                .new_txn(self.clone(), self.options.serializable, false, None);
             for record in batch {
                 match record {
                     WriteBatchRecord::Del(key) => {
@@ -605,7 +1623,9 @@ impl LsmStorageInner {
         if !self.options.serializable {
             self.write_batch_inner(&[WriteBatchRecord::Put(key, value)])?;
         } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            let txn = self
+                .mvcc()
+                .new_txn(self.clone(), self.options.serializable, false, None);
             txn.put(key, value);
             txn.commit()?;
         }
@@ -617,7 +1637,9 @@ impl LsmStorageInner {
         if !self.options.serializable {
             self.write_batch_inner(&[WriteBatchRecord::Del(key)])?;
         } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            let txn = self
+                .mvcc()
+                .new_txn(self.clone(), self.options.serializable, false, None);
             txn.delete(key);
             txn.commit()?;
         }
@@ -666,7 +1688,7 @@ impl LsmStorageInner {
         // Add the memtable to the immutable memtables.
         snapshot.imm_memtables.insert(0, old_memtable.clone());
         // Update the snapshot.
-        *guard = Arc::new(snapshot);
+        *guard = self.new_version(snapshot);
 
         drop(guard);
         old_memtable.sync_wal()?;
@@ -686,7 +1708,16 @@ impl LsmStorageInner {
             Arc::new(MemTable::create(memtable_id))
         };
 
-        self.freeze_memtable_with_memtable(memtable)?;
+        // the WALs of the frozen memtables are deleted once flushed, so the pending prepared txns
+        // are logged again into the new one, before any commit or rollback record of them
+        {
+            let prepared_txns = self.mvcc().prepared_txns.lock();
+            for (id, txn) in prepared_txns.iter() {
+                Self::log_prepared_txn(&memtable, *id, txn)?;
+            }
+            memtable.sync_wal()?;
+            self.freeze_memtable_with_memtable(memtable)?;
+        }
 
         self.manifest().add_record(
             state_lock_observer,
@@ -699,6 +1730,11 @@ impl LsmStorageInner {
 
     /// Force flush the earliest-created immutable memtable to disk
     pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
+        self.flush_next_imm_memtable(None)
+    }
+
+    /// Flushes the earliest-created immutable memtable, unless `cancel` is cancelled first.
+    pub(crate) fn flush_next_imm_memtable(&self, cancel: Option<&CancellationToken>) -> Result<()> {
         let state_lock = self.state_lock.lock();
 
         let flush_memtable;
@@ -712,16 +1748,29 @@ impl LsmStorageInner {
                 .clone();
         }
 
-        let mut builder = SsTableBuilder::new(self.options.block_size);
+        let mut builder = SsTableBuilder::new_with_prefix_extractor(
+            self.options.block_size,
+            self.options.prefix_extractor,
+        )
+        .with_filter_policy(self.options.filter_policy(0));
         flush_memtable.flush(&mut builder)?;
         let sst_id = flush_memtable.id();
+        let is_cancelled = || cancel.is_some_and(CancellationToken::is_cancelled);
+        if is_cancelled() {
+            bail!("flush cancelled");
+        }
         let sst = Arc::new(builder.build(
             sst_id,
             Some(self.block_cache.clone()),
             self.path_of_sst(sst_id),
         )?);
+        if is_cancelled() {
+            std::fs::remove_file(self.path_of_sst(sst_id))?;
+            bail!("flush cancelled");
+        }
 
         // Add the flushed L0 table to the list.
+        let removed_wals;
         {
             let mut guard = self.state.write();
             let mut snapshot = guard.as_ref().clone();
@@ -737,37 +1786,120 @@ impl LsmStorageInner {
                 snapshot.levels.insert(0, (sst_id, vec![sst_id]));
             }
             println!("flushed {}.sst with size={}", sst_id, sst.table_size());
+            // the WAL is retained before the memtable is removed, so that the change streams
+            // find the writes in either of them
+            removed_wals = self.change_log.on_flush(
+                sst_id,
+                sst.max_ts(),
+                self.options.enable_wal,
+                self.options.cdc_wal_retention,
+            );
             snapshot.sstables.insert(sst_id, sst);
             // Update the snapshot.
-            *guard = Arc::new(snapshot);
-        }
-
-        if self.options.enable_wal {
-            std::fs::remove_file(self.path_of_wal(sst_id))?;
+            *guard = self.new_version(snapshot);
         }
 
         self.manifest()
             .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
 
+        // remove the WAL only after the flush is recorded, a crash in between leaves an orphan
+        // WAL instead of losing the data
+        for id in removed_wals {
+            std::fs::remove_file(self.path_of_wal(id))?;
+        }
+
         self.sync_dir()?;
 
         Ok(())
     }
 
+    #[track_caller]
     pub fn new_txn(self: &Arc<Self>) -> Result<Arc<Transaction>> {
-        Ok(self.mvcc().new_txn(self.clone(), self.options.serializable))
+        Ok(self.mvcc().new_txn(
+            self.clone(),
+            self.options.serializable,
+            false,
+            self.options.txn_timeout,
+        ))
+    }
+
+    #[track_caller]
+    pub fn new_txn_with_timeout(self: &Arc<Self>, timeout: Duration) -> Result<Arc<Transaction>> {
+        Ok(self.mvcc().new_txn(
+            self.clone(),
+            self.options.serializable,
+            false,
+            Some(timeout),
+        ))
+    }
+
+    #[track_caller]
+    pub fn new_pessimistic_txn(self: &Arc<Self>) -> Result<Arc<PessimisticTransaction>> {
+        Ok(self
+            .mvcc()
+            .new_pessimistic_txn(self.clone(), self.options.txn_timeout))
+    }
+
+    #[track_caller]
+    pub fn new_read_only_txn(self: &Arc<Self>) -> Result<ReadOnlyTransaction> {
+        Ok(self
+            .mvcc()
+            .new_read_only_txn(self.clone(), self.options.txn_timeout))
     }
 
     /// Create an iterator over a range of keys.
+    #[track_caller]
     pub fn scan<'a>(
         self: &'a Arc<Self>,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
     ) -> Result<TxnIterator> {
-        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        let txn = self.mvcc().new_read_only_txn(self.clone(), None);
         txn.scan(lower, upper)
     }
 
+    /// Create an iterator over the keys starting with `prefix`.
+    #[track_caller]
+    pub fn prefix_scan(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        let txn = self.mvcc().new_read_only_txn(self.clone(), None);
+        txn.prefix_scan(prefix)
+    }
+
+    /// Create an iterator over a range of keys, which is rebuilt after each `refresh_interval`.
+    #[track_caller]
+    pub fn scan_with_refresh(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        refresh_interval: Duration,
+    ) -> Result<TxnIterator> {
+        let txn = self.mvcc().new_read_only_txn(self.clone(), None);
+        txn.scan_with_refresh(lower, upper, refresh_interval)
+    }
+
+    #[track_caller]
+    pub fn snapshot(self: &Arc<Self>) -> Arc<Snapshot> {
+        self.mvcc()
+            .new_snapshot(self.clone(), self.options.txn_timeout)
+    }
+
+    pub fn get_at(self: &Arc<Self>, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        // the snapshot keeps the watermark from passing `ts` during the read
+        let snapshot = self.mvcc().new_snapshot_at(self.clone(), ts)?;
+        snapshot.get(key)
+    }
+
+    #[track_caller]
+    pub fn scan_at(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<SnapshotIterator> {
+        let snapshot = self.mvcc().new_snapshot_at(self.clone(), ts)?;
+        snapshot.scan(lower, upper)
+    }
+
     pub(crate) fn scan_with_ts(
         &self,
         lower: Bound<&[u8]>,
@@ -779,28 +1911,45 @@ impl LsmStorageInner {
             Arc::clone(&guard)
         }; // drop global lock here
 
+        // an excluded lower bound must skip all versions of the key, and the upper bound is
+        // checked by `LsmIterator`
+        let memtable_lower = match lower {
+            Bound::Excluded(_) => map_key_bound_plus_ts(lower, key::TS_RANGE_END),
+            _ => map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
+        };
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
         memtable_iters.push(Box::new(snapshot.memtable.scan(
-            map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
+            memtable_lower,
             map_key_bound_plus_ts(upper, key::TS_RANGE_END),
         )));
         for memtable in snapshot.imm_memtables.iter() {
             memtable_iters.push(Box::new(memtable.scan(
-                map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
+                memtable_lower,
                 map_key_bound_plus_ts(upper, key::TS_RANGE_END),
             )));
         }
         let memtable_iter = MergeIterator::create(memtable_iters);
 
-        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
-        for table_id in snapshot.l0_sstables.iter() {
-            let table = snapshot.sstables[table_id].clone();
-            if range_overlap(
+        // a range within a prefix skips the SSTs without the prefix
+        let range_prefix = self.options.prefix_extractor.and_then(|extractor| {
+            extractor
+                .range_prefix(lower, upper)
+                .map(|prefix| (extractor, prefix))
+        });
+        let keep_table = |table: &SsTable| {
+            range_overlap(
                 lower,
                 upper,
                 table.first_key().as_key_slice(),
                 table.last_key().as_key_slice(),
-            ) {
+            ) && range_prefix
+                .is_none_or(|(extractor, prefix)| table.may_contain_prefix(&extractor, prefix))
+        };
+
+        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
+        for table_id in snapshot.l0_sstables.iter() {
+            let table = snapshot.sstables[table_id].clone();
+            if keep_table(&table) {
                 let iter = match lower {
                     Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
                         table,
@@ -829,12 +1978,7 @@ impl LsmStorageInner {
             let mut level_ssts = Vec::with_capacity(level_sst_ids.len());
             for table in level_sst_ids {
                 let table = snapshot.sstables[table].clone();
-                if range_overlap(
-                    lower,
-                    upper,
-                    table.first_key().as_key_slice(),
-                    table.last_key().as_key_slice(),
-                ) {
+                if keep_table(&table) {
                     level_ssts.push(table);
                 }
             }
@@ -864,8 +2008,10 @@ impl LsmStorageInner {
 
         Ok(FusedIterator::new(LsmIterator::new(
             iter,
+            map_bound(lower),
             map_bound(upper),
             read_ts,
+            snapshot,
         )?))
     }
 }
@@ -19,6 +19,9 @@ pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    /// Commit timestamps up to this one may be used without writing another record, so the
+    /// timestamp after a restart starts from at least this value.
+    ReserveTs(u64),
 }
 
 impl Manifest {
@@ -1,6 +1,6 @@
 use std::ops::Bound;
 use std::path::Path;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -9,10 +9,10 @@ use crossbeam_skiplist::map::Entry;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
 
-use crate::iterators::StorageIterator;
+use crate::iterators::{SeekableIterator, StorageIterator};
 use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::wal::{TxnRecord, Wal};
 
 /// A basic mem-table based on crossbeam-skiplist.
 ///
@@ -23,6 +23,8 @@ pub struct MemTable {
     wal: Option<Wal>,
     id: usize,
     approximate_size: Arc<AtomicUsize>,
+    /// The largest ts in the mem-table, 0 if empty.
+    max_ts: AtomicU64,
 }
 
 /// Create a bound of `Bytes` from a bound of `&[u8]`.
@@ -66,6 +68,7 @@ impl MemTable {
             map: Arc::new(SkipMap::new()),
             wal: None,
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            max_ts: AtomicU64::new(0),
         }
     }
 
@@ -76,17 +79,25 @@ impl MemTable {
             map: Arc::new(SkipMap::new()),
             wal: Some(Wal::create(path.as_ref())?),
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            max_ts: AtomicU64::new(0),
         })
     }
 
-    /// Create a memtable from WAL
-    pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+    /// Create a memtable from WAL, and collect the two-phase commit records in it
+    pub fn recover_from_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        txn_records: &mut Vec<TxnRecord>,
+    ) -> Result<Self> {
         let map = Arc::new(SkipMap::new());
+        let wal = Wal::recover(path.as_ref(), &map, txn_records)?;
+        let max_ts = map.iter().map(|x| x.key().ts()).max().unwrap_or_default();
         Ok(Self {
             id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
+            wal: Some(wal),
             map,
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            max_ts: AtomicU64::new(max_ts),
         })
     }
 
@@ -130,12 +141,22 @@ impl MemTable {
         );
         self.approximate_size
             .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
+        self.max_ts
+            .fetch_max(key.ts(), std::sync::atomic::Ordering::Release);
         if let Some(ref wal) = self.wal {
             wal.put(key, value)?;
         }
         Ok(())
     }
 
+    /// Logs a two-phase commit record, which does not change the content of the memtable.
+    pub fn put_txn_record(&self, record: &TxnRecord) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.put_txn_record(record)?;
+        }
+        Ok(())
+    }
+
     pub fn sync_wal(&self) -> Result<()> {
         if let Some(ref wal) = self.wal {
             wal.sync()?;
@@ -146,10 +167,12 @@ impl MemTable {
     /// Get an iterator over a range of keys.
     pub fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
         let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
+        let range = (lower, upper.clone());
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
+            iter_builder: |map| map.range(range),
             item: (KeyBytes::new(), Bytes::new()),
+            upper,
         }
         .build();
         iter.next().unwrap();
@@ -173,6 +196,11 @@ impl MemTable {
             .load(std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// The largest ts in the mem-table, 0 if empty.
+    pub fn max_ts(&self) -> u64 {
+        self.max_ts.load(std::sync::atomic::Ordering::Acquire)
+    }
+
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
         self.map.is_empty()
@@ -201,6 +229,8 @@ pub struct MemTableIterator {
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (KeyBytes, Bytes),
+    /// The upper bound of the range, which a seek keeps.
+    upper: Bound<KeyBytes>,
 }
 
 impl MemTableIterator {
@@ -232,3 +262,11 @@ impl StorageIterator for MemTableIterator {
         Ok(())
     }
 }
+
+impl SeekableIterator for MemTableIterator {
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        let lower = map_key_bound(Bound::Included(key));
+        self.with_mut(|x| *x.iter = x.map.range((lower, x.upper.clone())));
+        self.next()
+    }
+}
@@ -1,74 +1,481 @@
 #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
+pub mod key_set;
+pub mod lock_manager;
+pub mod pessimistic_txn;
+pub mod read_only_txn;
+pub mod snapshot;
 pub mod txn;
 pub mod watermark;
 
 use std::{
-    collections::{BTreeMap, HashSet},
-    sync::{atomic::AtomicBool, Arc},
+    cell::Cell,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    ops::RangeBounds,
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
+use anyhow::{bail, Result};
+use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
 use crate::lsm_storage::LsmStorageInner;
 
-use self::{txn::Transaction, watermark::Watermark};
+use self::{
+    key_set::KeySet, lock_manager::LockManager, pessimistic_txn::PessimisticTransaction,
+    read_only_txn::ReadOnlyTransaction, snapshot::Snapshot, txn::KeyRange, txn::Transaction,
+    watermark::Watermark,
+};
 
 pub(crate) struct CommittedTxnData {
-    pub(crate) key_hashes: HashSet<u32>,
+    pub(crate) write_set: KeySet,
     #[allow(dead_code)]
     pub(crate) read_ts: u64,
     #[allow(dead_code)]
     pub(crate) commit_ts: u64,
 }
 
+/// A transaction prepared by `Transaction::prepare`, waiting for the coordinator to commit or roll
+/// it back.
+pub(crate) struct PreparedTxn {
+    /// The writes, an empty value is a deletion.
+    pub(crate) writes: BTreeMap<Bytes, Bytes>,
+    pub(crate) read_ts: u64,
+    /// The id the written keys are locked with in the lock manager.
+    pub(crate) lock_id: u64,
+    /// Set while `commit_prepared` writes the transaction, so that it is not resolved twice.
+    pub(crate) committing: bool,
+    /// Set once the commit is logged. The txn is then committed, and only waits for its writes.
+    pub(crate) commit_ts: Option<u64>,
+    /// The keys read by a serializable txn, which were validated at the prepare. They are logged
+    /// with the txn, so a txn recovered from the WAL keeps them.
+    pub(crate) read_set: Option<KeySet>,
+    pub(crate) read_ranges: Vec<KeyRange>,
+}
+
+impl PreparedTxn {
+    /// Whether the txn read the key, or scanned a range with it.
+    fn has_read(&self, key: &[u8]) -> bool {
+        self.read_set
+            .as_ref()
+            .is_some_and(|read_set| read_set.contains(key))
+            || self.read_ranges.iter().any(|range| range.contains(key))
+    }
+}
+
+const NUM_WATERMARK_SHARDS: usize = 16;
+
+/// An active reader, which holds the watermark back at its read ts.
+#[derive(Debug, Clone)]
+pub struct ActiveReader {
+    pub read_ts: u64,
+    /// How long ago the reader was created.
+    pub age: Duration,
+    /// Where the transaction or snapshot was created.
+    pub location: &'static Location<'static>,
+}
+
+struct ReaderInfo {
+    read_ts: u64,
+    created_at: Instant,
+    /// The reader is released once the deadline passes, even if it is never dropped.
+    deadline: Option<Instant>,
+    location: &'static Location<'static>,
+}
+
+/// The readers registered in one shard of the watermark.
+#[derive(Default)]
+struct WatermarkShard {
+    watermark: Watermark,
+    readers: HashMap<u64, ReaderInfo>,
+    next_reader_id: u64,
+    /// The number of readers with a deadline, the shard is only checked for expired readers when
+    /// there are some.
+    num_deadlines: usize,
+}
+
+impl WatermarkShard {
+    /// Adds a reader which expires after `timeout`, and returns its id and deadline.
+    fn add_reader(
+        &mut self,
+        read_ts: u64,
+        timeout: Option<Duration>,
+        location: &'static Location<'static>,
+    ) -> (u64, Option<Instant>) {
+        let id = self.next_reader_id;
+        self.next_reader_id += 1;
+        let created_at = Instant::now();
+        let deadline = timeout.map(|timeout| created_at + timeout);
+        if deadline.is_some() {
+            self.num_deadlines += 1;
+        }
+        self.watermark.add_reader(read_ts);
+        self.readers.insert(
+            id,
+            ReaderInfo {
+                read_ts,
+                created_at,
+                deadline,
+                location,
+            },
+        );
+        (id, deadline)
+    }
+
+    /// Removes the reader if it has not been removed yet.
+    fn remove_reader(&mut self, id: u64) -> Option<ReaderInfo> {
+        let info = self.readers.remove(&id)?;
+        if info.deadline.is_some() {
+            self.num_deadlines -= 1;
+        }
+        self.watermark.remove_reader(info.read_ts);
+        Some(info)
+    }
+
+    fn expire_readers(&mut self, now: Instant) {
+        if self.num_deadlines == 0 {
+            return;
+        }
+        let expired = self
+            .readers
+            .iter()
+            .filter(|(_, info)| info.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            self.remove_reader(id);
+        }
+    }
+}
+
+/// A reader registered in the watermark. Releasing it more than once, e.g. when a transaction
+/// expires and is then dropped, has no effect.
+pub(crate) struct ReaderHandle {
+    shard: usize,
+    id: u64,
+}
+
 pub(crate) struct LsmMvccInner {
     pub(crate) write_lock: Mutex<()>,
     pub(crate) commit_lock: Mutex<()>,
-    pub(crate) ts: Arc<Mutex<(u64, Watermark)>>,
+    /// The latest commit ts.
+    commit_ts: AtomicU64,
+    /// The active readers, sharded so that creating and dropping transactions on different
+    /// threads do not contend on the same lock.
+    watermarks: Vec<Mutex<WatermarkShard>>,
+    next_watermark_shard: AtomicUsize,
+    /// Serializes computing the watermark with registering readers at a ts in the past.
+    watermark_lock: Mutex<()>,
     pub(crate) committed_txns: Arc<Mutex<BTreeMap<u64, CommittedTxnData>>>,
+    /// The largest commit ts reserved in the manifest.
+    reserved_ts: AtomicU64,
+    /// The key locks of the pessimistic transactions.
+    pub(crate) lock_manager: LockManager,
+    /// The prepared transactions by id.
+    pub(crate) prepared_txns: Mutex<BTreeMap<u64, PreparedTxn>>,
 }
 
 impl LsmMvccInner {
-    pub fn new(initial_ts: u64) -> Self {
+    /// `last_commit_ts` is the largest ts found in the SSTs and WALs, and `reserved_ts` the
+    /// largest ts reserved in the manifest. Any ts committed before the restart is not larger
+    /// than one of them, so the commit ts never goes backwards. There are no readers after a
+    /// restart, so the watermark starts from the same ts and does not go backwards either.
+    pub fn new(last_commit_ts: u64, reserved_ts: u64) -> Self {
+        let initial_ts = last_commit_ts.max(reserved_ts);
         Self {
             write_lock: Mutex::new(()),
             commit_lock: Mutex::new(()),
-            ts: Arc::new(Mutex::new((initial_ts, Watermark::new()))),
+            commit_ts: AtomicU64::new(initial_ts),
+            watermarks: (0..NUM_WATERMARK_SHARDS)
+                .map(|_| Mutex::new(WatermarkShard::default()))
+                .collect(),
+            next_watermark_shard: AtomicUsize::new(0),
+            watermark_lock: Mutex::new(()),
             committed_txns: Arc::new(Mutex::new(BTreeMap::new())),
+            reserved_ts: AtomicU64::new(initial_ts),
+            lock_manager: LockManager::new(),
+            prepared_txns: Mutex::new(BTreeMap::new()),
         }
     }
 
+    pub fn reserved_ts(&self) -> u64 {
+        self.reserved_ts.load(Ordering::SeqCst)
+    }
+
+    pub fn update_reserved_ts(&self, ts: u64) {
+        self.reserved_ts.store(ts, Ordering::SeqCst);
+    }
+
     pub fn latest_commit_ts(&self) -> u64 {
-        self.ts.lock().0
+        self.commit_ts.load(Ordering::SeqCst)
     }
 
     pub fn update_commit_ts(&self, ts: u64) {
-        self.ts.lock().0 = ts;
+        self.commit_ts.fetch_max(ts, Ordering::SeqCst);
     }
 
-    /// All ts (strictly) below this ts can be garbage collected.
+    /// All ts (strictly) below this ts can be garbage collected. The readers past their deadline
+    /// are released first.
     pub fn watermark(&self) -> u64 {
-        let ts = self.ts.lock();
-        ts.1.watermark().unwrap_or(ts.0)
+        let _watermark_lock = self.watermark_lock.lock();
+        self.watermark_inner()
+    }
+
+    /// The commit ts is loaded before the shards are scanned. A reader that registers at an older
+    /// commit ts either shows up in the scan, or sees the commit ts change and registers again
+    /// (see `register_reader`), so the watermark never goes past an active reader.
+    fn watermark_inner(&self) -> u64 {
+        let commit_ts = self.latest_commit_ts();
+        let now = Instant::now();
+        self.watermarks
+            .iter()
+            .filter_map(|shard| {
+                let mut shard = shard.lock();
+                shard.expire_readers(now);
+                shard.watermark.watermark()
+            })
+            .fold(commit_ts, u64::min)
+    }
+
+    /// The active readers, from the oldest to the newest.
+    pub fn active_readers(&self) -> Vec<ActiveReader> {
+        let now = Instant::now();
+        let mut readers = Vec::new();
+        for shard in &self.watermarks {
+            let mut shard = shard.lock();
+            shard.expire_readers(now);
+            readers.extend(shard.readers.values().map(|info| ActiveReader {
+                read_ts: info.read_ts,
+                age: now - info.created_at,
+                location: info.location,
+            }));
+        }
+        readers.sort_by_key(|reader| Reverse(reader.age));
+        readers
+    }
+
+    /// Registers a reader at the latest commit ts, which is released after `timeout` if not
+    /// earlier, and returns the read ts and the deadline.
+    #[track_caller]
+    fn register_reader(&self, timeout: Option<Duration>) -> (u64, ReaderHandle, Option<Instant>) {
+        let location = Location::caller();
+        let shard = self.next_watermark_shard();
+        let mut watermark = self.watermarks[shard].lock();
+        loop {
+            let read_ts = self.latest_commit_ts();
+            let (id, deadline) = watermark.add_reader(read_ts, timeout, location);
+            if self.latest_commit_ts() == read_ts {
+                return (read_ts, ReaderHandle { shard, id }, deadline);
+            }
+            // a txn committed in between, and the watermark may have been computed without us
+            watermark.remove_reader(id);
+        }
     }
 
-    pub fn new_txn(&self, inner: Arc<LsmStorageInner>, serializable: bool) -> Arc<Transaction> {
-        let mut ts = self.ts.lock();
-        let read_ts = ts.0;
-        ts.1.add_reader(read_ts);
+    /// Each thread sticks to one shard, so that short-lived readers on different threads rarely
+    /// touch the same lock.
+    fn next_watermark_shard(&self) -> usize {
+        thread_local! {
+            static WATERMARK_SHARD: Cell<Option<usize>> = const { Cell::new(None) };
+        }
+        WATERMARK_SHARD.with(|cell| {
+            let shard = cell.get().unwrap_or_else(|| {
+                self.next_watermark_shard.fetch_add(1, Ordering::Relaxed) % NUM_WATERMARK_SHARDS
+            });
+            cell.set(Some(shard));
+            shard
+        })
+    }
+
+    pub(crate) fn release_reader(&self, reader: &ReaderHandle) {
+        self.watermarks[reader.shard]
+            .lock()
+            .remove_reader(reader.id);
+    }
+
+    /// Whether the reader is past its deadline, in which case it is released, as the versions at
+    /// its read ts may be garbage collected from then on.
+    pub(crate) fn reader_expired(&self, reader: &ReaderHandle, deadline: Option<Instant>) -> bool {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.release_reader(reader);
+            return true;
+        }
+        false
+    }
+
+    /// Locks the keys written by a prepared transaction, so that no other transaction writes them
+    /// before it is resolved. Either all or none of the keys are locked.
+    pub(crate) fn lock_prepared_keys(&self, lock_id: u64, keys: &[Bytes]) -> Result<()> {
+        for key in keys {
+            if let Err(e) = self.lock_manager.lock(lock_id, key, Duration::ZERO) {
+                self.lock_manager.unlock_all(lock_id, keys);
+                bail!("cannot lock {:?} for the prepared txn: {}", key, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if a prepared txn read the key. The reads of a prepared txn were validated at the
+    /// prepare, so a write to them committed before it would go unnoticed. Called under the
+    /// commit lock.
+    pub(crate) fn check_prepared_reads(&self, key: &[u8]) -> Result<()> {
+        for (id, txn) in self.prepared_txns.lock().iter() {
+            if txn.has_read(key) {
+                bail!(
+                    "{:?} is read by the prepared txn {}",
+                    Bytes::copy_from_slice(key),
+                    id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if a prepared txn wrote a key that a txn being prepared read, as the former may
+    /// commit before the latter. Called under the commit lock.
+    pub(crate) fn check_prepared_writes(
+        &self,
+        read_set: &KeySet,
+        read_ranges: &[KeyRange],
+    ) -> Result<()> {
+        for (id, txn) in self.prepared_txns.lock().iter() {
+            for key in txn.writes.keys() {
+                if read_set.contains(key) || read_ranges.iter().any(|range| range.contains(key)) {
+                    bail!("{:?} is written by the prepared txn {}", key, id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates a transaction, which fails and releases its snapshot once `timeout` passes. A
+    /// `read_only` transaction cannot write, never tracks its reads and always commits.
+    #[track_caller]
+    pub fn new_txn(
+        &self,
+        inner: Arc<LsmStorageInner>,
+        serializable: bool,
+        read_only: bool,
+        timeout: Option<Duration>,
+    ) -> Arc<Transaction> {
+        let serializable = serializable && !read_only;
+        let (read_ts, reader, deadline) = self.register_reader(timeout);
+        let memory_limit = inner.options.txn_key_set_memory_limit;
         Arc::new(Transaction {
             inner,
             read_ts,
+            reader,
+            deadline,
             local_storage: Arc::new(SkipMap::new()),
             committed: Arc::new(AtomicBool::new(false)),
-            key_hashes: if serializable {
-                Some(Mutex::new((HashSet::new(), HashSet::new())))
+            key_sets: if serializable {
+                Some(Mutex::new((
+                    KeySet::new(memory_limit),
+                    KeySet::new(memory_limit),
+                )))
+            } else {
+                None
+            },
+            read_ranges: if serializable {
+                Some(Mutex::new(Vec::new()))
             } else {
                 None
             },
+            savepoints: Mutex::new(Vec::new()),
+            local_storage_size: AtomicUsize::new(0),
+            spilled_runs: Mutex::new(Vec::new()),
+            write_error: Mutex::new(None),
+            read_only,
+        })
+    }
+
+    #[track_caller]
+    pub fn new_read_only_txn(
+        &self,
+        inner: Arc<LsmStorageInner>,
+        timeout: Option<Duration>,
+    ) -> ReadOnlyTransaction {
+        ReadOnlyTransaction {
+            txn: self.new_txn(inner, false, true, timeout),
+        }
+    }
+
+    /// Creates a pessimistic transaction, which fails and releases its snapshot once `timeout`
+    /// passes.
+    #[track_caller]
+    pub fn new_pessimistic_txn(
+        &self,
+        inner: Arc<LsmStorageInner>,
+        timeout: Option<Duration>,
+    ) -> Arc<PessimisticTransaction> {
+        let (read_ts, reader, deadline) = self.register_reader(timeout);
+        Arc::new(PessimisticTransaction {
+            id: self.lock_manager.next_txn_id(),
+            read_ts,
+            reader,
+            deadline,
+            inner,
+            local_storage: SkipMap::new(),
+            committed: AtomicBool::new(false),
+            locked_keys: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Creates a snapshot, which fails and is released once `timeout` passes.
+    #[track_caller]
+    pub fn new_snapshot(
+        &self,
+        inner: Arc<LsmStorageInner>,
+        timeout: Option<Duration>,
+    ) -> Arc<Snapshot> {
+        let (read_ts, reader, deadline) = self.register_reader(timeout);
+        Arc::new(Snapshot {
+            read_ts,
+            reader,
+            deadline,
+            inner,
         })
     }
+
+    /// Creates a snapshot at a ts in the past, which must not be below the watermark because the
+    /// older versions may have been garbage collected, nor above the latest commit ts because the
+    /// writes of a later commit may be partly applied.
+    #[track_caller]
+    pub fn new_snapshot_at(
+        &self,
+        inner: Arc<LsmStorageInner>,
+        read_ts: u64,
+    ) -> Result<Arc<Snapshot>> {
+        let _watermark_lock = self.watermark_lock.lock();
+        let watermark = self.watermark_inner();
+        if read_ts < watermark {
+            bail!("ts {} is below the watermark {}", read_ts, watermark);
+        }
+        let latest_commit_ts = self.latest_commit_ts();
+        if read_ts > latest_commit_ts {
+            bail!(
+                "ts {} is above the latest commit ts {}",
+                read_ts,
+                latest_commit_ts
+            );
+        }
+        let shard = self.next_watermark_shard();
+        let (id, _) = self.watermarks[shard]
+            .lock()
+            .add_reader(read_ts, None, Location::caller());
+        Ok(Arc::new(Snapshot {
+            read_ts,
+            reader: ReaderHandle { shard, id },
+            deadline: None,
+            inner,
+        }))
+    }
 }
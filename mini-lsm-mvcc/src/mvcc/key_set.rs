@@ -0,0 +1,124 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    ops::Bound,
+};
+
+use bytes::Bytes;
+
+/// The keys read or written by a transaction. The exact keys are kept until they take more than
+/// the memory limit, and then the set falls back to 32-bit hashes, which may report spurious
+/// conflicts.
+#[derive(Debug)]
+pub(crate) enum KeySet {
+    Exact {
+        keys: BTreeSet<Bytes>,
+        size: usize,
+        memory_limit: Option<usize>,
+    },
+    Hashed(HashSet<u32>),
+}
+
+impl KeySet {
+    pub fn new(memory_limit: Option<usize>) -> Self {
+        Self::Exact {
+            keys: BTreeSet::new(),
+            size: 0,
+            memory_limit,
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        match self {
+            Self::Exact {
+                keys,
+                size,
+                memory_limit,
+            } => {
+                if keys.contains(key) {
+                    return;
+                }
+                keys.insert(Bytes::copy_from_slice(key));
+                *size += key.len();
+                if memory_limit.is_some_and(|limit| *size > limit) {
+                    *self = Self::Hashed(keys.iter().map(|key| farmhash::hash32(key)).collect());
+                }
+            }
+            Self::Hashed(hashes) => {
+                hashes.insert(farmhash::hash32(key));
+            }
+        }
+    }
+
+    /// Removes a key. A hashed set keeps the hash, as it may be shared with another key, which
+    /// may cause a spurious conflict.
+    pub fn remove(&mut self, key: &[u8]) {
+        if let Self::Exact { keys, size, .. } = self {
+            if keys.remove(key) {
+                *size -= key.len();
+            }
+        }
+    }
+
+    /// Whether the set has the key, or a key with the same hash.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        match self {
+            Self::Exact { keys, .. } => keys.contains(key),
+            Self::Hashed(hashes) => hashes.contains(&farmhash::hash32(key)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Exact { keys, .. } => keys.is_empty(),
+            Self::Hashed(hashes) => hashes.is_empty(),
+        }
+    }
+
+    /// Finds a key in both sets. The inner `None` means that only the hash of the key is known.
+    pub fn find_common(&self, other: &KeySet) -> Option<Option<Bytes>> {
+        match (self, other) {
+            (Self::Exact { keys, .. }, Self::Exact { keys: other, .. }) => {
+                keys.intersection(other).next().map(|key| Some(key.clone()))
+            }
+            (Self::Exact { keys, .. }, Self::Hashed(hashes))
+            | (Self::Hashed(hashes), Self::Exact { keys, .. }) => keys
+                .iter()
+                .find(|key| hashes.contains(&farmhash::hash32(key)))
+                .map(|key| Some(key.clone())),
+            (Self::Hashed(hashes), Self::Hashed(other)) => {
+                hashes.intersection(other).next().map(|_| None)
+            }
+        }
+    }
+
+    /// Finds a key within the range. Hashed sets cannot answer this, so they always report a
+    /// match whose key is unknown.
+    pub fn find_in_range(
+        &self,
+        lower: &Bound<Bytes>,
+        upper: &Bound<Bytes>,
+    ) -> Option<Option<Bytes>> {
+        match self {
+            Self::Exact { keys, .. } => {
+                if is_empty_range(lower, upper) {
+                    return None;
+                }
+                keys.range((lower.clone(), upper.clone()))
+                    .next()
+                    .map(|key| Some(key.clone()))
+            }
+            Self::Hashed(hashes) if hashes.is_empty() => None,
+            Self::Hashed(_) => Some(None),
+        }
+    }
+}
+
+/// `BTreeSet::range` panics on these ranges.
+fn is_empty_range(lower: &Bound<Bytes>, upper: &Bound<Bytes>) -> bool {
+    match (lower, upper) {
+        (Bound::Included(lower), Bound::Included(upper)) => lower > upper,
+        (Bound::Included(lower) | Bound::Excluded(lower), Bound::Excluded(upper))
+        | (Bound::Excluded(lower), Bound::Included(upper)) => lower >= upper,
+        _ => false,
+    }
+}
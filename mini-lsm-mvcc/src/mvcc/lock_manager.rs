@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+
+const NUM_SHARDS: usize = 16;
+
+/// Returned when a pessimistic transaction cannot lock a key, or an optimistic one cannot write
+/// a locked key. The transaction can be retried once the lock is released.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockError {
+    /// The key is still locked by another transaction after the lock wait timeout.
+    Timeout(Bytes),
+    /// Waiting for the key would close a cycle in the wait-for graph.
+    Deadlock(Bytes),
+    /// The key is locked by another transaction, which a commit does not wait for.
+    Locked(Bytes),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(key) => write!(f, "lock wait timeout on {:?}", key),
+            Self::Deadlock(key) => write!(f, "deadlock detected when locking {:?}", key),
+            Self::Locked(key) => write!(f, "{:?} is locked by another transaction", key),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+#[derive(Default)]
+struct LockShard {
+    /// The owner transaction of each locked key.
+    owners: Mutex<HashMap<Bytes, u64>>,
+    released: Condvar,
+}
+
+/// Exclusive per-key locks of the pessimistic transactions. The keys are sharded so that
+/// transactions locking unrelated keys do not contend on the same mutex.
+pub(crate) struct LockManager {
+    shards: Vec<LockShard>,
+    /// The wait-for graph, from a waiting transaction to the transaction holding the lock.
+    wait_for: Mutex<HashMap<u64, u64>>,
+    next_txn_id: AtomicU64,
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..NUM_SHARDS).map(|_| LockShard::default()).collect(),
+            wait_for: Mutex::new(HashMap::new()),
+            next_txn_id: AtomicU64::new(1),
+        }
+    }
+
+    pub fn next_txn_id(&self) -> u64 {
+        self.next_txn_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn shard(&self, key: &[u8]) -> &LockShard {
+        &self.shards[farmhash::hash32(key) as usize % NUM_SHARDS]
+    }
+
+    /// Locks `key` for `txn_id`, waiting at most `timeout` for the current owner to release it.
+    /// Returns whether the key is newly locked, or already held by the transaction.
+    pub fn lock(&self, txn_id: u64, key: &[u8], timeout: Duration) -> Result<bool, LockError> {
+        let shard = self.shard(key);
+        let deadline = Instant::now() + timeout;
+        let mut owners = shard.owners.lock();
+        loop {
+            let owner = match owners.get(key) {
+                None => {
+                    owners.insert(Bytes::copy_from_slice(key), txn_id);
+                    self.wait_for.lock().remove(&txn_id);
+                    return Ok(true);
+                }
+                Some(owner) if *owner == txn_id => return Ok(false),
+                Some(owner) => *owner,
+            };
+            {
+                let mut wait_for = self.wait_for.lock();
+                // each transaction waits for at most one other, so following the edges from the
+                // owner finds any cycle this wait would close
+                let mut txn = owner;
+                for _ in 0..wait_for.len() {
+                    let Some(next) = wait_for.get(&txn) else {
+                        break;
+                    };
+                    if *next == txn_id {
+                        wait_for.remove(&txn_id);
+                        return Err(LockError::Deadlock(Bytes::copy_from_slice(key)));
+                    }
+                    txn = *next;
+                }
+                wait_for.insert(txn_id, owner);
+            }
+            if shard.released.wait_until(&mut owners, deadline).timed_out()
+                && owners.contains_key(key)
+            {
+                self.wait_for.lock().remove(&txn_id);
+                return Err(LockError::Timeout(Bytes::copy_from_slice(key)));
+            }
+        }
+    }
+
+    pub fn is_locked(&self, key: &[u8]) -> bool {
+        self.shard(key).owners.lock().contains_key(key)
+    }
+
+    /// Releases the locks of `txn_id` on `keys` and wakes up the waiters.
+    pub fn unlock_all(&self, txn_id: u64, keys: &[Bytes]) {
+        for key in keys {
+            let shard = self.shard(key);
+            let mut owners = shard.owners.lock();
+            if owners.get(key) == Some(&txn_id) {
+                owners.remove(key);
+                shard.released.notify_all();
+            }
+        }
+    }
+}
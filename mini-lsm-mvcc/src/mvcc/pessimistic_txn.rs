@@ -0,0 +1,160 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+use parking_lot::Mutex;
+
+use crate::{
+    lsm_storage::{LsmStorageInner, WriteBatchRecord},
+    mvcc::{key_set::KeySet, CommittedTxnData, ReaderHandle},
+};
+
+/// A transaction that locks the keys it writes or reads for update, so that it never aborts on
+/// commit because of a conflict. The locks are released on commit or drop.
+pub struct PessimisticTransaction {
+    pub(crate) id: u64,
+    pub(crate) read_ts: u64,
+    pub(crate) reader: ReaderHandle,
+    /// Operations fail and the snapshot is released after the deadline.
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) inner: Arc<LsmStorageInner>,
+    pub(crate) local_storage: SkipMap<Bytes, Bytes>,
+    pub(crate) committed: AtomicBool,
+    /// The keys locked by the transaction, in the order they were locked.
+    pub(crate) locked_keys: Mutex<Vec<Bytes>>,
+}
+
+impl PessimisticTransaction {
+    /// Reads a key from the snapshot of the transaction, without locking it.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.check_deadline()?;
+        if let Some(entry) = self.local_storage.get(key) {
+            return Ok(Some(entry.value().clone()).filter(|value| !value.is_empty()));
+        }
+        self.inner.get_with_ts(key, self.read_ts)
+    }
+
+    /// Locks the key and reads its latest committed version, which cannot change until the
+    /// transaction ends.
+    pub fn get_for_update(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.lock(key)?;
+        if let Some(entry) = self.local_storage.get(key) {
+            return Ok(Some(entry.value().clone()).filter(|value| !value.is_empty()));
+        }
+        let latest_ts = self.inner.mvcc().latest_commit_ts();
+        self.inner.get_with_ts(key, latest_ts)
+    }
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.lock(key)?;
+        self.local_storage
+            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.lock(key)?;
+        self.local_storage
+            .insert(Bytes::copy_from_slice(key), Bytes::new());
+        Ok(())
+    }
+
+    /// Fails once the transaction is past its deadline, as the versions at its read ts may be
+    /// garbage collected from then on.
+    fn check_deadline(&self) -> Result<()> {
+        if self
+            .inner
+            .mvcc()
+            .reader_expired(&self.reader, self.deadline)
+        {
+            bail!("transaction at ts {} has expired", self.read_ts);
+        }
+        Ok(())
+    }
+
+    fn lock(&self, key: &[u8]) -> Result<()> {
+        self.check_deadline()?;
+        let mvcc = self.inner.mvcc();
+        if mvcc
+            .lock_manager
+            .lock(self.id, key, self.inner.options.lock_wait_timeout)?
+        {
+            self.locked_keys.lock().push(Bytes::copy_from_slice(key));
+            // an optimistic transaction that checked the locks before this one was taken has
+            // finished writing once the commit lock is free
+            drop(mvcc.commit_lock.lock());
+        }
+        Ok(())
+    }
+
+    pub fn commit(&self) -> Result<()> {
+        self.committed
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .expect("cannot operate on committed txn!");
+        self.check_deadline()?;
+        let mvcc = self.inner.mvcc();
+        let _commit_lock = mvcc.commit_lock.lock();
+        for entry in self.local_storage.iter() {
+            mvcc.check_prepared_reads(entry.key())?;
+        }
+        let batch = self
+            .local_storage
+            .iter()
+            .map(|entry| {
+                if entry.value().is_empty() {
+                    WriteBatchRecord::Del(entry.key().clone())
+                } else {
+                    WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
+                }
+            })
+            .collect::<Vec<_>>();
+        // the keys are locked by this txn, which the checks of `write_batch_inner` refuse
+        let ts = self.inner.write_records_inner(batch.iter().map(Ok))?;
+        if self.inner.options.serializable {
+            // serializable optimistic transactions check their reads against this write set
+            let mut write_set = KeySet::new(self.inner.options.txn_key_set_memory_limit);
+            for entry in self.local_storage.iter() {
+                write_set.insert(entry.key());
+            }
+            mvcc.committed_txns.lock().insert(
+                ts,
+                CommittedTxnData {
+                    write_set,
+                    read_ts: self.read_ts,
+                    commit_ts: ts,
+                },
+            );
+        }
+        let locked_keys = std::mem::take(&mut *self.locked_keys.lock());
+        mvcc.lock_manager.unlock_all(self.id, &locked_keys);
+        Ok(())
+    }
+}
+
+impl Drop for PessimisticTransaction {
+    fn drop(&mut self) {
+        let mvcc = self.inner.mvcc();
+        mvcc.lock_manager
+            .unlock_all(self.id, &self.locked_keys.lock());
+        mvcc.release_reader(&self.reader)
+    }
+}
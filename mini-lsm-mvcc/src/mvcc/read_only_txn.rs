@@ -0,0 +1,50 @@
+use std::{ops::Bound, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::mvcc::txn::{Transaction, TxnIterator};
+
+/// A transaction that can only read, so it never records a read set and always commits. The
+/// snapshot is still pinned through the watermark until the transaction is dropped.
+pub struct ReadOnlyTransaction {
+    /// A transaction created with the `read_only` flag of `LsmMvccInner::new_txn`, and no write
+    /// is done through it.
+    pub(crate) txn: Arc<Transaction>,
+}
+
+impl ReadOnlyTransaction {
+    pub fn read_ts(&self) -> u64 {
+        self.txn.read_ts
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.txn.get(key)
+    }
+
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.txn.multi_get(keys)
+    }
+
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.txn.scan(lower, upper)
+    }
+
+    pub fn prefix_scan(&self, prefix: &[u8]) -> Result<TxnIterator> {
+        self.txn.prefix_scan(prefix)
+    }
+
+    pub fn scan_with_refresh(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        refresh_interval: Duration,
+    ) -> Result<TxnIterator> {
+        self.txn.scan_with_refresh(lower, upper, refresh_interval)
+    }
+
+    /// There is nothing to validate or write.
+    pub fn commit(&self) -> Result<()> {
+        Ok(())
+    }
+}
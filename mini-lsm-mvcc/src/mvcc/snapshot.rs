@@ -0,0 +1,101 @@
+use std::{ops::Bound, sync::Arc, time::Instant};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::{
+    iterators::{SeekableIterator, StorageIterator},
+    lsm_iterator::{FusedIterator, LsmIterator},
+    lsm_storage::LsmStorageInner,
+    mvcc::ReaderHandle,
+};
+
+/// A consistent view of the storage at `read_ts`. The snapshot is registered with the watermark,
+/// so the versions it reads are not garbage collected until it is dropped.
+pub struct Snapshot {
+    pub(crate) read_ts: u64,
+    pub(crate) reader: ReaderHandle,
+    /// Reads fail and the snapshot is released after the deadline.
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) inner: Arc<LsmStorageInner>,
+}
+
+impl Snapshot {
+    pub fn read_ts(&self) -> u64 {
+        self.read_ts
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.check_deadline()?;
+        self.inner.get_with_ts(key, self.read_ts)
+    }
+
+    pub fn scan(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<SnapshotIterator> {
+        self.check_deadline()?;
+        Ok(SnapshotIterator {
+            iter: self.inner.scan_with_ts(lower, upper, self.read_ts)?,
+            snapshot: self.clone(),
+        })
+    }
+
+    /// Fails once the snapshot is past its deadline, as the versions at its read ts may be
+    /// garbage collected from then on.
+    fn check_deadline(&self) -> Result<()> {
+        if self
+            .inner
+            .mvcc()
+            .reader_expired(&self.reader, self.deadline)
+        {
+            bail!("snapshot at ts {} has expired", self.read_ts);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.inner.mvcc().release_reader(&self.reader)
+    }
+}
+
+/// An iterator over a snapshot, which keeps the snapshot alive.
+pub struct SnapshotIterator {
+    iter: FusedIterator<LsmIterator>,
+    snapshot: Arc<Snapshot>,
+}
+
+impl StorageIterator for SnapshotIterator {
+    type KeyType<'a> = &'a [u8] where Self: 'a;
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.snapshot.check_deadline()?;
+        self.iter.next()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
+
+impl SeekableIterator for SnapshotIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.snapshot.check_deadline()?;
+        self.iter.seek(key)
+    }
+}
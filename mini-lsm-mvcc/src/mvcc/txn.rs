@@ -1,10 +1,12 @@
 use std::{
-    collections::HashSet,
+    collections::BTreeMap,
+    fmt,
     ops::Bound,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Result};
@@ -14,20 +16,88 @@ use ouroboros::self_referencing;
 use parking_lot::Mutex;
 
 use crate::{
-    iterators::{two_merge_iterator::TwoMergeIterator, StorageIterator},
-    lsm_iterator::{FusedIterator, LsmIterator},
-    lsm_storage::{LsmStorageInner, WriteBatchRecord},
+    iterators::{
+        merge_iterator::MergeIterator, two_merge_iterator::TwoMergeIterator, SeekableIterator,
+        StorageIterator,
+    },
+    key::{self, KeySlice},
+    lsm_iterator::{seek_target, FusedIterator, LsmIterator},
+    lsm_storage::{prefix_end, LsmStorageInner, WriteBatchRecord},
     mem_table::map_bound,
-    mvcc::CommittedTxnData,
+    mvcc::{key_set::KeySet, lock_manager::LockError, CommittedTxnData, PreparedTxn, ReaderHandle},
+    table::{SsTable, SsTableBuilder, SsTableIterator},
 };
 
+/// A range of user keys.
+pub(crate) type KeyRange = (Bound<Bytes>, Bound<Bytes>);
+
+/// The values written by the transaction, in memory or spilled, of the keys before they were
+/// written again, `None` if not written.
+pub(crate) type UndoLog = Vec<(Bytes, Option<Bytes>)>;
+
+/// The state of the writes that `rollback_to_savepoint` goes back to.
+pub(crate) struct Savepoint {
+    pub(crate) undo_log: UndoLog,
+    /// The number of spilled runs when the savepoint was set, the later ones are dropped on
+    /// rollback.
+    pub(crate) num_spilled_runs: usize,
+}
+
+/// Returned by `Transaction::commit` when a transaction committed after the read ts wrote a key
+/// that the transaction read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxnConflictError {
+    /// The conflicting key, or `None` if only its hash is known.
+    pub key: Option<Bytes>,
+    /// The commit ts of the transaction that wrote the key.
+    pub commit_ts: u64,
+}
+
+impl fmt::Display for TxnConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(
+                f,
+                "serializable check failed: {:?} was written by the txn committed at ts {}",
+                key, self.commit_ts
+            ),
+            None => write!(
+                f,
+                "serializable check failed: conflict with the txn committed at ts {}",
+                self.commit_ts
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TxnConflictError {}
+
 pub struct Transaction {
     pub(crate) read_ts: u64,
+    pub(crate) reader: ReaderHandle,
+    /// Operations fail and the snapshot is released after the deadline.
+    pub(crate) deadline: Option<Instant>,
     pub(crate) inner: Arc<LsmStorageInner>,
     pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
     pub(crate) committed: Arc<AtomicBool>,
     /// Write set and read set
-    pub(crate) key_hashes: Option<Mutex<(HashSet<u32>, HashSet<u32>)>>,
+    pub(crate) key_sets: Option<Mutex<(KeySet, KeySet)>>,
+    /// The ranges scanned by the transaction, so that the keys written into them by other
+    /// transactions (phantoms) are detected on commit
+    pub(crate) read_ranges: Option<Mutex<Vec<KeyRange>>>,
+    /// The undo log of the writes after each savepoint
+    pub(crate) savepoints: Mutex<Vec<Savepoint>>,
+    /// The approximate size of `local_storage` in bytes
+    pub(crate) local_storage_size: AtomicUsize,
+    /// The writes spilled from `local_storage` to temporary SSTs, from latest to earliest. The
+    /// files are deleted when the transaction is dropped.
+    pub(crate) spilled_runs: Mutex<Vec<Arc<SsTable>>>,
+    /// The first error of a spill, or of reading the spilled writes for a savepoint. The writes
+    /// cannot return it, so it fails the commit instead.
+    pub(crate) write_error: Mutex<Option<anyhow::Error>>,
+    /// Set for the transactions behind `ReadOnlyTransaction`, which cannot write and commit
+    /// without any check
+    pub(crate) read_only: bool,
 }
 
 impl Transaction {
@@ -35,117 +105,490 @@ impl Transaction {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        if let Some(guard) = &self.key_hashes {
+        self.check_deadline()?;
+        if let Some(guard) = &self.key_sets {
             let mut guard = guard.lock();
             let (_, read_set) = &mut *guard;
-            read_set.insert(farmhash::hash32(key));
+            read_set.insert(key);
         }
-        if let Some(entry) = self.local_storage.get(key) {
-            if entry.value().is_empty() {
+        if let Some(value) = self.get_local(key)? {
+            if value.is_empty() {
                 return Ok(None);
             } else {
-                return Ok(Some(entry.value().clone()));
+                return Ok(Some(value));
             }
         }
         self.inner.get_with_ts(key, self.read_ts)
     }
 
-    pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+    /// Gets the keys from the same snapshot, in the order of `keys`.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
+        self.check_deadline()?;
+        if let Some(guard) = &self.key_sets {
+            let mut guard = guard.lock();
+            let (_, read_set) = &mut *guard;
+            for key in keys {
+                read_set.insert(key);
+            }
+        }
+        let mut values = Vec::with_capacity(keys.len());
+        let mut remote_keys = Vec::new();
+        for key in keys {
+            let value = self.get_local(key)?;
+            if value.is_none() {
+                remote_keys.push(*key);
+            }
+            values.push(value);
+        }
+        let mut remote_values = self
+            .inner
+            .multi_get_with_ts(&remote_keys, self.read_ts)?
+            .into_iter();
+        Ok(values
+            .into_iter()
+            .map(|value| match value {
+                Some(value) => Some(value).filter(|value| !value.is_empty()),
+                None => remote_values.next().unwrap(),
+            })
+            .collect())
+    }
+
+    /// Fails once the transaction is past its deadline, as the versions at its read ts may be
+    /// garbage collected from then on.
+    fn check_deadline(&self) -> Result<()> {
+        if self
+            .inner
+            .mvcc()
+            .reader_expired(&self.reader, self.deadline)
+        {
+            bail!("transaction at ts {} has expired", self.read_ts);
+        }
+        Ok(())
+    }
+
+    /// Gets the value written by the transaction, which is empty for a deletion.
+    fn get_local(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        if let Some(entry) = self.local_storage.get(key) {
+            return Ok(Some(entry.value().clone()));
+        }
+        for run in self.spilled_runs.lock().iter() {
+            if key < run.first_key().key_ref() || key > run.last_key().key_ref() {
+                continue;
+            }
+            if !run.may_contain_hash(farmhash::fingerprint32(key)) {
+                continue;
+            }
+            let iter = SsTableIterator::create_and_seek_to_key(
+                run.clone(),
+                KeySlice::from_slice(key, key::TS_DEFAULT),
+            )?;
+            if iter.is_valid() && iter.key().key_ref() == key {
+                return Ok(Some(Bytes::copy_from_slice(iter.value())));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Iterates over the writes of the transaction, both in memory and spilled.
+    fn local_iter(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnLocalMergeIterator> {
         let mut local_iter = TxnLocalIteratorBuilder {
             map: self.local_storage.clone(),
             iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
             item: (Bytes::new(), Bytes::new()),
+            upper: map_bound(upper),
         }
         .build();
         let entry = local_iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
         local_iter.with_mut(|x| *x.item = entry);
 
-        TxnIterator::create(
-            self.clone(),
-            TwoMergeIterator::create(
-                local_iter,
-                self.inner.scan_with_ts(lower, upper, self.read_ts)?,
-            )?,
-        )
+        let spilled_runs = self.spilled_runs.lock();
+        let mut run_iters = Vec::with_capacity(spilled_runs.len());
+        for run in spilled_runs.iter() {
+            let iter = match lower {
+                Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
+                    run.clone(),
+                    KeySlice::from_slice(key, key::TS_DEFAULT),
+                )?,
+                Bound::Excluded(key) => {
+                    let mut iter = SsTableIterator::create_and_seek_to_key(
+                        run.clone(),
+                        KeySlice::from_slice(key, key::TS_DEFAULT),
+                    )?;
+                    if iter.is_valid() && iter.key().key_ref() == key {
+                        iter.next()?;
+                    }
+                    iter
+                }
+                Bound::Unbounded => SsTableIterator::create_and_seek_to_first(run.clone())?,
+            };
+            run_iters.push(Box::new(iter));
+        }
+        let spilled_iter =
+            SpilledRunIterator::new(MergeIterator::create(run_iters), map_bound(upper));
+        TwoMergeIterator::create(local_iter, spilled_iter)
+    }
+
+    /// Moves `local_storage` into a temporary SST once it grows beyond the spill threshold.
+    /// After a failed spill, the writes are kept in memory and the commit fails.
+    fn maybe_spill(&self, written: usize) {
+        let size = self.local_storage_size.fetch_add(written, Ordering::SeqCst) + written;
+        let Some(threshold) = self.inner.options.txn_spill_threshold else {
+            return;
+        };
+        if size <= threshold || self.write_error.lock().is_some() {
+            return;
+        }
+        if let Err(e) = self.spill() {
+            self.set_write_error(e.context("failed to spill txn writes"));
+        }
+    }
+
+    fn set_write_error(&self, e: anyhow::Error) {
+        self.write_error.lock().get_or_insert(e);
+    }
+
+    fn check_write_error(&self) -> Result<()> {
+        match self.write_error.lock().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn spill(&self) -> Result<()> {
+        let mut spilled_runs = self.spilled_runs.lock();
+        let mut builder = SsTableBuilder::new(self.inner.options.block_size);
+        for entry in self.local_storage.iter() {
+            builder.add(
+                KeySlice::from_slice(entry.key(), key::TS_DEFAULT),
+                entry.value(),
+            );
+        }
+        let id = self.inner.next_sst_id();
+        let run = builder.build(id, None, self.inner.path_of_sst(id))?;
+        // never added to the LSM tree, so the file is deleted once the run is dropped, and it is
+        // cleaned up as an orphan file after a crash
+        run.mark_obsolete();
+        spilled_runs.insert(0, Arc::new(run));
+        self.local_storage.clear();
+        self.local_storage_size.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.scan_inner(lower, upper, None)
+    }
+
+    /// Scans the keys starting with `prefix`.
+    pub fn prefix_scan(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        let end = prefix_end(prefix);
+        let upper = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        self.scan(Bound::Included(prefix), upper)
+    }
+
+    /// Like `scan`, but the iterator is rebuilt after each `refresh_interval`, still at the read
+    /// ts of the transaction. The memtables and SSTs it has moved past can then be freed, which
+    /// keeps a long-running export from holding on to flushed and compacted data.
+    pub fn scan_with_refresh(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        refresh_interval: Duration,
+    ) -> Result<TxnIterator> {
+        self.scan_inner(lower, upper, Some(refresh_interval))
+    }
+
+    fn scan_inner(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        refresh_interval: Option<Duration>,
+    ) -> Result<TxnIterator> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.check_deadline()?;
+        if let Some(read_ranges) = &self.read_ranges {
+            read_ranges
+                .lock()
+                .push((map_bound(lower), map_bound(upper)));
+        }
+        TxnIterator::create(self.clone(), lower, upper, refresh_interval)
     }
 
     pub fn put(&self, key: &[u8], value: &[u8]) {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
+        if self.read_only {
+            panic!("cannot write to a read-only txn!");
+        }
+        self.record_undo(key);
         self.local_storage
             .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
-        if let Some(key_hashes) = &self.key_hashes {
-            let mut key_hashes = key_hashes.lock();
-            let (write_hashes, _) = &mut *key_hashes;
-            write_hashes.insert(farmhash::hash32(key));
+        if let Some(key_sets) = &self.key_sets {
+            let mut key_sets = key_sets.lock();
+            let (write_set, _) = &mut *key_sets;
+            write_set.insert(key);
         }
+        self.maybe_spill(key.len() + value.len());
     }
 
     pub fn delete(&self, key: &[u8]) {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
+        if self.read_only {
+            panic!("cannot write to a read-only txn!");
+        }
+        self.record_undo(key);
         self.local_storage
             .insert(Bytes::copy_from_slice(key), Bytes::new());
-        if let Some(key_hashes) = &self.key_hashes {
-            let mut key_hashes = key_hashes.lock();
-            let (write_hashes, _) = &mut *key_hashes;
-            write_hashes.insert(farmhash::hash32(key));
+        if let Some(key_sets) = &self.key_sets {
+            let mut key_sets = key_sets.lock();
+            let (write_set, _) = &mut *key_sets;
+            write_set.insert(key);
         }
+        self.maybe_spill(key.len());
     }
 
-    pub fn commit(&self) -> Result<()> {
+    fn record_undo(&self, key: &[u8]) {
+        let mut savepoints = self.savepoints.lock();
+        let Some(savepoint) = savepoints.last_mut() else {
+            return;
+        };
+        match self.get_local(key) {
+            Ok(old_value) => savepoint
+                .undo_log
+                .push((Bytes::copy_from_slice(key), old_value)),
+            Err(e) => self.set_write_error(e),
+        }
+    }
+
+    /// Marks the current state of the writes, which `rollback_to_savepoint` goes back to.
+    /// Savepoints can be nested.
+    pub fn set_savepoint(&self) {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        let num_spilled_runs = self.spilled_runs.lock().len();
+        self.savepoints.lock().push(Savepoint {
+            undo_log: Vec::new(),
+            num_spilled_runs,
+        });
+    }
+
+    /// Undoes the writes made after the latest savepoint and removes the savepoint.
+    pub fn rollback_to_savepoint(&self) -> Result<()> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        let Some(savepoint) = self.savepoints.lock().pop() else {
+            bail!("no savepoint to roll back to");
+        };
+        {
+            let mut spilled_runs = self.spilled_runs.lock();
+            let num_later_runs = spilled_runs.len() - savepoint.num_spilled_runs;
+            if num_later_runs > 0 {
+                // the first run spilled after the savepoint has the writes that were in memory
+                // at the savepoint, which are loaded back, and the later writes are undone below
+                let mut writes = Vec::new();
+                let mut iter = SsTableIterator::create_and_seek_to_first(
+                    spilled_runs[num_later_runs - 1].clone(),
+                )?;
+                while iter.is_valid() {
+                    writes.push((
+                        Bytes::copy_from_slice(iter.key().key_ref()),
+                        Bytes::copy_from_slice(iter.value()),
+                    ));
+                    iter.next()?;
+                }
+                spilled_runs.drain(..num_later_runs);
+                self.local_storage.clear();
+                let mut size = 0;
+                for (key, value) in writes {
+                    size += key.len() + value.len();
+                    self.local_storage.insert(key, value);
+                }
+                self.local_storage_size.store(size, Ordering::SeqCst);
+            }
+        }
+        let mut key_sets = self.key_sets.as_ref().map(|key_sets| key_sets.lock());
+        for (key, old_value) in savepoint.undo_log.into_iter().rev() {
+            match old_value {
+                Some(old_value) => {
+                    self.local_storage_size
+                        .fetch_add(key.len() + old_value.len(), Ordering::SeqCst);
+                    self.local_storage.insert(key, old_value);
+                }
+                None => {
+                    // the key was not written before the savepoint, in memory or spilled
+                    self.local_storage.remove(&key);
+                    if let Some(key_sets) = &mut key_sets {
+                        let (write_set, _) = &mut **key_sets;
+                        write_set.remove(&key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Discards the writes and releases the snapshot of the transaction without committing.
+    pub fn rollback(&self) {
         self.committed
             .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
             .expect("cannot operate on committed txn!");
-        let _commit_lock = self.inner.mvcc().commit_lock.lock();
-        let serializability_check;
-        if let Some(guard) = &self.key_hashes {
-            let guard = guard.lock();
-            let (write_set, read_set) = &*guard;
-            println!(
-                "commit txn: write_set: {:?}, read_set: {:?}",
-                write_set, read_set
-            );
-            if !write_set.is_empty() {
-                let committed_txns = self.inner.mvcc().committed_txns.lock();
-                for (_, txn_data) in committed_txns.range((self.read_ts + 1)..) {
-                    for key_hash in read_set {
-                        if txn_data.key_hashes.contains(key_hash) {
-                            bail!("serializable check failed");
-                        }
+        self.local_storage.clear();
+        self.spilled_runs.lock().clear();
+        self.savepoints.lock().clear();
+        self.inner.mvcc().release_reader(&self.reader)
+    }
+
+    /// Checks the reads of a serializable transaction against the transactions committed after
+    /// its read ts. Returns whether the transaction is serializable.
+    fn check_conflicts(&self) -> Result<bool> {
+        let Some(guard) = &self.key_sets else {
+            return Ok(false);
+        };
+        let guard = guard.lock();
+        let (write_set, read_set) = &*guard;
+        println!(
+            "commit txn: write_set: {:?}, read_set: {:?}",
+            write_set, read_set
+        );
+        if !write_set.is_empty() {
+            let committed_txns = self.inner.mvcc().committed_txns.lock();
+            let read_ranges = self.read_ranges.as_ref().unwrap().lock();
+            for (commit_ts, txn_data) in committed_txns.range((self.read_ts + 1)..) {
+                let conflict = txn_data.write_set.find_common(read_set).or_else(|| {
+                    read_ranges
+                        .iter()
+                        .find_map(|(lower, upper)| txn_data.write_set.find_in_range(lower, upper))
+                });
+                if let Some(key) = conflict {
+                    return Err(TxnConflictError {
+                        key,
+                        commit_ts: *commit_ts,
                     }
+                    .into());
                 }
             }
-            serializability_check = true;
-        } else {
-            serializability_check = false;
-        }
-        let batch = self
-            .local_storage
-            .iter()
-            .map(|entry| {
-                if entry.value().is_empty() {
-                    WriteBatchRecord::Del(entry.key().clone())
-                } else {
-                    WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
+        }
+        Ok(true)
+    }
+
+    /// The first phase of a two-phase commit. The transaction is validated like in `commit`, and
+    /// its writes are logged to the WAL and locked, so that `commit_prepared` cannot fail on a
+    /// conflict. Returns the id to commit or roll back the transaction with, which is kept
+    /// across restarts. The reads are validated as of the prepare, and until the transaction is
+    /// resolved, the other transactions cannot commit writes to them. The reads are not kept
+    /// across restarts. Fails without the WAL, which makes the prepare durable.
+    pub fn prepare(&self) -> Result<u64> {
+        if !self.inner.options.enable_wal {
+            bail!("cannot prepare a txn without the WAL");
+        }
+        self.committed
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .expect("cannot operate on committed txn!");
+        self.check_deadline()?;
+        self.check_write_error()?;
+        let mvcc = self.inner.mvcc();
+        let _commit_lock = mvcc.commit_lock.lock();
+        let mut writes = BTreeMap::new();
+        let mut local_iter = self.local_iter(Bound::Unbounded, Bound::Unbounded)?;
+        while local_iter.is_valid() {
+            writes.insert(
+                Bytes::copy_from_slice(local_iter.key()),
+                Bytes::copy_from_slice(local_iter.value()),
+            );
+            local_iter.next()?;
+        }
+        let keys = writes.keys().cloned().collect::<Vec<_>>();
+        let lock_id = mvcc.lock_manager.next_txn_id();
+        mvcc.lock_prepared_keys(lock_id, &keys)?;
+        let prepared = self.check_conflicts().and_then(|_| {
+            for key in &keys {
+                mvcc.check_prepared_reads(key)?;
+            }
+            let (read_set, read_ranges) = match (&self.key_sets, &self.read_ranges) {
+                (Some(key_sets), Some(read_ranges)) => {
+                    let read_set = std::mem::replace(&mut key_sets.lock().1, KeySet::new(None));
+                    let read_ranges = std::mem::take(&mut *read_ranges.lock());
+                    mvcc.check_prepared_writes(&read_set, &read_ranges)?;
+                    (Some(read_set), read_ranges)
                 }
+                _ => (None, Vec::new()),
+            };
+            self.inner.prepare_txn(PreparedTxn {
+                writes,
+                read_ts: self.read_ts,
+                lock_id,
+                committing: false,
+                commit_ts: None,
+                read_set,
+                read_ranges,
             })
-            .collect::<Vec<_>>();
-        let ts = self.inner.write_batch_inner(&batch)?;
+        });
+        if prepared.is_err() {
+            mvcc.lock_manager.unlock_all(lock_id, &keys);
+        }
+        prepared
+    }
+
+    pub fn commit(&self) -> Result<()> {
+        self.committed
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .expect("cannot operate on committed txn!");
+        self.check_deadline()?;
+        if self.read_only {
+            return Ok(());
+        }
+        self.check_write_error()?;
+        let _commit_lock = self.inner.mvcc().commit_lock.lock();
+        // the keys locked by pessimistic transactions may only be written by their owners, and
+        // the keys read by prepared transactions are not written before they are resolved
+        let mvcc = self.inner.mvcc();
+        let mut local_iter = self.local_iter(Bound::Unbounded, Bound::Unbounded)?;
+        while local_iter.is_valid() {
+            if mvcc.lock_manager.is_locked(local_iter.key()) {
+                return Err(LockError::Locked(Bytes::copy_from_slice(local_iter.key())).into());
+            }
+            mvcc.check_prepared_reads(local_iter.key())?;
+            local_iter.next()?;
+        }
+        let serializability_check = self.check_conflicts()?;
+        // stream the writes into the batch path, so that the spilled ones are not loaded into
+        // memory at once
+        let mut local_iter = self.local_iter(Bound::Unbounded, Bound::Unbounded)?;
+        let records = std::iter::from_fn(|| {
+            if !local_iter.is_valid() {
+                return None;
+            }
+            let key = Bytes::copy_from_slice(local_iter.key());
+            let record = if local_iter.value().is_empty() {
+                WriteBatchRecord::Del(key)
+            } else {
+                WriteBatchRecord::Put(key, Bytes::copy_from_slice(local_iter.value()))
+            };
+            Some(local_iter.next().map(|_| record))
+        });
+        let ts = self.inner.write_records_inner(records)?;
         if serializability_check {
             let mut committed_txns = self.inner.mvcc().committed_txns.lock();
-            let mut key_hashes = self.key_hashes.as_ref().unwrap().lock();
-            let (write_set, _) = &mut *key_hashes;
+            let mut key_sets = self.key_sets.as_ref().unwrap().lock();
+            let (write_set, _) = &mut *key_sets;
 
             let old_data = committed_txns.insert(
                 ts,
                 CommittedTxnData {
-                    key_hashes: std::mem::take(write_set),
+                    write_set: std::mem::replace(write_set, KeySet::new(None)),
                     read_ts: self.read_ts,
                     commit_ts: ts,
                 },
@@ -168,10 +611,13 @@ impl Transaction {
 
 impl Drop for Transaction {
     fn drop(&mut self) {
-        self.inner.mvcc().ts.lock().1.remove_reader(self.read_ts)
+        self.inner.mvcc().release_reader(&self.reader)
     }
 }
 
+/// Merges the writes in memory with the spilled ones, the former are newer.
+pub(crate) type TxnLocalMergeIterator = TwoMergeIterator<TxnLocalIterator, SpilledRunIterator>;
+
 type SkipMapRangeIter<'a> =
     crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
 
@@ -185,6 +631,8 @@ pub struct TxnLocalIterator {
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (Bytes, Bytes),
+    /// The upper bound of the range, which a seek keeps.
+    upper: Bound<Bytes>,
 }
 
 impl TxnLocalIterator {
@@ -217,17 +665,105 @@ impl StorageIterator for TxnLocalIterator {
     }
 }
 
+impl SeekableIterator for TxnLocalIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let lower = Bound::Included(Bytes::copy_from_slice(key));
+        self.with_mut(|x| *x.iter = x.map.range((lower, x.upper.clone())));
+        self.next()
+    }
+}
+
+/// Iterates over the spilled runs of a transaction, from the lower bound it was seeked to up to
+/// `end_bound`.
+pub struct SpilledRunIterator {
+    iter: MergeIterator<SsTableIterator>,
+    end_bound: Bound<Bytes>,
+    is_valid: bool,
+}
+
+impl SpilledRunIterator {
+    fn new(iter: MergeIterator<SsTableIterator>, end_bound: Bound<Bytes>) -> Self {
+        let mut iter = Self {
+            iter,
+            end_bound,
+            is_valid: false,
+        };
+        iter.check_end_bound();
+        iter
+    }
+
+    fn check_end_bound(&mut self) {
+        self.is_valid = self.iter.is_valid()
+            && match self.end_bound.as_ref() {
+                Bound::Unbounded => true,
+                Bound::Included(key) => self.iter.key().key_ref() <= key.as_ref(),
+                Bound::Excluded(key) => self.iter.key().key_ref() < key.as_ref(),
+            };
+    }
+}
+
+impl StorageIterator for SpilledRunIterator {
+    type KeyType<'a> = &'a [u8];
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key().key_ref()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.is_valid
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()?;
+        self.check_end_bound();
+        Ok(())
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
+
+impl SeekableIterator for SpilledRunIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(KeySlice::from_slice(key, key::TS_DEFAULT))?;
+        self.check_end_bound();
+        Ok(())
+    }
+}
+
+type TxnIteratorInner = TwoMergeIterator<TxnLocalMergeIterator, FusedIterator<LsmIterator>>;
+
 pub struct TxnIterator {
     txn: Arc<Transaction>,
-    iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+    start_bound: Bound<Bytes>,
+    end_bound: Bound<Bytes>,
+    iter: TxnIteratorInner,
+    /// How often the iterator is rebuilt, so that it does not pin the memtables and SSTs it
+    /// started with.
+    refresh_interval: Option<Duration>,
+    refreshed_at: Instant,
 }
 
 impl TxnIterator {
     pub fn create(
         txn: Arc<Transaction>,
-        iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        refresh_interval: Option<Duration>,
     ) -> Result<Self> {
-        let mut iter = Self { txn, iter };
+        let mut iter = Self {
+            iter: Self::create_inner(&txn, lower, upper)?,
+            txn,
+            start_bound: map_bound(lower),
+            end_bound: map_bound(upper),
+            refresh_interval,
+            refreshed_at: Instant::now(),
+        };
         iter.skip_deletes()?;
         if iter.is_valid() {
             iter.add_to_read_set(iter.key());
@@ -235,6 +771,31 @@ impl TxnIterator {
         Ok(iter)
     }
 
+    fn create_inner(
+        txn: &Arc<Transaction>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIteratorInner> {
+        TwoMergeIterator::create(
+            txn.local_iter(lower, upper)?,
+            txn.inner.scan_with_ts(lower, upper, txn.read_ts)?,
+        )
+    }
+
+    /// Replaces the underlying iterators with new ones over the latest state at the same read
+    /// ts, starting from `lower`.
+    fn refresh(&mut self, lower: Bound<&[u8]>) -> Result<()> {
+        let upper = self.end_bound.as_ref().map(|key| key.as_ref());
+        self.iter = Self::create_inner(&self.txn, lower, upper)?;
+        self.refreshed_at = Instant::now();
+        Ok(())
+    }
+
+    fn refresh_due(&self) -> bool {
+        self.refresh_interval
+            .is_some_and(|interval| self.refreshed_at.elapsed() >= interval)
+    }
+
     fn skip_deletes(&mut self) -> Result<()> {
         while self.iter.is_valid() && self.iter.value().is_empty() {
             self.iter.next()?;
@@ -243,10 +804,10 @@ impl TxnIterator {
     }
 
     fn add_to_read_set(&self, key: &[u8]) {
-        if let Some(guard) = &self.txn.key_hashes {
+        if let Some(guard) = &self.txn.key_sets {
             let mut guard = guard.lock();
             let (_, read_set) = &mut *guard;
-            read_set.insert(farmhash::hash32(key));
+            read_set.insert(key);
         }
     }
 }
@@ -267,7 +828,13 @@ impl StorageIterator for TxnIterator {
     }
 
     fn next(&mut self) -> Result<()> {
-        self.iter.next()?;
+        self.txn.check_deadline()?;
+        if self.iter.is_valid() && self.refresh_due() {
+            let key = Bytes::copy_from_slice(self.iter.key());
+            self.refresh(Bound::Excluded(&key))?;
+        } else {
+            self.iter.next()?;
+        }
         self.skip_deletes()?;
         if self.is_valid() {
             self.add_to_read_set(self.key());
@@ -279,3 +846,31 @@ impl StorageIterator for TxnIterator {
         self.iter.num_active_iterators()
     }
 }
+
+impl SeekableIterator for TxnIterator {
+    /// Moves to the first key >= `key` in the transaction's view. The key is clamped to the start
+    /// of the scanned range, which the read set already covers.
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.txn.check_deadline()?;
+        let (key, skip_key) = seek_target(&self.start_bound, key);
+        if self.refresh_interval.is_some() {
+            // a refreshed iterator starts after the keys it has returned, so it cannot seek back
+            let key = Bytes::copy_from_slice(key);
+            self.refresh(if skip_key {
+                Bound::Excluded(&key)
+            } else {
+                Bound::Included(&key)
+            })?;
+        } else {
+            self.iter.seek(key)?;
+            if skip_key && self.iter.is_valid() && self.iter.key() == key {
+                self.iter.next()?;
+            }
+        }
+        self.skip_deletes()?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+}
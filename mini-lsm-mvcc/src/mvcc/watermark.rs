@@ -1,7 +1,13 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 
+/// The read ts of the active readers. Readers are almost always added at the latest commit ts,
+/// so they are kept in a ring buffer sorted by ts, where adding one is amortized O(1). The
+/// entries of the removed readers are dropped lazily once they reach either end of the buffer.
 pub struct Watermark {
-    readers: BTreeMap<u64, usize>,
+    /// The read ts and the number of readers at that ts, sorted by ts.
+    readers: VecDeque<(u64, usize)>,
+    /// The number of entries with readers.
+    num_retained_snapshots: usize,
 }
 
 impl Default for Watermark {
@@ -13,24 +19,81 @@ impl Default for Watermark {
 impl Watermark {
     pub fn new() -> Self {
         Self {
-            readers: BTreeMap::new(),
+            readers: VecDeque::new(),
+            num_retained_snapshots: 0,
         }
     }
 
     pub fn add_reader(&mut self, ts: u64) {
-        *self.readers.entry(ts).or_default() += 1;
+        let idx = match self.readers.back() {
+            Some((last_ts, _)) if *last_ts < ts => self.readers.len(),
+            None => 0,
+            // readers at a ts in the past are rare
+            Some(_) => self
+                .readers
+                .partition_point(|(reader_ts, _)| *reader_ts < ts),
+        };
+        match self.readers.get_mut(idx) {
+            Some((reader_ts, cnt)) if *reader_ts == ts => {
+                if *cnt == 0 {
+                    self.num_retained_snapshots += 1;
+                }
+                *cnt += 1;
+            }
+            _ => {
+                self.readers.insert(idx, (ts, 1));
+                self.num_retained_snapshots += 1;
+            }
+        }
     }
 
     pub fn remove_reader(&mut self, ts: u64) {
-        let cnt = self.readers.get_mut(&ts).unwrap();
+        let idx = self
+            .readers
+            .partition_point(|(reader_ts, _)| *reader_ts < ts);
+        let (reader_ts, cnt) = &mut self.readers[idx];
+        assert_eq!(*reader_ts, ts, "no reader at ts {}", ts);
         *cnt -= 1;
         if *cnt == 0 {
-            self.readers.remove(&ts);
+            self.num_retained_snapshots -= 1;
+            while matches!(self.readers.front(), Some((_, 0))) {
+                self.readers.pop_front();
+            }
+            while matches!(self.readers.back(), Some((_, 0))) {
+                self.readers.pop_back();
+            }
         }
     }
 
     pub fn num_retained_snapshots(&self) -> usize {
-        self.readers.len()
+        self.num_retained_snapshots
+    }
+
+    pub fn watermark(&self) -> Option<u64> {
+        // the front entry always has readers
+        self.readers.front().map(|(ts, _)| *ts)
+    }
+}
+
+/// The watermark before it moved to the ring buffers, with the readers in a map by ts. Only for
+/// the watermark benchmark, as its baseline.
+#[doc(hidden)]
+#[derive(Default)]
+pub struct BTreeWatermark {
+    readers: BTreeMap<u64, usize>,
+}
+
+impl BTreeWatermark {
+    pub fn add_reader(&mut self, ts: u64) {
+        *self.readers.entry(ts).or_default() += 1;
+    }
+
+    pub fn remove_reader(&mut self, ts: u64) {
+        let cnt = self.readers.get_mut(&ts).unwrap();
+        *cnt -= 1;
+        if *cnt == 0 {
+            self.readers.remove(&ts);
+        }
     }
 
     pub fn watermark(&self) -> Option<u64> {
@@ -1,9 +1,13 @@
+mod blocked_bloom;
 pub(crate) mod bloom;
 mod builder;
+pub mod filter;
 mod iterator;
+mod ribbon;
 
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Result};
@@ -13,9 +17,10 @@ pub use iterator::SsTableIterator;
 
 use crate::block::Block;
 use crate::key::{KeyBytes, KeySlice};
-use crate::lsm_storage::BlockCache;
+use crate::lsm_storage::{BlockCache, PrefixExtractor};
 
 use self::bloom::Bloom;
+use self::filter::{decode_filter, Filter, FilterType};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
@@ -27,9 +32,89 @@ pub struct BlockMeta {
     pub last_key: KeyBytes,
 }
 
+/// Table-level properties stored after the block meta.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    /// The smallest timestamp of all keys in the table.
+    pub min_ts: u64,
+    /// The largest timestamp of all keys in the table.
+    pub max_ts: u64,
+    /// When the table was built, in seconds since the UNIX epoch.
+    pub create_time: u64,
+    /// The extractor of the prefixes added to the bloom filter.
+    pub prefix_extractor: Option<PrefixExtractor>,
+    /// The type of the filter after the block meta.
+    pub filter_type: FilterType,
+}
+
+impl TableProperties {
+    /// The version of the layout, written before the properties.
+    const VERSION: u8 = 1;
+    const ENCODED_SIZE: usize = std::mem::size_of::<u64>() * 4 + std::mem::size_of::<u8>() * 3;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u8(Self::VERSION);
+        buf.put_u64(self.min_ts);
+        buf.put_u64(self.max_ts);
+        buf.put_u64(self.create_time);
+        match self.prefix_extractor {
+            None => {
+                buf.put_u8(0);
+                buf.put_u64(0);
+            }
+            Some(PrefixExtractor::FixedLength(n)) => {
+                buf.put_u8(1);
+                buf.put_u64(n as u64);
+            }
+            Some(PrefixExtractor::Delimiter(delimiter)) => {
+                buf.put_u8(2);
+                buf.put_u64(delimiter as u64);
+            }
+        }
+        buf.put_u8(self.filter_type.to_u8());
+    }
+
+    /// Decodes the `len` bytes of properties. The layout written before the version has only
+    /// the 8 bytes of `max_ts`, and the other fields are 0, so periodic compaction rewrites those
+    /// SSTs first. The prefix extractor is a type byte and a u64 argument, which wastes 7 bytes
+    /// on `PrefixExtractor::Delimiter`; this is fine only because a change of the layout bumps
+    /// the version.
+    fn decode(buf: &mut &[u8], len: usize) -> Result<Self> {
+        let mut properties = Self::default();
+        match len {
+            8 => {
+                properties.max_ts = buf.get_u64();
+                return Ok(properties);
+            }
+            Self::ENCODED_SIZE => {}
+            _ => bail!("invalid size {} of table properties", len),
+        }
+        let version = buf.get_u8();
+        if version != Self::VERSION {
+            bail!("unknown version {} of table properties", version);
+        }
+        properties.min_ts = buf.get_u64();
+        properties.max_ts = buf.get_u64();
+        properties.create_time = buf.get_u64();
+        let extractor_type = buf.get_u8();
+        let extractor_arg = buf.get_u64();
+        properties.prefix_extractor = match extractor_type {
+            1 => Some(PrefixExtractor::FixedLength(extractor_arg as usize)),
+            2 => Some(PrefixExtractor::Delimiter(extractor_arg as u8)),
+            _ => None,
+        };
+        properties.filter_type = FilterType::from_u8(buf.get_u8())?;
+        Ok(properties)
+    }
+}
+
 impl BlockMeta {
     /// Encode block meta to a buffer.
-    pub fn encode_block_meta(block_meta: &[BlockMeta], max_ts: u64, buf: &mut Vec<u8>) {
+    pub fn encode_block_meta(
+        block_meta: &[BlockMeta],
+        properties: &TableProperties,
+        buf: &mut Vec<u8>,
+    ) {
         let mut estimated_size = std::mem::size_of::<u32>(); // number of blocks
         for meta in block_meta {
             // The size of offset
@@ -43,7 +128,7 @@ impl BlockMeta {
             // The size of actual key
             estimated_size += meta.last_key.raw_len();
         }
-        estimated_size += std::mem::size_of::<u64>(); // max timestamp
+        estimated_size += TableProperties::ENCODED_SIZE;
         estimated_size += std::mem::size_of::<u32>(); // checksum
 
         // Reserve the space to improve performance, especially when the size of incoming data is
@@ -60,13 +145,13 @@ impl BlockMeta {
             buf.put_slice(meta.last_key.key_ref());
             buf.put_u64(meta.last_key.ts());
         }
-        buf.put_u64(max_ts);
+        properties.encode(buf);
         buf.put_u32(crc32fast::hash(&buf[original_len + 4..]));
         assert_eq!(estimated_size, buf.len() - original_len);
     }
 
     /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, TableProperties)> {
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
         let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
@@ -84,23 +169,32 @@ impl BlockMeta {
                 last_key,
             });
         }
-        let max_ts = buf.get_u64();
+        let Some(properties_len) = buf.remaining().checked_sub(4) else {
+            bail!("invalid block meta");
+        };
+        let properties = TableProperties::decode(&mut buf, properties_len)?;
         if buf.get_u32() != checksum {
             bail!("meta checksum mismatched");
         }
 
-        Ok((block_meta, max_ts))
+        Ok((block_meta, properties))
     }
 }
 
-/// A file object.
-pub struct FileObject(Option<File>, u64);
+/// A file object. The path is kept to delete the file when it is dropped after being marked as
+/// obsolete.
+pub struct FileObject {
+    file: Option<File>,
+    size: u64,
+    path: Option<PathBuf>,
+    obsolete: AtomicBool,
+}
 
 impl FileObject {
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
         use std::os::unix::fs::FileExt;
         let mut data = vec![0; len as usize];
-        self.0
+        self.file
             .as_ref()
             .unwrap()
             .read_exact_at(&mut data[..], offset)?;
@@ -108,23 +202,43 @@ impl FileObject {
     }
 
     pub fn size(&self) -> u64 {
-        self.1
+        self.size
     }
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
         std::fs::write(path, &data)?;
         File::open(path)?.sync_all()?;
-        Ok(FileObject(
-            Some(File::options().read(true).write(false).open(path)?),
-            data.len() as u64,
-        ))
+        Ok(FileObject {
+            file: Some(File::options().read(true).write(false).open(path)?),
+            size: data.len() as u64,
+            path: Some(path.to_path_buf()),
+            obsolete: AtomicBool::new(false),
+        })
     }
 
     pub fn open(path: &Path) -> Result<Self> {
         let file = File::options().read(true).write(false).open(path)?;
         let size = file.metadata()?.len();
-        Ok(FileObject(Some(file), size))
+        Ok(FileObject {
+            file: Some(file),
+            size,
+            path: Some(path.to_path_buf()),
+            obsolete: AtomicBool::new(false),
+        })
+    }
+}
+
+impl Drop for FileObject {
+    fn drop(&mut self) {
+        if !self.obsolete.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(path) = &self.path {
+            if let Err(e) = std::fs::remove_file(path) {
+                eprintln!("failed to remove obsolete {}: {}", path.display(), e);
+            }
+        }
     }
 }
 
@@ -140,8 +254,11 @@ pub struct SsTable {
     block_cache: Option<Arc<BlockCache>>,
     first_key: KeyBytes,
     last_key: KeyBytes,
+    /// The filter if it is a standard bloom filter.
     pub(crate) bloom: Option<Bloom>,
-    max_ts: u64,
+    /// The filter if it is of another type.
+    filter: Option<Box<dyn Filter>>,
+    properties: TableProperties,
 }
 impl SsTable {
     #[cfg(test)]
@@ -152,14 +269,17 @@ impl SsTable {
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
         let len = file.size();
-        let raw_bloom_offset = file.read(len - 4, 4)?;
-        let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
-        let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
-        let bloom_filter = Bloom::decode(&raw_bloom)?;
-        let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
+        let raw_filter_offset = file.read(len - 4, 4)?;
+        let filter_offset = (&raw_filter_offset[..]).get_u32() as u64;
+        let raw_meta_offset = file.read(filter_offset - 4, 4)?;
         let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let raw_meta = file.read(block_meta_offset, filter_offset - 4 - block_meta_offset)?;
+        let (block_meta, properties) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let raw_filter = file.read(filter_offset, len - 4 - filter_offset)?;
+        let (bloom, filter) = match properties.filter_type {
+            FilterType::Bloom => (Some(Bloom::decode(&raw_filter)?), None),
+            filter_type => (None, Some(decode_filter(filter_type, &raw_filter)?)),
+        };
         Ok(Self {
             file,
             first_key: block_meta.first().unwrap().first_key.clone(),
@@ -168,8 +288,9 @@ impl SsTable {
             block_meta_offset: block_meta_offset as usize,
             id,
             block_cache,
-            bloom: Some(bloom_filter),
-            max_ts,
+            bloom,
+            filter,
+            properties,
         })
     }
 
@@ -181,7 +302,12 @@ impl SsTable {
         last_key: KeyBytes,
     ) -> Self {
         Self {
-            file: FileObject(None, file_size),
+            file: FileObject {
+                file: None,
+                size: file_size,
+                path: None,
+                obsolete: AtomicBool::new(false),
+            },
             block_meta: vec![],
             block_meta_offset: 0,
             id,
@@ -189,10 +315,27 @@ impl SsTable {
             first_key,
             last_key,
             bloom: None,
-            max_ts: 0,
+            filter: None,
+            properties: TableProperties::default(),
         }
     }
 
+    /// Create a mock SST with only first key + last key metadata and the range of the timestamps
+    /// of its keys
+    pub fn create_meta_only_with_ts_range(
+        id: usize,
+        file_size: u64,
+        first_key: KeyBytes,
+        last_key: KeyBytes,
+        min_ts: u64,
+        max_ts: u64,
+    ) -> Self {
+        let mut table = Self::create_meta_only(id, file_size, first_key, last_key);
+        table.properties.min_ts = min_ts;
+        table.properties.max_ts = max_ts;
+        table
+    }
+
     /// Read a block from the disk.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
         let offset = self.block_meta[block_idx].offset;
@@ -245,14 +388,52 @@ impl SsTable {
     }
 
     pub fn table_size(&self) -> u64 {
-        self.file.1
+        self.file.size
     }
 
     pub fn sst_id(&self) -> usize {
         self.id
     }
 
+    pub fn min_ts(&self) -> u64 {
+        self.properties.min_ts
+    }
+
     pub fn max_ts(&self) -> u64 {
-        self.max_ts
+        self.properties.max_ts
+    }
+
+    /// When the table was built, in seconds since the UNIX epoch.
+    pub fn create_time(&self) -> u64 {
+        self.properties.create_time
+    }
+
+    /// The type of the filter of the table.
+    pub fn filter_type(&self) -> FilterType {
+        self.properties.filter_type
+    }
+
+    /// Whether the table may have a key with the hash, according to its filter.
+    pub fn may_contain_hash(&self, h: u32) -> bool {
+        if let Some(bloom) = &self.bloom {
+            bloom.may_contain(h)
+        } else if let Some(filter) = &self.filter {
+            filter.may_contain(h)
+        } else {
+            true
+        }
+    }
+
+    /// Whether the table may have keys starting with `prefix`, which must be extracted by
+    /// `extractor`. Only the tables built with the same extractor can tell.
+    pub fn may_contain_prefix(&self, extractor: &PrefixExtractor, prefix: &[u8]) -> bool {
+        self.properties.prefix_extractor.as_ref() != Some(extractor)
+            || self.may_contain_hash(farmhash::fingerprint32(prefix))
+    }
+
+    /// Marks the table as removed from the LSM tree, so that the file is deleted once the last
+    /// reference to the table is dropped.
+    pub(crate) fn mark_obsolete(&self) {
+        self.file.obsolete.store(true, Ordering::SeqCst);
     }
 }
@@ -0,0 +1,108 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::bloom::{BitSlice, BitSliceMut, Bloom};
+use super::filter::{Filter, FilterType};
+
+/// The bytes of a block, which is a cache line.
+const BLOCK_SIZE: usize = 64;
+/// The bits to address a bit in a block.
+const BLOCK_BITS_LOG2: u32 = 9;
+
+/// A Bloom filter split into blocks of a cache line. A key is hashed to a block, and all its bits
+/// are set in the block, so that a lookup touches one cache line instead of `k`.
+pub(crate) struct BlockedBloom {
+    /// The blocks, `BLOCK_SIZE` bytes each.
+    filter: Bytes,
+    /// number of hash functions
+    k: u8,
+}
+
+/// Remixes the key hash, so that the bits in a block do not correlate with the block.
+fn remix(mut h: u32) -> u32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+impl BlockedBloom {
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < BLOCK_SIZE + 5 {
+            bail!("invalid size {} of blocked bloom filters", buf.len());
+        }
+        let checksum = (&buf[buf.len() - 4..buf.len()]).get_u32();
+        if checksum != crc32fast::hash(&buf[..buf.len() - 4]) {
+            bail!("checksum mismatched for blocked bloom filters");
+        }
+        let filter = &buf[..buf.len() - 5];
+        if filter.is_empty() || !filter.len().is_multiple_of(BLOCK_SIZE) {
+            bail!("invalid size {} of blocked bloom filters", filter.len());
+        }
+        let k = buf[buf.len() - 5];
+        Ok(Self {
+            filter: filter.to_vec().into(),
+            k,
+        })
+    }
+
+    pub(crate) fn build_from_key_hashes(keys: &[u32], bits_per_key: usize) -> Self {
+        let k = (bits_per_key as f64 * 0.69) as u32;
+        let k = k.clamp(1, 30);
+        let nbits = keys.len() * bits_per_key;
+        let num_blocks = nbits.div_ceil(BLOCK_SIZE * 8).max(1);
+        let mut filter = BytesMut::zeroed(num_blocks * BLOCK_SIZE);
+        for h in keys {
+            let mut block = &mut filter[Self::block_range(*h, num_blocks)];
+            let mut h = remix(*h);
+            for _ in 0..k {
+                block.set_bit((h >> (32 - BLOCK_BITS_LOG2)) as usize, true);
+                h = h.wrapping_mul(0x9e37_79b9);
+            }
+        }
+        Self {
+            filter: filter.freeze(),
+            k: k as u8,
+        }
+    }
+
+    fn block_range(h: u32, num_blocks: usize) -> std::ops::Range<usize> {
+        let idx = ((h as u64 * num_blocks as u64) >> 32) as usize;
+        idx * BLOCK_SIZE..(idx + 1) * BLOCK_SIZE
+    }
+}
+
+impl Filter for BlockedBloom {
+    fn filter_type(&self) -> FilterType {
+        FilterType::BlockedBloom
+    }
+
+    fn may_contain(&self, h: u32) -> bool {
+        let block = &self.filter[Self::block_range(h, self.filter.len() / BLOCK_SIZE)];
+        let mut h = remix(h);
+        for _ in 0..self.k {
+            if !block.get_bit((h >> (32 - BLOCK_BITS_LOG2)) as usize) {
+                return false;
+            }
+            h = h.wrapping_mul(0x9e37_79b9);
+        }
+        true
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        buf.extend(&self.filter);
+        buf.put_u8(self.k);
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+    }
+
+    fn size(&self) -> usize {
+        self.filter.len() + 5
+    }
+
+    fn into_bloom(self: Box<Self>) -> Result<Bloom, Box<dyn Filter>> {
+        Err(self)
+    }
+}
@@ -1,14 +1,16 @@
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
 use bytes::BufMut;
 
 use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
+use super::filter::{Filter, FilterPolicy};
+use super::{BlockMeta, FileObject, SsTable, TableProperties};
 use crate::block::BlockBuilder;
 use crate::key::{KeySlice, KeyVec};
-use crate::lsm_storage::BlockCache;
+use crate::lsm_storage::{BlockCache, PrefixExtractor};
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
@@ -19,12 +21,27 @@ pub struct SsTableBuilder {
     pub(crate) meta: Vec<BlockMeta>,
     block_size: usize,
     key_hashes: Vec<u32>,
+    prefix_extractor: Option<PrefixExtractor>,
+    /// The prefix of the last key added, whose hash is already in `key_hashes`.
+    last_prefix: Option<Vec<u8>>,
+    /// Builds the filter, a bloom filter with 1% false positive rate if not set.
+    filter_policy: Option<Arc<dyn FilterPolicy>>,
+    min_ts: u64,
     max_ts: u64,
 }
 
 impl SsTableBuilder {
     /// Create a builder based on target block size.
     pub fn new(block_size: usize) -> Self {
+        Self::new_with_prefix_extractor(block_size, None)
+    }
+
+    /// Create a builder which also adds the key prefixes extracted by `prefix_extractor` to the
+    /// bloom filter.
+    pub fn new_with_prefix_extractor(
+        block_size: usize,
+        prefix_extractor: Option<PrefixExtractor>,
+    ) -> Self {
         Self {
             data: Vec::new(),
             meta: Vec::new(),
@@ -33,20 +50,43 @@ impl SsTableBuilder {
             block_size,
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
+            prefix_extractor,
+            last_prefix: None,
+            filter_policy: None,
+            min_ts: u64::MAX,
             max_ts: 0,
         }
     }
 
+    /// Builds the filter of the SSTable with `filter_policy`.
+    pub fn with_filter_policy(mut self, filter_policy: Option<Arc<dyn FilterPolicy>>) -> Self {
+        self.filter_policy = filter_policy;
+        self
+    }
+
     /// Adds a key-value pair to SSTable
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
 
+        if key.ts() < self.min_ts {
+            self.min_ts = key.ts();
+        }
         if key.ts() > self.max_ts {
             self.max_ts = key.ts();
         }
         self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
+        if let Some(prefix) = self
+            .prefix_extractor
+            .and_then(|extractor| extractor.extract(key.key_ref()))
+        {
+            // the keys are sorted, so the keys with the same prefix are added one after another
+            if self.last_prefix.as_deref() != Some(prefix) {
+                self.key_hashes.push(farmhash::fingerprint32(prefix));
+                self.last_prefix = Some(prefix.to_vec());
+            }
+        }
 
         if self.builder.add(key, value) {
             self.last_key.set_from_slice(key);
@@ -90,15 +130,33 @@ impl SsTableBuilder {
         self.finish_block();
         let mut buf = self.data;
         let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
+        let filter: Box<dyn Filter> = match &self.filter_policy {
+            Some(policy) => policy.build(&self.key_hashes),
+            None => Box::new(Bloom::build_from_key_hashes(
+                &self.key_hashes,
+                Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
+            )),
+        };
+        let properties = TableProperties {
+            min_ts: self.min_ts,
+            max_ts: self.max_ts,
+            create_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or_default(),
+            prefix_extractor: self.prefix_extractor,
+            filter_type: filter.filter_type(),
+        };
+        BlockMeta::encode_block_meta(&self.meta, &properties, &mut buf);
         buf.put_u32(meta_offset as u32);
-        let bloom = Bloom::build_from_key_hashes(
-            &self.key_hashes,
-            Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
-        );
-        let bloom_offset = buf.len();
-        bloom.encode(&mut buf);
-        buf.put_u32(bloom_offset as u32);
+        let filter_offset = buf.len();
+        filter.encode(&mut buf);
+        buf.put_u32(filter_offset as u32);
+        // the standard bloom filter is kept apart, so that it can be checked without dynamic dispatch
+        let (bloom, filter) = match filter.into_bloom() {
+            Ok(bloom) => (Some(bloom), None),
+            Err(filter) => (None, Some(filter)),
+        };
         let file = FileObject::create(path.as_ref(), buf)?;
         Ok(SsTable {
             id,
@@ -108,8 +166,9 @@ impl SsTableBuilder {
             block_meta: self.meta,
             block_meta_offset: meta_offset,
             block_cache,
-            bloom: Some(bloom),
-            max_ts: self.max_ts,
+            bloom,
+            filter,
+            properties,
         })
     }
 
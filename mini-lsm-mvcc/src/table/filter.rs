@@ -0,0 +1,133 @@
+use std::fmt::Debug;
+
+use anyhow::{bail, Result};
+
+use super::blocked_bloom::BlockedBloom;
+use super::bloom::Bloom;
+use super::ribbon::Ribbon;
+
+/// The type of the filter of an SST, which is recorded in the SST to decode the filter with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterType {
+    /// The standard Bloom filter.
+    #[default]
+    Bloom,
+    /// A Bloom filter whose probes for a key are all in one cache line.
+    BlockedBloom,
+    /// A Ribbon filter, which is smaller than a Bloom filter at the same false positive rate, but
+    /// slower to build.
+    Ribbon,
+}
+
+impl FilterType {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            FilterType::Bloom => 0,
+            FilterType::BlockedBloom => 1,
+            FilterType::Ribbon => 2,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Result<Self> {
+        Ok(match value {
+            0 => FilterType::Bloom,
+            1 => FilterType::BlockedBloom,
+            2 => FilterType::Ribbon,
+            _ => bail!("unknown filter type {}", value),
+        })
+    }
+}
+
+/// A filter over the key hashes of an SST, which tells that a key is not in the SST.
+pub trait Filter: Send + Sync {
+    fn filter_type(&self) -> FilterType;
+
+    /// Whether a key with the hash may be in the SST. A `false` is always right.
+    fn may_contain(&self, h: u32) -> bool;
+
+    /// Appends the filter followed by its checksum.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// The size of the encoded filter in bytes.
+    fn size(&self) -> usize;
+
+    /// Unwraps a standard Bloom filter, which the SSTs keep apart from the other filters.
+    fn into_bloom(self: Box<Self>) -> Result<Bloom, Box<dyn Filter>>;
+}
+
+impl Filter for Bloom {
+    fn filter_type(&self) -> FilterType {
+        FilterType::Bloom
+    }
+
+    fn may_contain(&self, h: u32) -> bool {
+        Bloom::may_contain(self, h)
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        Bloom::encode(self, buf)
+    }
+
+    fn size(&self) -> usize {
+        self.filter.len() + 5
+    }
+
+    fn into_bloom(self: Box<Self>) -> Result<Bloom, Box<dyn Filter>> {
+        Ok(*self)
+    }
+}
+
+/// Decodes a filter encoded by `Filter::encode`.
+pub(crate) fn decode_filter(filter_type: FilterType, buf: &[u8]) -> Result<Box<dyn Filter>> {
+    Ok(match filter_type {
+        FilterType::Bloom => Box::new(Bloom::decode(buf)?),
+        FilterType::BlockedBloom => Box::new(BlockedBloom::decode(buf)?),
+        FilterType::Ribbon => Box::new(Ribbon::decode(buf)?),
+    })
+}
+
+/// Builds the filters of the SSTs.
+pub trait FilterPolicy: Debug + Send + Sync {
+    fn build(&self, key_hashes: &[u32]) -> Box<dyn Filter>;
+}
+
+/// Builds standard Bloom filters.
+#[derive(Debug, Clone, Copy)]
+pub struct BloomPolicy {
+    pub bits_per_key: usize,
+}
+
+impl FilterPolicy for BloomPolicy {
+    fn build(&self, key_hashes: &[u32]) -> Box<dyn Filter> {
+        Box::new(Bloom::build_from_key_hashes(key_hashes, self.bits_per_key))
+    }
+}
+
+/// Builds Bloom filters in blocks of a cache line. A lookup reads a single cache line, at the
+/// cost of a higher false positive rate than a standard Bloom filter of the same size.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockedBloomPolicy {
+    pub bits_per_key: usize,
+}
+
+impl FilterPolicy for BlockedBloomPolicy {
+    fn build(&self, key_hashes: &[u32]) -> Box<dyn Filter> {
+        Box::new(BlockedBloom::build_from_key_hashes(
+            key_hashes,
+            self.bits_per_key,
+        ))
+    }
+}
+
+/// Builds Ribbon filters with a false positive rate of about `2^-bits_per_key`, which take 5% to
+/// 15% more than `bits_per_key` bits for each key, the more keys the more.
+#[derive(Debug, Clone, Copy)]
+pub struct RibbonPolicy {
+    pub bits_per_key: usize,
+}
+
+impl FilterPolicy for RibbonPolicy {
+    fn build(&self, key_hashes: &[u32]) -> Box<dyn Filter> {
+        Box::new(Ribbon::build_from_key_hashes(key_hashes, self.bits_per_key))
+    }
+}
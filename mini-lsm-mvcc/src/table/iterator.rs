@@ -4,7 +4,7 @@ use anyhow::Result;
 
 use super::SsTable;
 use crate::block::BlockIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{SeekableIterator, StorageIterator};
 use crate::key::KeySlice;
 
 /// An iterator over the contents of an SSTable.
@@ -103,3 +103,9 @@ impl StorageIterator for SsTableIterator {
         Ok(())
     }
 }
+
+impl SeekableIterator for SsTableIterator {
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_to_key(key)
+    }
+}
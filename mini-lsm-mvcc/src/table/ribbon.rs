@@ -0,0 +1,207 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+use super::bloom::Bloom;
+use super::filter::{Filter, FilterType};
+
+/// The width of the band of coefficients of a key.
+const WIDTH: usize = 64;
+/// The seeds tried at a size before the filter is made larger.
+const SEEDS_PER_SIZE: u32 = 2;
+
+/// A standard Ribbon filter, see "Ribbon filter: practically smaller than Bloom and Xor" by
+/// Dillinger and Walzer.
+///
+/// Each key is a linear equation over GF(2): its `WIDTH` coefficients starting at a slot, dotted
+/// with the solution, give its `r`-bit fingerprint. The equations are solved by banded Gaussian
+/// elimination when the filter is built, and a lookup checks the equation of the key.
+pub(crate) struct Ribbon {
+    /// The solution. The `r` bits of a slot are in `r` columns of words, and the words of the
+    /// columns are interleaved, `data[i * r + b]` holding slots `i * 64..(i + 1) * 64` of column
+    /// `b`.
+    data: Vec<u64>,
+    /// The number of slots, which is a multiple of 64.
+    num_slots: usize,
+    /// The bits of the fingerprints.
+    result_bits: u8,
+    seed: u32,
+}
+
+fn mix64(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
+
+/// The equation of a key: the first slot, the coefficients from that slot, and the fingerprint.
+fn equation(h: u32, seed: u32, num_slots: usize, result_bits: u8) -> (usize, u64, u32) {
+    let h = mix64(((seed as u64) << 32) | h as u64);
+    let num_starts = (num_slots - WIDTH + 1) as u64;
+    let start = (((h >> 32) * num_starts) >> 32) as usize;
+    let coeff = mix64(h ^ 0x9e37_79b9_7f4a_7c15) | 1;
+    let fingerprint = h as u32 & result_mask(result_bits);
+    (start, coeff, fingerprint)
+}
+
+fn result_mask(result_bits: u8) -> u32 {
+    (((1u64) << result_bits) - 1) as u32
+}
+
+/// The slots for `n` keys at the `attempt`-th size. The equations of a standard Ribbon filter
+/// need more spare slots with more keys, about 6% at 10k keys and 12% at 1M keys to be solved
+/// with the first seed, and every size tried after it has 1% more.
+fn num_slots(n: usize, attempt: u32) -> usize {
+    let overhead = 0.006 * (n.max(2) as f64).log2() + 0.01 * (attempt / SEEDS_PER_SIZE) as f64;
+    (n + (n as f64 * overhead) as usize + WIDTH).next_multiple_of(64)
+}
+
+impl Ribbon {
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 13 {
+            bail!("invalid size {} of ribbon filters", buf.len());
+        }
+        let checksum = (&buf[buf.len() - 4..]).get_u32();
+        if checksum != crc32fast::hash(&buf[..buf.len() - 4]) {
+            bail!("checksum mismatched for ribbon filters");
+        }
+        let mut trailer = &buf[buf.len() - 13..buf.len() - 4];
+        let num_blocks = trailer.get_u32() as usize;
+        let result_bits = trailer.get_u8();
+        let seed = trailer.get_u32();
+        let mut words = &buf[..buf.len() - 13];
+        if result_bits == 0
+            || result_bits > 32
+            || num_blocks == 0
+            || words.len() != num_blocks * result_bits as usize * 8
+        {
+            bail!("invalid ribbon filters");
+        }
+        let mut data = Vec::with_capacity(words.len() / 8);
+        while words.has_remaining() {
+            data.push(words.get_u64_le());
+        }
+        Ok(Self {
+            data,
+            num_slots: num_blocks * 64,
+            result_bits,
+            seed,
+        })
+    }
+
+    pub(crate) fn build_from_key_hashes(keys: &[u32], bits_per_key: usize) -> Self {
+        let result_bits = bits_per_key.clamp(1, 32) as u8;
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+        for attempt in 0.. {
+            let num_slots = num_slots(keys.len(), attempt);
+            if let Some(ribbon) = Self::try_build(&keys, num_slots, result_bits, attempt) {
+                return ribbon;
+            }
+        }
+        unreachable!()
+    }
+
+    /// Solves the equations of the keys, which fails if they are not linearly independent.
+    fn try_build(keys: &[u32], num_slots: usize, result_bits: u8, seed: u32) -> Option<Self> {
+        // the row of the equation whose first coefficient is at the slot, 0 for none
+        let mut coeffs = vec![0u64; num_slots];
+        let mut results = vec![0u32; num_slots];
+        for &h in keys {
+            let (mut start, mut coeff, mut fingerprint) = equation(h, seed, num_slots, result_bits);
+            loop {
+                if coeffs[start] == 0 {
+                    coeffs[start] = coeff;
+                    results[start] = fingerprint;
+                    break;
+                }
+                coeff ^= coeffs[start];
+                fingerprint ^= results[start];
+                if coeff == 0 {
+                    // another key with the same equation is fine, a contradicting one is not
+                    if fingerprint != 0 {
+                        return None;
+                    }
+                    break;
+                }
+                let shift = coeff.trailing_zeros();
+                start += shift as usize;
+                coeff >>= shift;
+            }
+        }
+
+        // back substitution, from the last slot to the first, with the solution of the following
+        // 63 slots in the bits 1.. of `state`
+        let r = result_bits as usize;
+        let mut data = vec![0u64; num_slots / 64 * r];
+        let mut state = vec![0u64; r];
+        for slot in (0..num_slots).rev() {
+            let coeff = coeffs[slot];
+            // a slot without equation can be anything, and random bits keep the false positive
+            // rate of other keys at `2^-r`
+            let result = if coeff == 0 {
+                mix64(slot as u64 ^ seed as u64) as u32
+            } else {
+                results[slot]
+            };
+            for (b, state) in state.iter_mut().enumerate() {
+                *state <<= 1;
+                let mut bit = (result >> b) as u64 & 1;
+                if coeff != 0 {
+                    bit ^= (coeff & *state).count_ones() as u64 & 1;
+                }
+                *state |= bit;
+                data[slot / 64 * r + b] |= bit << (slot % 64);
+            }
+        }
+        Some(Self {
+            data,
+            num_slots,
+            result_bits,
+            seed,
+        })
+    }
+}
+
+impl Filter for Ribbon {
+    fn filter_type(&self) -> FilterType {
+        FilterType::Ribbon
+    }
+
+    fn may_contain(&self, h: u32) -> bool {
+        let (start, coeff, fingerprint) = equation(h, self.seed, self.num_slots, self.result_bits);
+        let r = self.result_bits as usize;
+        let (block, offset) = (start / 64, start % 64);
+        let mut result = 0;
+        for b in 0..r {
+            let mut window = self.data[block * r + b] >> offset;
+            if offset > 0 {
+                window |= self.data[(block + 1) * r + b] << (64 - offset);
+            }
+            result |= ((coeff & window).count_ones() & 1) << b;
+        }
+        result == fingerprint
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        for word in &self.data {
+            buf.put_u64_le(*word);
+        }
+        buf.put_u32((self.num_slots / 64) as u32);
+        buf.put_u8(self.result_bits);
+        buf.put_u32(self.seed);
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+    }
+
+    fn size(&self) -> usize {
+        self.data.len() * 8 + 13
+    }
+
+    fn into_bloom(self: Box<Self>) -> Result<Bloom, Box<dyn Filter>> {
+        Err(self)
+    }
+}
@@ -1,4 +1,31 @@
+mod background_work;
+mod cdc;
+mod commit_ts;
+mod compaction_priority;
+mod filter_policy;
 mod harness;
+mod intra_l0_compaction;
+mod iterator_seek;
+mod multi_get;
+mod obsolete_files;
+mod orphan_files;
+mod periodic_compaction;
+mod pessimistic_txn;
+mod point_lookup;
+mod prefix_bloom;
+mod read_only_txn;
+mod refreshing_iterator;
+mod savepoint;
+mod scan_bounds;
+mod serializable_scan;
+mod snapshot;
+mod table_properties;
+mod tiered_compaction;
+mod two_phase_commit;
+mod txn_conflict;
+mod txn_spill;
+mod txn_timeout;
+mod watermark;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
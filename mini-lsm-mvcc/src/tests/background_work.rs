@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, CompactionPriority, CompactionTask, LeveledCompactionOptions},
+    lsm_storage::{CancellationToken, LsmStorageOptions, MiniLsm},
+};
+
+fn sst_files_in_dir(path: &std::path::Path) -> Vec<String> {
+    let mut files = path
+        .read_dir()
+        .unwrap()
+        .map(|f| f.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|f| f.ends_with(".sst"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_pause_and_resume_background_work() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+            LeveledCompactionOptions {
+                level_size_multiplier: 2,
+                level0_file_num_compaction_trigger: 2,
+                max_levels: 3,
+                base_level_size_mb: 1,
+                periodic_compaction_seconds: None,
+                compaction_priority: CompactionPriority::OldestSstId,
+                level0_intra_compaction_trigger: None,
+            },
+        )),
+    )
+    .unwrap();
+    storage.pause_background_work();
+    for i in 0..3 {
+        storage
+            .put(format!("{i}").as_bytes(), format!("v{i}").as_bytes())
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 3);
+
+    storage.resume_background_work();
+    let mut compacted = false;
+    for _ in 0..100 {
+        std::thread::sleep(Duration::from_millis(50));
+        if storage.inner.state.read().l0_sstables.is_empty() {
+            compacted = true;
+            break;
+        }
+    }
+    assert!(compacted, "compaction did not resume");
+    for i in 0..3 {
+        assert_eq!(
+            storage.get(format!("{i}").as_bytes()).unwrap(),
+            Some(Bytes::from(format!("v{i}")))
+        );
+    }
+}
+
+#[test]
+fn test_cancelled_compaction_leaves_no_output() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    for i in 0..2 {
+        storage
+            .put(format!("{i}").as_bytes(), format!("v{i}").as_bytes())
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    let files = sst_files_in_dir(dir.path());
+
+    let task = {
+        let state = storage.inner.state.read();
+        CompactionTask::ForceFullCompaction {
+            l0_sstables: state.l0_sstables.clone(),
+            l1_sstables: state.levels[0].1.clone(),
+        }
+    };
+    let token = CancellationToken::default();
+    token.cancel();
+    assert!(storage.inner.compact(&task, Some(&token)).is_err());
+    assert_eq!(sst_files_in_dir(dir.path()), files);
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 2);
+
+    storage.force_full_compaction().unwrap();
+    assert!(storage.inner.state.read().l0_sstables.is_empty());
+    for i in 0..2 {
+        assert_eq!(
+            storage.get(format!("{i}").as_bytes()).unwrap(),
+            Some(Bytes::from(format!("v{i}")))
+        );
+    }
+}
+
+#[test]
+fn test_pause_does_not_cancel_user_work() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    // stands for a background flush or compaction that takes a while to stop
+    let guard = storage.inner.background_work_guard().unwrap();
+    std::thread::scope(|s| {
+        let pause = s.spawn(|| storage.pause_background_work());
+        while !guard.token.is_cancelled() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // the flushes and compactions requested by the user while pausing are not cancelled
+        for i in 0..2 {
+            storage
+                .put(format!("{i}").as_bytes(), format!("v{i}").as_bytes())
+                .unwrap();
+            storage.force_flush().unwrap();
+        }
+        storage.force_full_compaction().unwrap();
+        drop(guard);
+        pause.join().unwrap();
+    });
+    assert!(storage.inner.state.read().l0_sstables.is_empty());
+    for i in 0..2 {
+        assert_eq!(
+            storage.get(format!("{i}").as_bytes()).unwrap(),
+            Some(Bytes::from(format!("v{i}")))
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::cdc::ChangeRecord;
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, OrphanFileAction, WriteBatchRecord};
+
+fn change(commit_ts: u64, key: &'static [u8], value: Option<&'static [u8]>) -> ChangeRecord {
+    ChangeRecord {
+        commit_ts,
+        key: Bytes::from_static(key),
+        value: value.map(Bytes::from_static),
+    }
+}
+
+#[test]
+fn test_subscribe() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut stream = storage.subscribe(1).unwrap();
+    assert!(stream.poll().unwrap().is_empty());
+
+    storage.put(b"b", b"1").unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"c"[..], &b"2"[..]),
+            WriteBatchRecord::Put(b"a", b"2"),
+            WriteBatchRecord::Del(b"b"),
+        ])
+        .unwrap();
+    assert_eq!(
+        stream.poll().unwrap(),
+        vec![
+            change(1, b"b", Some(b"1")),
+            change(2, b"a", Some(b"2")),
+            change(2, b"b", None),
+            change(2, b"c", Some(b"2")),
+        ]
+    );
+    assert!(stream.poll().unwrap().is_empty());
+    assert_eq!(stream.next_ts(), 3);
+
+    // resume from a ts
+    let mut stream = storage.subscribe(2).unwrap();
+    storage.put(b"d", b"3").unwrap();
+    let changes = stream.poll().unwrap();
+    assert_eq!(changes.len(), 4);
+    assert_eq!(changes[3], change(3, b"d", Some(b"3")));
+}
+
+#[test]
+fn test_subscribe_poll_from_position() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut stream = storage.subscribe(1).unwrap();
+    storage.put(b"a", b"0").unwrap();
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    assert_eq!(stream.poll().unwrap(), vec![change(1, b"a", Some(b"0"))]);
+    // only the new versions are returned, whatever the older versions of the keys
+    for ts in 2..10 {
+        storage.put(b"a", b"1").unwrap();
+        storage.put(b"b", b"1").unwrap();
+        assert_eq!(
+            stream.poll().unwrap(),
+            vec![
+                change(ts * 2 - 2, b"a", Some(b"1")),
+                change(ts * 2 - 1, b"b", Some(b"1")),
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_subscribe_wait() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut stream = storage.subscribe(1).unwrap();
+    assert!(stream.wait(Duration::from_millis(10)).unwrap().is_empty());
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            storage.put(b"a", b"1").unwrap();
+        })
+    };
+    assert_eq!(
+        stream.wait(Duration::from_secs(10)).unwrap(),
+        vec![change(1, b"a", Some(b"1"))]
+    );
+    writer.join().unwrap();
+}
+
+#[test]
+fn test_subscribe_retained_wals() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.cdc_wal_retention = 1;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut stream = storage.subscribe(1).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"a", b"2").unwrap();
+
+    // the flushed writes are read from the retained WAL
+    let mut lagging_stream = storage.subscribe(1).unwrap();
+    assert_eq!(
+        lagging_stream.poll().unwrap(),
+        vec![
+            change(1, b"a", Some(b"1")),
+            change(2, b"b", Some(b"1")),
+            change(3, b"a", Some(b"2")),
+        ]
+    );
+    assert_eq!(stream.poll().unwrap().len(), 3);
+
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.force_flush().unwrap();
+    // only the latest WAL is retained
+    assert!(storage.subscribe(1).is_err());
+    assert!(storage.subscribe(3).is_err());
+    assert!(lagging_stream.poll().is_ok());
+    assert_eq!(
+        storage.subscribe(4).unwrap().poll().unwrap(),
+        vec![change(4, b"b", Some(b"2"))]
+    );
+    assert_eq!(stream.poll().unwrap(), vec![change(4, b"b", Some(b"2"))]);
+}
+
+#[test]
+fn test_subscribe_resume_in_retained_wals() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.cdc_wal_retention = 3;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut stream = storage.subscribe(1).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"1").unwrap();
+    assert_eq!(
+        stream.poll().unwrap(),
+        vec![change(1, b"a", Some(b"1")), change(2, b"b", Some(b"1"))]
+    );
+    // the stream continues in the WAL it stopped in, and then in the newer ones
+    storage.put(b"a", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"c", b"1").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(
+        stream.poll().unwrap(),
+        vec![change(3, b"a", Some(b"2")), change(4, b"c", Some(b"1"))]
+    );
+    assert!(stream.poll().unwrap().is_empty());
+    storage.put(b"b", b"2").unwrap();
+    assert_eq!(stream.poll().unwrap(), vec![change(5, b"b", Some(b"2"))]);
+}
+
+#[test]
+fn test_subscribe_without_retention() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let mut stream = storage.subscribe(1).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    assert!(stream.poll().is_err());
+    assert!(storage.subscribe(1).is_err());
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(
+        storage.subscribe(2).unwrap().poll().unwrap(),
+        vec![change(2, b"a", Some(b"2"))]
+    );
+    storage.close().unwrap();
+    drop(storage);
+
+    // the writes in the WALs are still available after a restart
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.subscribe(1).is_err());
+    assert_eq!(
+        storage.subscribe(2).unwrap().poll().unwrap(),
+        vec![change(2, b"a", Some(b"2"))]
+    );
+}
+
+#[test]
+fn test_subscribe_retained_wals_after_restart() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.cdc_wal_retention = 2;
+    options.orphan_file_action = OrphanFileAction::Quarantine;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for value in [b"1", b"2", b"3"] {
+        storage.put(b"a", value).unwrap();
+        storage.force_flush().unwrap();
+    }
+    storage.put(b"a", b"4").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // the retained WALs are not orphan files
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert!(storage.orphan_files().wals.is_empty());
+    assert!(storage.subscribe(1).is_err());
+    assert_eq!(
+        storage.subscribe(2).unwrap().poll().unwrap(),
+        vec![
+            change(2, b"a", Some(b"2")),
+            change(3, b"a", Some(b"3")),
+            change(4, b"a", Some(b"4")),
+        ]
+    );
+    storage.force_flush().unwrap();
+    assert!(storage.subscribe(2).is_err());
+    assert_eq!(storage.subscribe(3).unwrap().poll().unwrap().len(), 2);
+    storage.close().unwrap();
+    drop(storage);
+
+    // the WALs beyond a lower retention are not kept
+    options.cdc_wal_retention = 1;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.orphan_files().wals.len(), 1);
+    assert!(storage.subscribe(3).is_err());
+    assert_eq!(
+        storage.subscribe(4).unwrap().poll().unwrap(),
+        vec![change(4, b"a", Some(b"4"))]
+    );
+}
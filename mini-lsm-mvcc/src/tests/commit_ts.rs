@@ -0,0 +1,33 @@
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_commit_ts_not_going_backwards_after_restart() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.ts_reservation_window = 3;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..10 {
+        storage
+            .put(format!("key{}", i).as_bytes(), b"value")
+            .unwrap();
+    }
+    let commit_ts = storage.inner.mvcc().latest_commit_ts();
+    let watermark = storage.inner.mvcc().watermark();
+    assert_eq!(commit_ts, 10);
+    // the memtable is not flushed and there is no WAL, so all the data is lost
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.get(b"key0").unwrap(), None);
+    assert!(storage.inner.mvcc().latest_commit_ts() >= commit_ts);
+    assert!(storage.inner.mvcc().watermark() >= watermark);
+    storage.put(b"key0", b"value").unwrap();
+    let new_commit_ts = storage.inner.mvcc().latest_commit_ts();
+    assert!(new_commit_ts > commit_ts);
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.inner.mvcc().latest_commit_ts() >= new_commit_ts);
+}
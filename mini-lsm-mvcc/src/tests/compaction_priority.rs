@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::{
+    compact::{
+        CompactionPriority, LeveledCompactionController, LeveledCompactionOptions,
+        LeveledCompactionTask,
+    },
+    key::KeyBytes,
+    lsm_storage::LsmStorageState,
+    mem_table::MemTable,
+    table::SsTable,
+};
+
+fn key_of(key: &'static str) -> KeyBytes {
+    KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(key.as_bytes()))
+}
+
+fn add_sst(
+    state: &mut LsmStorageState,
+    id: usize,
+    first_key: &'static str,
+    last_key: &'static str,
+) {
+    state.sstables.insert(
+        id,
+        Arc::new(SsTable::create_meta_only(
+            id,
+            1024 * 1024,
+            key_of(first_key),
+            key_of(last_key),
+        )),
+    );
+}
+
+fn round_robin_controller() -> LeveledCompactionController {
+    LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+        base_level_size_mb: 1,
+        periodic_compaction_seconds: None,
+        compaction_priority: CompactionPriority::RoundRobin,
+        level0_intra_compaction_trigger: None,
+    })
+}
+
+#[test]
+fn test_round_robin_cursor_recovered_from_task() {
+    let mut state = LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: Vec::new(),
+        levels: vec![(1, vec![1, 2, 3]), (2, Vec::new())],
+        sstables: Default::default(),
+    };
+    add_sst(&mut state, 1, "a", "b");
+    add_sst(&mut state, 2, "c", "d");
+    add_sst(&mut state, 3, "e", "f");
+
+    let controller = round_robin_controller();
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(1));
+    assert_eq!(task.upper_level_sst_ids, vec![1]);
+    assert_eq!(task.compact_cursor.as_deref(), Some(&b"b"[..]));
+
+    // the cursor is carried by the task through the manifest encoding
+    let task = serde_json::from_slice::<LeveledCompactionTask>(&serde_json::to_vec(&task).unwrap())
+        .unwrap();
+    add_sst(&mut state, 4, "a", "b");
+    let (new_state, _) = controller.apply_compaction_result(&state, &task, &[4]);
+    let next_task = controller.generate_compaction_task(&new_state).unwrap();
+    assert_eq!(next_task.upper_level_sst_ids, vec![2]);
+
+    // replaying the task on a new controller restores the cursor
+    let recovered_controller = round_robin_controller();
+    let (new_state, _) = recovered_controller.apply_compaction_result(&state, &task, &[4]);
+    let next_task = recovered_controller
+        .generate_compaction_task(&new_state)
+        .unwrap();
+    assert_eq!(next_task.upper_level_sst_ids, vec![2]);
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, SimpleLeveledCompactionOptions};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::table::filter::{
+    decode_filter, BlockedBloomPolicy, BloomPolicy, FilterPolicy, FilterType, RibbonPolicy,
+};
+
+fn key_hashes(range: std::ops::Range<usize>) -> Vec<u32> {
+    range
+        .map(|idx| farmhash::fingerprint32(format!("key_{:05}", idx).as_bytes()))
+        .collect()
+}
+
+#[test]
+fn test_filter_policies() {
+    let policies: [(Arc<dyn FilterPolicy>, FilterType, f64); 4] = [
+        (
+            Arc::new(BloomPolicy { bits_per_key: 10 }),
+            FilterType::Bloom,
+            0.03,
+        ),
+        (
+            Arc::new(BlockedBloomPolicy { bits_per_key: 10 }),
+            FilterType::BlockedBloom,
+            0.03,
+        ),
+        (
+            Arc::new(RibbonPolicy { bits_per_key: 10 }),
+            FilterType::Ribbon,
+            0.005,
+        ),
+        (
+            Arc::new(RibbonPolicy { bits_per_key: 4 }),
+            FilterType::Ribbon,
+            0.1,
+        ),
+    ];
+    let absent_keys = key_hashes(50000..70000);
+    for (policy, filter_type, max_fpr) in &policies {
+        for num_keys in [0, 1, 100, 10000] {
+            let keys = key_hashes(0..num_keys);
+            let filter = policy.build(&keys);
+            assert_eq!(filter.filter_type(), *filter_type);
+            let mut buf = Vec::new();
+            filter.encode(&mut buf);
+            assert_eq!(buf.len(), filter.size());
+            let filter = decode_filter(*filter_type, &buf).unwrap();
+            for h in &keys {
+                assert!(filter.may_contain(*h), "{:?} misses a key", policy);
+            }
+            if num_keys >= 100 {
+                let fpr = absent_keys
+                    .iter()
+                    .filter(|h| filter.may_contain(**h))
+                    .count() as f64
+                    / absent_keys.len() as f64;
+                assert!(
+                    fpr <= *max_fpr,
+                    "{:?} with {} keys has false positive rate {}",
+                    policy,
+                    num_keys,
+                    fpr
+                );
+            }
+            // a corrupted or truncated filter is rejected
+            buf[0] ^= 1;
+            assert!(decode_filter(*filter_type, &buf).is_err());
+            for len in 0..6 {
+                assert!(decode_filter(*filter_type, &buf[..len]).is_err());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_filter_policy_per_level() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    // the last policy is also used for L2
+    options.filter_policies = vec![
+        Arc::new(RibbonPolicy { bits_per_key: 8 }),
+        Arc::new(BlockedBloomPolicy { bits_per_key: 12 }),
+    ];
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for (step, value) in [(2, b"bottom"), (3, b"middle")] {
+        for idx in (0..1000).step_by(step) {
+            storage
+                .put(format!("key_{:05}", idx).as_bytes(), value)
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let mut compacted = false;
+    for _ in 0..100 {
+        std::thread::sleep(Duration::from_millis(50));
+        let state = storage.inner.state.read();
+        if state.l0_sstables.is_empty() && state.levels[0].1.is_empty() {
+            compacted = true;
+            break;
+        }
+    }
+    assert!(compacted, "the SSTs are not compacted to L2");
+    storage.pause_background_work();
+    for idx in (0..1000).step_by(5) {
+        storage
+            .put(format!("key_{:05}", idx).as_bytes(), b"l0")
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+
+    let check = |storage: &MiniLsm| {
+        {
+            let state = storage.inner.state.read();
+            assert_eq!(state.l0_sstables.len(), 1);
+            for id in &state.l0_sstables {
+                assert_eq!(state.sstables[id].filter_type(), FilterType::Ribbon);
+            }
+            assert!(!state.levels[1].1.is_empty());
+            for id in &state.levels[1].1 {
+                assert_eq!(state.sstables[id].filter_type(), FilterType::BlockedBloom);
+            }
+        }
+        for idx in 0..1000 {
+            let value = storage.get(format!("key_{:05}", idx).as_bytes()).unwrap();
+            let expected: Option<&[u8]> = if idx % 5 == 0 {
+                Some(b"l0")
+            } else if idx % 3 == 0 {
+                Some(b"middle")
+            } else if idx % 2 == 0 {
+                Some(b"bottom")
+            } else {
+                None
+            };
+            assert_eq!(value.as_deref(), expected, "key_{:05}", idx);
+        }
+    };
+    check(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    // the filter types are read from the SSTs, whatever the policies are
+    options.filter_policies = Vec::new();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+}
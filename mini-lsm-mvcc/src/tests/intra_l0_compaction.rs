@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, CompactionPriority, LeveledCompactionController,
+        LeveledCompactionOptions,
+    },
+    key::KeyBytes,
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+    mem_table::MemTable,
+    table::SsTable,
+};
+
+fn leveled_options() -> LeveledCompactionOptions {
+    LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+        base_level_size_mb: 1,
+        periodic_compaction_seconds: None,
+        compaction_priority: CompactionPriority::OldestSstId,
+        level0_intra_compaction_trigger: Some(2),
+    }
+}
+
+#[test]
+fn test_intra_l0_compaction_task() {
+    let controller = LeveledCompactionController::new(leveled_options());
+    let mut state = LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: vec![5, 4, 3, 2],
+        levels: vec![(1, Vec::new()), (2, Vec::new()), (3, vec![1])],
+        sstables: Default::default(),
+    };
+    for id in 1..=6 {
+        state.sstables.insert(
+            id,
+            Arc::new(SsTable::create_meta_only(
+                id,
+                1024,
+                KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(b"a")),
+                KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(b"z")),
+            )),
+        );
+    }
+
+    // nothing is running on the base level
+    assert!(controller
+        .generate_intra_l0_compaction_task(&state, &HashSet::new())
+        .is_none());
+
+    // L0 -> L3 is running on the two oldest L0 SSTs
+    let compacting_ssts = HashSet::from([3, 2, 1]);
+    let task = controller
+        .generate_intra_l0_compaction_task(&state, &compacting_ssts)
+        .unwrap();
+    assert_eq!(task.upper_level, None);
+    assert_eq!(task.lower_level, 0);
+    assert_eq!(task.upper_level_sst_ids, vec![5, 4]);
+    assert!(controller
+        .generate_intra_l0_compaction_task(&state, &HashSet::from([4, 3, 2, 1]))
+        .is_none());
+
+    // a new SST is flushed while the task runs, the output stays between the newer and the older
+    // SSTs
+    state.l0_sstables.insert(0, 6);
+    let (new_state, files_to_remove) = controller.apply_compaction_result(&state, &task, &[7]);
+    assert_eq!(new_state.l0_sstables, vec![6, 7, 3, 2]);
+    assert_eq!(files_to_remove, vec![5, 4]);
+}
+
+#[test]
+fn test_intra_l0_compaction_when_base_level_busy() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(leveled_options()));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"0", b"v0").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"5", b"v5").unwrap();
+    storage.force_flush().unwrap();
+    let mut base_level_ssts = Vec::new();
+    for _ in 0..100 {
+        std::thread::sleep(Duration::from_millis(50));
+        let snapshot = storage.inner.state.read().clone();
+        if snapshot.l0_sstables.is_empty() {
+            base_level_ssts = snapshot.levels[2].1.clone();
+            break;
+        }
+    }
+    assert!(!base_level_ssts.is_empty());
+
+    // pretend the base level is being compacted, so that L0 can only be merged within itself
+    storage
+        .inner
+        .compacting_ssts
+        .lock()
+        .extend(base_level_ssts.iter().copied());
+    for i in 1..5 {
+        storage
+            .put(format!("{i}").as_bytes(), format!("v{i}").as_bytes())
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    let mut merged = false;
+    for _ in 0..100 {
+        std::thread::sleep(Duration::from_millis(50));
+        if storage.inner.state.read().l0_sstables.len() == 1 {
+            merged = true;
+            break;
+        }
+    }
+    assert!(merged, "intra-L0 compaction did not run");
+    assert_eq!(storage.inner.state.read().levels[2].1, base_level_ssts);
+    for i in 0..6 {
+        assert_eq!(
+            storage.get(format!("{i}").as_bytes()).unwrap(),
+            Some(Bytes::from(format!("v{i}")))
+        );
+    }
+
+    // the intra-L0 compaction is replayed from the manifest
+    for id in &base_level_ssts {
+        storage.inner.compacting_ssts.lock().remove(id);
+    }
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..6 {
+        assert_eq!(
+            storage.get(format!("{i}").as_bytes()).unwrap(),
+            Some(Bytes::from(format!("v{i}")))
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::{SeekableIterator, StorageIterator};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:04}", idx).into_bytes()
+}
+
+fn take<I: for<'a> StorageIterator<KeyType<'a> = &'a [u8]>>(
+    iter: &mut I,
+    n: usize,
+) -> Vec<(Bytes, Bytes)> {
+    let mut entries = Vec::new();
+    while iter.is_valid() && entries.len() < n {
+        entries.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+fn open_with_data(dir: &tempfile::TempDir) -> std::sync::Arc<MiniLsm> {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 128;
+    let storage = MiniLsm::open(dir, options).unwrap();
+    for round in 0..6 {
+        for idx in (round..400).step_by(round + 2) {
+            if (idx + round) % 7 == 0 {
+                storage.delete(&key_of(idx)).unwrap();
+            } else {
+                storage
+                    .put(&key_of(idx), format!("value_{}_{}", idx, round).as_bytes())
+                    .unwrap();
+            }
+        }
+        match round {
+            2 => storage.force_full_compaction().unwrap(),
+            4 => storage
+                .inner
+                .force_freeze_memtable(&storage.inner.state_lock.lock())
+                .unwrap(),
+            5 => {}
+            _ => storage.force_flush().unwrap(),
+        }
+    }
+    storage
+}
+
+#[test]
+fn test_seek_matches_new_scan() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_data(&dir);
+    let bounds = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(key_of(100)), Bound::Excluded(key_of(300))),
+        (Bound::Excluded(key_of(101)), Bound::Included(key_of(299))),
+    ];
+    // forward, backward, repeated, before the range and past the end
+    let seeks = [150, 20, 20, 101, 102, 299, 350, 0, 1000, 250, 3];
+    for (lower, upper) in &bounds {
+        let lower = lower.as_ref().map(|key| key.as_slice());
+        let upper = upper.as_ref().map(|key| key.as_slice());
+        let mut iter = storage.scan(lower, upper).unwrap();
+        for idx in seeks {
+            let key = key_of(idx);
+            iter.seek(&key).unwrap();
+            let seek_lower = match lower {
+                Bound::Included(start) if key.as_slice() < start => lower,
+                Bound::Excluded(start) if key.as_slice() <= start => lower,
+                _ => Bound::Included(key.as_slice()),
+            };
+            let mut expected = storage.scan(seek_lower, upper).unwrap();
+            assert_eq!(take(&mut iter, 20), take(&mut expected, 20));
+        }
+    }
+}
+
+#[test]
+fn test_seek_keeps_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_data(&dir);
+    let snapshot = storage.snapshot();
+    let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let expected = take(&mut iter, 10);
+
+    storage.put(&key_of(0), b"new").unwrap();
+    storage.delete(&expected[1].0).unwrap();
+    storage.force_flush().unwrap();
+    iter.seek(&key_of(0)).unwrap();
+    assert_eq!(take(&mut iter, 10), expected);
+}
+
+#[test]
+fn test_seek_in_txn() {
+    let dir = tempdir().unwrap();
+    let storage = open_with_data(&dir);
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(5), b"local");
+    txn.put(&key_of(6), b"local");
+    txn.delete(&key_of(7));
+    txn.delete(&key_of(8));
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    take(&mut iter, 50);
+    iter.seek(&key_of(5)).unwrap();
+    let entries = take(&mut iter, 3);
+    assert_eq!(entries[0], (Bytes::from(key_of(5)), Bytes::from("local")));
+    assert_eq!(entries[1], (Bytes::from(key_of(6)), Bytes::from("local")));
+    assert!(entries[2].0.as_ref() > key_of(8).as_slice());
+
+    let mut expected = txn
+        .scan(Bound::Included(&key_of(3)), Bound::Unbounded)
+        .unwrap();
+    iter.seek(&key_of(3)).unwrap();
+    assert_eq!(take(&mut iter, 20), take(&mut expected, 20));
+    txn.commit().unwrap();
+}
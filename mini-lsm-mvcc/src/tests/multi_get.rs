@@ -0,0 +1,116 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+#[test]
+fn test_multi_get_matches_get() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    // small blocks, so that the versions of a key are split across blocks
+    options.block_size = 64;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+
+    for round in 0..4 {
+        for idx in (round..200).step_by(3) {
+            if idx % 7 == round {
+                storage.delete(&key_of(idx)).unwrap();
+            } else {
+                storage
+                    .put(&key_of(idx), format!("value_{}_{}", idx, round).as_bytes())
+                    .unwrap();
+            }
+        }
+        storage.force_flush().unwrap();
+        if round == 1 {
+            storage.force_full_compaction().unwrap();
+        }
+    }
+    // one immutable memtable and the current memtable
+    for idx in (0..200).step_by(5) {
+        storage.put(&key_of(idx), b"imm").unwrap();
+    }
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    for idx in (0..200).step_by(11) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    {
+        let state = storage.inner.state.read();
+        assert!(!state.l0_sstables.is_empty());
+        assert!(state.levels.iter().any(|(_, files)| !files.is_empty()));
+        assert_eq!(state.imm_memtables.len(), 1);
+    }
+
+    // unsorted, with duplicates and keys missing from the storage
+    let keys = (0..250)
+        .rev()
+        .chain([3, 199, 3, 1000])
+        .map(key_of)
+        .collect::<Vec<_>>();
+    let keys = keys.iter().map(|key| key.as_slice()).collect::<Vec<_>>();
+    let values = storage.multi_get(&keys).unwrap();
+    assert_eq!(values.len(), keys.len());
+    for (key, value) in keys.iter().zip(values) {
+        assert_eq!(value, storage.get(key).unwrap());
+    }
+    assert!(storage.multi_get(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn test_multi_get_in_txn() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"c", b"1").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    let snapshot = storage.new_read_only_txn().unwrap();
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"c").unwrap();
+    storage.put(b"d", b"2").unwrap();
+    txn.put(b"b", b"3");
+    txn.delete(b"a");
+
+    let keys: [&[u8]; 5] = [b"d", b"c", b"b", b"a", b"e"];
+    assert_eq!(
+        txn.multi_get(&keys).unwrap(),
+        vec![
+            None,
+            Some(Bytes::from_static(b"1")),
+            Some(Bytes::from_static(b"3")),
+            None,
+            None
+        ]
+    );
+    assert_eq!(
+        snapshot.multi_get(&keys).unwrap(),
+        vec![
+            None,
+            Some(Bytes::from_static(b"1")),
+            Some(Bytes::from_static(b"1")),
+            Some(Bytes::from_static(b"1")),
+            None
+        ]
+    );
+    assert_eq!(
+        storage.multi_get(&keys).unwrap(),
+        vec![
+            Some(Bytes::from_static(b"2")),
+            None,
+            Some(Bytes::from_static(b"1")),
+            Some(Bytes::from_static(b"2")),
+            None
+        ]
+    );
+}
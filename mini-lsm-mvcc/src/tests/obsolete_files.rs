@@ -0,0 +1,50 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_obsolete_files_deleted_when_unreferenced() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.force_flush().unwrap();
+    let old_sst_ids = storage.inner.state.read().l0_sstables.clone();
+    assert_eq!(old_sst_ids.len(), 2);
+    assert!(storage.obsolete_files().is_empty());
+
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    storage.force_full_compaction().unwrap();
+
+    // the iterator still reads the compacted SSTs
+    let mut sorted_old_sst_ids = old_sst_ids.clone();
+    sorted_old_sst_ids.sort();
+    assert_eq!(storage.obsolete_files(), sorted_old_sst_ids);
+    for id in &old_sst_ids {
+        assert!(storage.inner.path_of_sst(*id).exists());
+    }
+    let live_versions = storage.live_versions();
+    assert!(live_versions.len() >= 2);
+    let current = live_versions.iter().find(|v| v.is_current).unwrap();
+    assert!(old_sst_ids.iter().all(|id| !current.sst_ids.contains(id)));
+    assert!(live_versions
+        .iter()
+        .any(|v| !v.is_current && old_sst_ids.iter().all(|id| v.sst_ids.contains(id))));
+
+    assert_eq!(iter.key(), b"a");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"b");
+    drop(iter);
+
+    assert!(storage.obsolete_files().is_empty());
+    for id in &old_sst_ids {
+        assert!(!storage.inner.path_of_sst(*id).exists());
+    }
+    let live_versions = storage.live_versions();
+    assert_eq!(live_versions.len(), 1);
+    assert!(live_versions[0].is_current);
+}
@@ -0,0 +1,67 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, OrphanFileAction, OrphanFilesReport};
+
+#[test]
+fn test_orphan_files_cleanup_on_open() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let orphan_sst = dir.path().join("00099.sst");
+    let orphan_wal = dir.path().join("00098.wal");
+    let create_orphan_files = || {
+        std::fs::write(&orphan_sst, b"partial sst").unwrap();
+        std::fs::write(&orphan_wal, b"stale wal").unwrap();
+    };
+    let expected_report = OrphanFilesReport {
+        ssts: vec![orphan_sst.clone()],
+        wals: vec![orphan_wal.clone()],
+    };
+    let check_data = |storage: &MiniLsm| {
+        assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+        assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from_static(b"2")));
+    };
+
+    // dry run only reports the files
+    create_orphan_files();
+    options.orphan_file_action = OrphanFileAction::DryRun;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.orphan_files(), &expected_report);
+    assert!(orphan_sst.exists() && orphan_wal.exists());
+    check_data(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    // quarantine moves the files away
+    options.orphan_file_action = OrphanFileAction::Quarantine;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.orphan_files(), &expected_report);
+    assert!(!orphan_sst.exists() && !orphan_wal.exists());
+    assert!(dir.path().join("quarantine").join("00099.sst").exists());
+    assert!(dir.path().join("quarantine").join("00098.wal").exists());
+    check_data(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    // delete removes the files
+    create_orphan_files();
+    options.orphan_file_action = OrphanFileAction::Delete;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.orphan_files(), &expected_report);
+    assert!(!orphan_sst.exists() && !orphan_wal.exists());
+    check_data(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.orphan_files(), &OrphanFilesReport::default());
+    check_data(&storage);
+}
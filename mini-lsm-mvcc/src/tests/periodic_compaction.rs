@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, CompactionPriority, LeveledCompactionOptions},
+    iterators::StorageIterator,
+    lsm_storage::{CompactionFilter, LsmStorageOptions, MiniLsm},
+};
+
+use super::harness::construct_merge_iterator_over_storage;
+
+#[test]
+fn test_periodic_compaction_runs_filters() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(
+        &dir,
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+            LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 4,
+                level_size_multiplier: 2,
+                base_level_size_mb: 1,
+                max_levels: 3,
+                periodic_compaction_seconds: Some(1),
+                compaction_priority: CompactionPriority::OldestSstId,
+                level0_intra_compaction_trigger: None,
+            },
+        )),
+    )
+    .unwrap();
+    storage.put(b"table1_a", b"1").unwrap();
+    storage.put(b"table2_a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.add_compaction_filter(CompactionFilter::Prefix(Bytes::from("table2_")));
+
+    // no size-based trigger fires for a single L0 SST, only the periodic one does
+    let mut filtered = false;
+    for _ in 0..100 {
+        std::thread::sleep(Duration::from_millis(100));
+        let mut iter = construct_merge_iterator_over_storage(&storage.inner.state.read());
+        let mut keys = Vec::new();
+        while iter.is_valid() {
+            keys.push(Bytes::copy_from_slice(iter.key().key_ref()));
+            iter.next().unwrap();
+        }
+        if keys == vec![Bytes::from("table1_a")] {
+            filtered = true;
+            break;
+        }
+    }
+    assert!(
+        filtered,
+        "periodic compaction did not run the compaction filter"
+    );
+    assert!(storage.inner.state.read().l0_sstables.is_empty());
+    assert_eq!(
+        storage.get(b"table1_a").unwrap(),
+        Some(Bytes::from_static(b"1"))
+    );
+    assert_eq!(storage.get(b"table2_a").unwrap(), None);
+}
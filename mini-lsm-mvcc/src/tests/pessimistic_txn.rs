@@ -0,0 +1,142 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::mvcc::lock_manager::LockError;
+
+fn open_storage(lock_wait_timeout: Duration) -> (tempfile::TempDir, Arc<MiniLsm>) {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    options.lock_wait_timeout = lock_wait_timeout;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    (dir, storage)
+}
+
+#[test]
+fn test_pessimistic_txn_hot_row() {
+    let (_dir, storage) = open_storage(Duration::from_secs(10));
+    storage.put(b"counter", b"0").unwrap();
+    let threads = (0..4)
+        .map(|_| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for _ in 0..10 {
+                    let txn = storage.new_pessimistic_txn().unwrap();
+                    let value = txn.get_for_update(b"counter").unwrap().unwrap();
+                    let value = std::str::from_utf8(&value).unwrap().parse::<u64>().unwrap();
+                    txn.put(b"counter", (value + 1).to_string().as_bytes())
+                        .unwrap();
+                    txn.commit().unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(
+        storage.get(b"counter").unwrap(),
+        Some(Bytes::from_static(b"40"))
+    );
+}
+
+#[test]
+fn test_pessimistic_txn_lock_wait_timeout() {
+    let (_dir, storage) = open_storage(Duration::from_millis(50));
+    let txn1 = storage.new_pessimistic_txn().unwrap();
+    let txn2 = storage.new_pessimistic_txn().unwrap();
+    txn1.put(b"key", b"1").unwrap();
+    let err = txn2.put(b"key", b"2").unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<LockError>(),
+        Some(&LockError::Timeout(Bytes::from_static(b"key")))
+    );
+    // the lock is released when the txn is dropped
+    drop(txn1);
+    txn2.put(b"key", b"2").unwrap();
+    txn2.commit().unwrap();
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from_static(b"2")));
+}
+
+#[test]
+fn test_pessimistic_txn_deadlock() {
+    let (_dir, storage) = open_storage(Duration::from_secs(10));
+    let txn1 = storage.new_pessimistic_txn().unwrap();
+    let txn2 = storage.new_pessimistic_txn().unwrap();
+    txn1.put(b"a", b"1").unwrap();
+    txn2.put(b"b", b"2").unwrap();
+    let waiter = {
+        let txn1 = txn1.clone();
+        std::thread::spawn(move || txn1.put(b"b", b"1"))
+    };
+    std::thread::sleep(Duration::from_millis(100));
+    let err = txn2.get_for_update(b"a").unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<LockError>(),
+        Some(&LockError::Deadlock(Bytes::from_static(b"a")))
+    );
+    drop(txn2);
+    waiter.join().unwrap().unwrap();
+    txn1.commit().unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from_static(b"1")));
+}
+
+#[test]
+fn test_pessimistic_txn_with_optimistic_txn() {
+    let (_dir, storage) = open_storage(Duration::from_secs(10));
+    storage.put(b"key", b"0").unwrap();
+    let pessimistic_txn = storage.new_pessimistic_txn().unwrap();
+    assert_eq!(
+        pessimistic_txn.get_for_update(b"key").unwrap(),
+        Some(Bytes::from_static(b"0"))
+    );
+
+    // optimistic txns cannot write a locked key, and can retry later
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"key", b"1");
+    assert_eq!(
+        txn.commit().unwrap_err().downcast_ref::<LockError>(),
+        Some(&LockError::Locked(Bytes::from_static(b"key")))
+    );
+
+    // and they see the writes of pessimistic txns as conflicts
+    let txn = storage.new_txn().unwrap();
+    txn.get(b"key").unwrap();
+    txn.put(b"other", b"1");
+    pessimistic_txn.put(b"key", b"2").unwrap();
+    pessimistic_txn.commit().unwrap();
+    assert!(txn.commit().is_err());
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from_static(b"2")));
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"key", b"3");
+    txn.commit().unwrap();
+}
+
+#[test]
+fn test_pessimistic_txn_with_plain_writes() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"key", b"0").unwrap();
+    let txn = storage.new_pessimistic_txn().unwrap();
+    txn.put(b"key", b"1").unwrap();
+
+    // the writes outside of transactions cannot overwrite a locked key either
+    assert_eq!(
+        storage
+            .put(b"key", b"2")
+            .unwrap_err()
+            .downcast_ref::<LockError>(),
+        Some(&LockError::Locked(Bytes::from_static(b"key")))
+    );
+    assert!(storage.delete(b"key").is_err());
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from_static(b"1")));
+    storage.put(b"key", b"2").unwrap();
+}
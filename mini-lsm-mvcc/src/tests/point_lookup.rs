@@ -0,0 +1,60 @@
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_early_exit_get_matches_merged_get() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let key_of = |idx: usize| format!("key_{:04}", idx).into_bytes();
+
+    let mut snapshots = Vec::new();
+    for round in 0..8 {
+        for idx in (round % 3..300).step_by(round + 1) {
+            if (idx + round) % 5 == 0 {
+                storage.delete(&key_of(idx)).unwrap();
+            } else {
+                storage
+                    .put(&key_of(idx), format!("value_{}_{}", idx, round).as_bytes())
+                    .unwrap();
+            }
+        }
+        snapshots.push(storage.snapshot());
+        if round == 3 {
+            storage.force_full_compaction().unwrap();
+        } else if round % 2 == 0 {
+            storage.force_flush().unwrap();
+        } else {
+            storage
+                .inner
+                .force_freeze_memtable(&storage.inner.state_lock.lock())
+                .unwrap();
+        }
+    }
+
+    {
+        let state = storage.inner.state.read();
+        assert!(!state.imm_memtables.is_empty());
+        assert!(!state.l0_sstables.is_empty());
+        assert!(state.levels.iter().any(|(_, files)| !files.is_empty()));
+    }
+
+    for idx in 0..310 {
+        let key = key_of(idx);
+        assert_eq!(
+            storage.get(&key).unwrap(),
+            storage.get_merged(&key).unwrap()
+        );
+        for snapshot in &snapshots {
+            assert_eq!(
+                storage.inner.get_with_ts(&key, snapshot.read_ts()).unwrap(),
+                storage
+                    .inner
+                    .get_with_ts_merged(&key, snapshot.read_ts())
+                    .unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, PrefixExtractor};
+
+fn collect<I: for<'a> StorageIterator<KeyType<'a> = &'a [u8]>>(mut iter: I) -> Vec<Bytes> {
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(Bytes::copy_from_slice(iter.key()));
+        iter.next().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_prefix_scan_skips_ssts() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.prefix_extractor = Some(PrefixExtractor::Delimiter(b'/'));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // both SSTs span the prefix `b/`, but only the second one has it
+    storage.put(b"a/1", b"1").unwrap();
+    storage.put(b"c/1", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b/1", b"1").unwrap();
+    storage.put(b"b/2", b"1").unwrap();
+    storage.put(b"d/1", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b/3", b"1").unwrap();
+    storage.put(b"b0", b"1").unwrap();
+
+    let (without_prefix, with_prefix) = {
+        let state = storage.inner.state.read();
+        let l0 = &state.l0_sstables;
+        (
+            state.sstables[&l0[1]].clone(),
+            state.sstables[&l0[0]].clone(),
+        )
+    };
+    let extractor = PrefixExtractor::Delimiter(b'/');
+    assert!(!without_prefix.may_contain_prefix(&extractor, b"b/"));
+    assert!(with_prefix.may_contain_prefix(&extractor, b"b/"));
+    assert!(without_prefix.may_contain_prefix(&extractor, b"c/"));
+
+    let iter = storage.prefix_scan(b"b/").unwrap();
+    // the iterator over the first SST is not created
+    let wider_iter = storage
+        .scan(Bound::Included(b"b/"), Bound::Excluded(b"b1"))
+        .unwrap();
+    assert!(iter.num_active_iterators() < wider_iter.num_active_iterators());
+    assert_eq!(
+        collect(iter),
+        vec![
+            Bytes::from_static(b"b/1"),
+            Bytes::from_static(b"b/2"),
+            Bytes::from_static(b"b/3")
+        ]
+    );
+    assert_eq!(collect(storage.prefix_scan(b"b/2").unwrap()).len(), 1);
+    assert_eq!(collect(storage.prefix_scan(b"b").unwrap()).len(), 4);
+    assert!(collect(storage.prefix_scan(b"e/").unwrap()).is_empty());
+
+    // the SSTs built with another extractor are not skipped
+    storage.close().unwrap();
+    drop(storage);
+    options.prefix_extractor = Some(PrefixExtractor::FixedLength(2));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(collect(storage.prefix_scan(b"b/").unwrap()).len(), 3);
+    let state = storage.inner.state.read();
+    for table in state.sstables.values() {
+        assert!(table.may_contain_prefix(&PrefixExtractor::FixedLength(2), b"x/"));
+    }
+}
+
+#[test]
+fn test_prefix_scan_stops_at_prefix_end() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.prefix_extractor = Some(PrefixExtractor::FixedLength(2));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for key in [
+        &b"a\xfe"[..],
+        b"a\xff",
+        b"a\xff\x01",
+        b"a\xff\xff",
+        b"b",
+        b"b\x00",
+        b"\xff\xff",
+        b"\xff\xff\x01",
+    ] {
+        storage.put(key, b"1").unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.delete(b"a\xff\x01").unwrap();
+
+    assert_eq!(
+        collect(storage.prefix_scan(b"a\xff").unwrap()),
+        vec![
+            Bytes::from_static(b"a\xff"),
+            Bytes::from_static(b"a\xff\xff")
+        ]
+    );
+    assert_eq!(collect(storage.prefix_scan(b"\xff\xff").unwrap()).len(), 2);
+    assert_eq!(collect(storage.prefix_scan(b"b").unwrap()).len(), 2);
+    assert_eq!(collect(storage.prefix_scan(b"").unwrap()).len(), 7);
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a\xff\x02", b"1");
+    txn.delete(b"a\xff");
+    assert_eq!(
+        collect(txn.prefix_scan(b"a\xff").unwrap()),
+        vec![
+            Bytes::from_static(b"a\xff\x02"),
+            Bytes::from_static(b"a\xff\xff")
+        ]
+    );
+}
@@ -0,0 +1,63 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_read_only_txn() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+
+    let txn = storage.new_read_only_txn().unwrap();
+    assert!(txn.txn.key_sets.is_none());
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(storage.inner.mvcc().watermark(), txn.read_ts());
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"a"[..], &b"1"[..]));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"b"[..], &b"1"[..]));
+    drop(iter);
+    // the keys read are written by another txn, but there is nothing to validate
+    storage.put(b"b", b"2").unwrap();
+    txn.commit().unwrap();
+    drop(txn);
+    assert_eq!(
+        storage.inner.mvcc().watermark(),
+        storage.inner.mvcc().latest_commit_ts()
+    );
+}
+
+#[test]
+fn test_read_only_flag_of_new_txn() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+
+    let txn = storage
+        .inner
+        .mvcc()
+        .new_txn(storage.inner.clone(), true, true, None);
+    assert!(txn.key_sets.is_none() && txn.read_ranges.is_none());
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    storage.put(b"a", b"2").unwrap();
+    txn.commit().unwrap();
+
+    let txn = storage
+        .inner
+        .mvcc()
+        .new_txn(storage.inner.clone(), true, true, None);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| txn.put(b"a", b"3")));
+    assert!(result.is_err());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"2")));
+}
@@ -0,0 +1,127 @@
+use std::ops::Bound;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::{SeekableIterator, StorageIterator};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+fn collect<I: for<'a> StorageIterator<KeyType<'a> = &'a [u8]>>(
+    iter: &mut I,
+) -> Vec<(Bytes, Bytes)> {
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_refresh_releases_compacted_ssts() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), b"1").unwrap();
+    }
+    storage.force_flush().unwrap();
+    for idx in (0..100).step_by(3) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    for idx in (0..100).step_by(5) {
+        storage.put(&key_of(idx), b"2").unwrap();
+    }
+    let expected = collect(&mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap());
+
+    let mut refreshing = storage
+        .scan_with_refresh(Bound::Unbounded, Bound::Unbounded, Duration::ZERO)
+        .unwrap();
+    let mut pinning = storage
+        .scan_with_refresh(
+            Bound::Unbounded,
+            Bound::Unbounded,
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+    let old_sst_ids = storage.inner.state.read().l0_sstables.clone();
+    let mut entries = Vec::new();
+    for _ in 0..10 {
+        entries.push((
+            Bytes::copy_from_slice(refreshing.key()),
+            Bytes::copy_from_slice(refreshing.value()),
+        ));
+        refreshing.next().unwrap();
+    }
+
+    // writes after the read ts are not visible, even to the rebuilt iterators
+    for idx in 0..100 {
+        storage.put(&key_of(idx), b"3").unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert!(!storage.obsolete_files().is_empty());
+
+    entries.extend(collect(&mut refreshing));
+    assert_eq!(entries, expected);
+    drop(refreshing);
+    // only the iterator which has not been refreshed keeps the old SSTs
+    let mut obsolete_files = storage.obsolete_files();
+    obsolete_files.sort();
+    let mut old_sst_ids = old_sst_ids;
+    old_sst_ids.sort();
+    assert_eq!(obsolete_files, old_sst_ids);
+    assert_eq!(collect(&mut pinning), expected);
+    drop(pinning);
+    assert!(storage.obsolete_files().is_empty());
+}
+
+#[test]
+fn test_refreshing_iterator_in_txn() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..50 {
+        storage.put(&key_of(idx), b"1").unwrap();
+    }
+    storage.force_flush().unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(10), b"local");
+    txn.delete(&key_of(11));
+    txn.put(&key_of(100), b"local");
+    let expected = collect(
+        &mut txn
+            .scan(Bound::Excluded(&key_of(5)), Bound::Included(&key_of(100)))
+            .unwrap(),
+    );
+
+    let mut iter = txn
+        .scan_with_refresh(
+            Bound::Excluded(&key_of(5)),
+            Bound::Included(&key_of(100)),
+            Duration::ZERO,
+        )
+        .unwrap();
+    storage.put(&key_of(12), b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(collect(&mut iter), expected);
+
+    // seeking back before the keys already returned, and before the range
+    iter.seek(&key_of(20)).unwrap();
+    assert_eq!(iter.key(), key_of(20));
+    iter.seek(&key_of(0)).unwrap();
+    assert_eq!(collect(&mut iter), expected);
+    txn.commit().unwrap();
+}
@@ -0,0 +1,61 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_rollback_to_savepoint() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"c", b"0").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    assert!(txn.rollback_to_savepoint().is_err());
+    txn.put(b"a", b"1");
+    txn.set_savepoint();
+    txn.put(b"a", b"2");
+    txn.put(b"b", b"2");
+    txn.set_savepoint();
+    txn.delete(b"c");
+    assert_eq!(txn.get(b"c").unwrap(), None);
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(b"c").unwrap(), Some(Bytes::from_static(b"0")));
+    assert_eq!(txn.get(b"b").unwrap(), Some(Bytes::from_static(b"2")));
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    assert_eq!(txn.get(b"b").unwrap(), None);
+
+    // the rolled back writes are not in the write set, so they do not conflict
+    let other_txn = storage.new_txn().unwrap();
+    other_txn.get(b"b").unwrap();
+    other_txn.get(b"c").unwrap();
+    other_txn.put(b"d", b"1");
+    txn.commit().unwrap();
+    other_txn.commit().unwrap();
+
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from_static(b"0")));
+}
+
+#[test]
+fn test_rollback() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(storage.inner.mvcc().watermark(), txn.read_ts);
+    txn.put(b"b", b"1");
+    txn.rollback();
+    // the watermark reader is released without dropping the txn
+    assert_eq!(
+        storage.inner.mvcc().watermark(),
+        storage.inner.mvcc().latest_commit_ts()
+    );
+    drop(txn);
+    assert_eq!(storage.get(b"b").unwrap(), None);
+}
@@ -0,0 +1,45 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn check_scan(storage: &MiniLsm, lower: Bound<&[u8]>, expected: &[(&[u8], &[u8])]) {
+    let mut iter = storage.scan(lower, Bound::Unbounded).unwrap();
+    for (key, value) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), *key);
+        assert_eq!(iter.value(), *value);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_scan_excluded_lower_bound() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"a", b"2").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    // none of the versions of the excluded key is returned, wherever they are
+    let expected: [(&[u8], &[u8]); 1] = [(b"b", b"1")];
+    check_scan(&storage, Bound::Excluded(b"a"), &expected);
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    check_scan(&storage, Bound::Excluded(b"a"), &expected);
+    storage.force_flush().unwrap();
+    check_scan(&storage, Bound::Excluded(b"a"), &expected);
+    storage.put(b"a", b"3").unwrap();
+    check_scan(&storage, Bound::Excluded(b"a"), &expected);
+    check_scan(
+        &storage,
+        Bound::Included(b"a"),
+        &[(b"a", b"3"), (b"b", b"1")],
+    );
+}
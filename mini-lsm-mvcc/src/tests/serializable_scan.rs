@@ -0,0 +1,46 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_serializable_scan_detects_phantoms() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"c", b"1").unwrap();
+
+    // a key inserted into the scanned range fails the commit
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.scan(Bound::Included(b"a"), Bound::Excluded(b"c"))
+        .unwrap();
+    txn1.put(b"count", b"1");
+    txn2.put(b"b", b"1");
+    txn2.commit().unwrap();
+    assert!(txn1.commit().is_err());
+
+    // keys written outside of the scanned range do not conflict
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.scan(Bound::Excluded(b"a"), Bound::Included(b"b"))
+        .unwrap();
+    txn1.put(b"count", b"2");
+    txn2.put(b"a", b"2");
+    txn2.put(b"c", b"2");
+    txn2.commit().unwrap();
+    txn1.commit().unwrap();
+
+    // a deletion in the scanned range also conflicts
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.scan(Bound::Unbounded, Bound::Excluded(b"b")).unwrap();
+    txn1.put(b"count", b"3");
+    txn2.delete(b"a");
+    txn2.commit().unwrap();
+    assert!(txn1.commit().is_err());
+}
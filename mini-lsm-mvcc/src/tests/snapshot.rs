@@ -0,0 +1,86 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_snapshot_reads() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let snapshot = storage.snapshot();
+    assert_eq!(storage.inner.mvcc().watermark(), snapshot.read_ts());
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"2").unwrap();
+    storage.force_flush().unwrap();
+    let latest_ts = storage.inner.mvcc().latest_commit_ts();
+
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    assert_eq!(snapshot.get(b"b").unwrap(), Some(Bytes::from_static(b"1")));
+    assert_eq!(snapshot.get(b"c").unwrap(), None);
+    let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"a"[..], &b"1"[..]));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"b"[..], &b"1"[..]));
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    drop(iter);
+
+    // any ts from the watermark to the latest commit ts can be read
+    let ts = snapshot.read_ts() + 1;
+    assert_eq!(
+        storage.get_at(b"a", ts).unwrap(),
+        Some(Bytes::from_static(b"2"))
+    );
+    assert_eq!(
+        storage.get_at(b"b", ts).unwrap(),
+        Some(Bytes::from_static(b"1"))
+    );
+    let mut iter = storage
+        .scan_at(Bound::Unbounded, Bound::Unbounded, latest_ts)
+        .unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"a"[..], &b"2"[..]));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"c"[..], &b"2"[..]));
+    drop(iter);
+    assert!(storage.get_at(b"a", snapshot.read_ts() - 1).is_err());
+    assert!(storage.get_at(b"a", latest_ts + 1).is_err());
+    assert!(storage
+        .scan_at(Bound::Unbounded, Bound::Unbounded, latest_ts + 1)
+        .is_err());
+
+    // the watermark is released when the snapshot and its iterators are dropped
+    let read_ts = snapshot.read_ts();
+    let iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    drop(snapshot);
+    assert_eq!(storage.inner.mvcc().watermark(), read_ts);
+    drop(iter);
+    assert_eq!(storage.inner.mvcc().watermark(), latest_ts);
+    assert!(storage.get_at(b"a", read_ts).is_err());
+}
+
+#[test]
+fn test_snapshot_at_future_ts() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let latest_ts = storage.inner.mvcc().latest_commit_ts();
+    // a snapshot at a future ts would see the next commits as they land
+    let future_ts = latest_ts + 1;
+    assert!(storage
+        .inner
+        .mvcc()
+        .new_snapshot_at(storage.inner.clone(), future_ts)
+        .is_err());
+    assert!(storage.get_at(b"a", future_ts).is_err());
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(
+        storage.get_at(b"a", future_ts).unwrap(),
+        Some(Bytes::from_static(b"2"))
+    );
+}
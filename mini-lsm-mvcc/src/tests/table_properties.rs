@@ -0,0 +1,73 @@
+use bytes::{BufMut, Bytes};
+
+use crate::key::KeyBytes;
+use crate::lsm_storage::PrefixExtractor;
+use crate::table::filter::FilterType;
+use crate::table::{BlockMeta, TableProperties};
+
+/// Encodes the block meta with the properties in a layout without the version byte.
+fn encode_legacy_block_meta(block_meta: &[BlockMeta], properties: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.put_u32(block_meta.len() as u32);
+    for meta in block_meta {
+        buf.put_u32(meta.offset as u32);
+        buf.put_u16(meta.first_key.key_len() as u16);
+        buf.put_slice(meta.first_key.key_ref());
+        buf.put_u64(meta.first_key.ts());
+        buf.put_u16(meta.last_key.key_len() as u16);
+        buf.put_slice(meta.last_key.key_ref());
+        buf.put_u64(meta.last_key.ts());
+    }
+    buf.put_slice(properties);
+    let checksum = crc32fast::hash(&buf[4..]);
+    buf.put_u32(checksum);
+    buf
+}
+
+#[test]
+fn test_decode_table_properties_layouts() {
+    let block_meta = vec![BlockMeta {
+        offset: 0,
+        first_key: KeyBytes::from_bytes_with_ts(Bytes::from_static(b"a"), 5),
+        last_key: KeyBytes::from_bytes_with_ts(Bytes::from_static(b"b"), 3),
+    }];
+    let properties = TableProperties {
+        min_ts: 3,
+        max_ts: 5,
+        create_time: 100,
+        prefix_extractor: Some(PrefixExtractor::FixedLength(2)),
+        filter_type: FilterType::Ribbon,
+    };
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&block_meta, &properties, &mut buf);
+    assert_eq!(
+        BlockMeta::decode_block_meta(&buf).unwrap(),
+        (block_meta.clone(), properties.clone())
+    );
+
+    // the layout before the version has only `max_ts`
+    let buf_without_version = encode_legacy_block_meta(&block_meta, &5u64.to_be_bytes());
+    assert_eq!(
+        BlockMeta::decode_block_meta(&buf_without_version).unwrap(),
+        (
+            block_meta.clone(),
+            TableProperties {
+                max_ts: 5,
+                ..Default::default()
+            }
+        )
+    );
+    // and any other size without the version is rejected
+    let buf_without_version = encode_legacy_block_meta(
+        &block_meta,
+        &[5u64.to_be_bytes(), 100u64.to_be_bytes()].concat(),
+    );
+    assert!(BlockMeta::decode_block_meta(&buf_without_version).is_err());
+
+    // an unknown version is rejected, which is before the 34 bytes of fields and the checksum
+    let len = buf.len();
+    buf[len - 4 - 34 - 1] = 2;
+    let checksum = crc32fast::hash(&buf[4..len - 4]);
+    buf[len - 4..].copy_from_slice(&checksum.to_be_bytes());
+    assert!(BlockMeta::decode_block_meta(&buf).is_err());
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::{
+    compact::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask},
+    key::KeyBytes,
+    lsm_storage::LsmStorageState,
+    mem_table::MemTable,
+    table::SsTable,
+};
+
+fn add_sst(
+    state: &mut LsmStorageState,
+    id: usize,
+    size: u64,
+    first_key: &'static str,
+    last_key: &'static str,
+) {
+    state.sstables.insert(
+        id,
+        Arc::new(SsTable::create_meta_only(
+            id,
+            size,
+            KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(first_key.as_bytes())),
+            KeyBytes::for_testing_from_bytes_no_ts(Bytes::from_static(last_key.as_bytes())),
+        )),
+    );
+}
+
+fn new_state() -> LsmStorageState {
+    LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: Vec::new(),
+        levels: Vec::new(),
+        sstables: Default::default(),
+    }
+}
+
+#[test]
+fn test_tiered_compaction_by_bytes() {
+    let controller = TieredCompactionController::new(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+        partial_bottom_tier_compaction: false,
+    });
+    // the upper tiers have more files than the bottom tier, but much fewer bytes, so only the
+    // upper tiers are merged to reduce sorted runs
+    let mut state = new_state();
+    state.levels = vec![(5, vec![5]), (4, vec![2, 3, 4]), (1, vec![1])];
+    add_sst(&mut state, 1, 1 << 30, "a", "z");
+    add_sst(&mut state, 2, 1 << 20, "a", "b");
+    add_sst(&mut state, 3, 1 << 20, "c", "d");
+    add_sst(&mut state, 4, 1 << 20, "e", "f");
+    add_sst(&mut state, 5, 1 << 20, "a", "z");
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, state.levels[..2]);
+    assert!(!task.bottom_tier_included);
+
+    // once the upper tiers outgrow the bottom tier, all tiers are compacted
+    add_sst(&mut state, 6, 4 << 30, "a", "z");
+    state.levels[0].1.push(6);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, state.levels);
+    assert!(task.bottom_tier_included);
+    assert!(!task.partial_bottom_tier);
+}
+
+#[test]
+fn test_tiered_partial_bottom_tier_compaction() {
+    let controller = TieredCompactionController::new(TieredCompactionOptions {
+        num_tiers: 2,
+        max_size_amplification_percent: 1,
+        size_ratio: 1,
+        min_merge_width: 2,
+        partial_bottom_tier_compaction: true,
+    });
+    let mut state = new_state();
+    state.levels = vec![(5, vec![5]), (1, vec![1, 2, 3, 4])];
+    add_sst(&mut state, 1, 1 << 20, "a", "b");
+    add_sst(&mut state, 2, 1 << 20, "c", "d");
+    add_sst(&mut state, 3, 1 << 20, "e", "f");
+    add_sst(&mut state, 4, 1 << 20, "g", "h");
+    add_sst(&mut state, 5, 1 << 20, "d", "e");
+
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, vec![(5, vec![5]), (1, vec![2, 3])]);
+    assert!(task.bottom_tier_included);
+    assert!(task.partial_bottom_tier);
+
+    add_sst(&mut state, 6, 1 << 20, "c", "d");
+    add_sst(&mut state, 7, 1 << 20, "e", "f");
+    let (new_state, files_to_remove) = controller.apply_compaction_result(&state, &task, &[6, 7]);
+    assert_eq!(new_state.levels, vec![(1, vec![1, 6, 7, 4])]);
+    assert_eq!(files_to_remove, vec![5, 2, 3]);
+
+    // the bottom tier is left untouched if none of its SSTs overlap with the upper tiers
+    let mut state = new_state;
+    state.levels.insert(0, (8, vec![8]));
+    add_sst(&mut state, 8, 1 << 20, "fa", "fb");
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.tiers, vec![(8, vec![8]), (1, vec![])]);
+    assert!(task.partial_bottom_tier);
+    add_sst(&mut state, 9, 1 << 20, "fa", "fb");
+    let (new_state, files_to_remove) = controller.apply_compaction_result(&state, &task, &[9]);
+    assert_eq!(new_state.levels, vec![(1, vec![1, 6, 7, 9, 4])]);
+    assert_eq!(files_to_remove, vec![8]);
+
+    // tasks recorded in the manifest before partial compaction was added are full compactions
+    let task = serde_json::from_str::<TieredCompactionTask>(
+        r#"{"tiers":[[5,[5]],[1,[1,2,3,4]]],"bottom_tier_included":true}"#,
+    )
+    .unwrap();
+    assert!(!task.partial_bottom_tier);
+}
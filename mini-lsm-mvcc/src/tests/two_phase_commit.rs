@@ -0,0 +1,323 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::mvcc::txn::TxnConflictError;
+
+#[test]
+fn test_prepare_and_commit() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a", b"2");
+    txn.delete(b"b");
+    let id = txn.prepare().unwrap();
+    drop(txn);
+    assert_eq!(storage.prepared_txns(), vec![id]);
+    // the writes are not visible yet, and no other txn can write the keys
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    assert!(storage.put(b"a", b"3").is_err());
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"b", b"3");
+    assert!(txn.commit().is_err());
+
+    storage.commit_prepared(id).unwrap();
+    assert!(storage.prepared_txns().is_empty());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"2")));
+    assert!(storage.commit_prepared(id).is_err());
+    storage.put(b"a", b"3").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"c", b"1");
+    let id = txn.prepare().unwrap();
+    storage.rollback_prepared(id).unwrap();
+    assert!(storage.prepared_txns().is_empty());
+    assert_eq!(storage.get(b"c").unwrap(), None);
+    storage.put(b"c", b"2").unwrap();
+}
+
+#[test]
+fn test_prepare_conflict() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.get(b"a").unwrap();
+    txn1.put(b"b", b"1");
+    txn2.put(b"a", b"2");
+    txn2.commit().unwrap();
+    let err = txn1.prepare().unwrap_err();
+    assert!(err.downcast_ref::<TxnConflictError>().is_some());
+    assert!(storage.prepared_txns().is_empty());
+    // the keys locked by the failed prepare are released
+    storage.put(b"b", b"2").unwrap();
+}
+
+#[test]
+fn test_prepared_txns_recovery() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a", b"2");
+    txn.put(b"b", b"2");
+    let id1 = txn.prepare().unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"c", b"2");
+    let id2 = txn.prepare().unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"d", b"2");
+    let id3 = txn.prepare().unwrap();
+    storage.rollback_prepared(id3).unwrap();
+    // the WAL with the prepared txns is deleted after the flush
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.prepared_txns(), vec![id1, id2]);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    // the keys are locked again
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"b", b"3");
+    assert!(txn.commit().is_err());
+    storage.commit_prepared(id1).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"2")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from_static(b"2")));
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.prepared_txns(), vec![id2]);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"2")));
+    storage.rollback_prepared(id2).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.prepared_txns().is_empty());
+    assert_eq!(storage.get(b"c").unwrap(), None);
+    // the ids are never reused
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"c", b"3");
+    assert!(txn.prepare().unwrap() > id2);
+}
+
+#[test]
+fn test_crash_between_commit_record_and_writes() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a", b"2");
+    txn.delete(b"b");
+    let id1 = txn.prepare().unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"c", b"2");
+    let id2 = txn.prepare().unwrap();
+
+    // the commits are logged, and the process stops before the writes are applied
+    let commit_ts1 = storage.inner.mvcc().latest_commit_ts() + 1;
+    storage.inner.log_prepared_commit(id1, commit_ts1).unwrap();
+    assert!(storage.rollback_prepared(id1).is_err());
+    let commit_ts2 = commit_ts1 + 1;
+    storage.inner.log_prepared_commit(id2, commit_ts2).unwrap();
+    // the commit decision is logged again into the new WAL
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.prepared_txns().is_empty());
+    assert!(storage.rollback_prepared(id1).is_err());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"2")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from_static(b"2")));
+    assert!(storage.inner.mvcc().latest_commit_ts() >= commit_ts2);
+    // the keys are not locked anymore
+    storage.put(b"a", b"3").unwrap();
+    storage.put(b"c", b"3").unwrap();
+}
+
+#[test]
+fn test_concurrent_resolve_prepared() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+
+    // a txn being committed cannot be rolled back
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a", b"1");
+    let id = txn.prepare().unwrap();
+    storage
+        .inner
+        .mvcc()
+        .prepared_txns
+        .lock()
+        .get_mut(&id)
+        .unwrap()
+        .committing = true;
+    assert!(storage.rollback_prepared(id).is_err());
+    assert_eq!(storage.prepared_txns(), vec![id]);
+    storage
+        .inner
+        .mvcc()
+        .prepared_txns
+        .lock()
+        .get_mut(&id)
+        .unwrap()
+        .committing = false;
+    storage.commit_prepared(id).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+
+    // only one of the concurrent resolutions succeeds, the others fail without panicking
+    for round in 0..20 {
+        let txn = storage.new_txn().unwrap();
+        txn.put(b"b", format!("{}", round).as_bytes());
+        let id = txn.prepare().unwrap();
+        let results = std::thread::scope(|s| {
+            let handles = (0..4)
+                .map(|i| {
+                    let storage = &storage;
+                    s.spawn(move || {
+                        if i % 2 == 0 {
+                            storage.commit_prepared(id).is_ok()
+                        } else {
+                            storage.rollback_prepared(id).is_ok()
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(results.iter().filter(|ok| **ok).count(), 1);
+        assert!(storage.prepared_txns().is_empty());
+        // the keys are unlocked once
+        storage.put(b"b", b"x").unwrap();
+    }
+}
+
+#[test]
+fn test_prepared_reads_are_protected() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"c1", b"1").unwrap();
+
+    // the prepared txn read `a` and scanned `c..d`, and wrote `b`
+    let txn = storage.new_txn().unwrap();
+    txn.get(b"a").unwrap();
+    let mut iter = txn
+        .scan(
+            std::ops::Bound::Included(b"c"),
+            std::ops::Bound::Excluded(b"d"),
+        )
+        .unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+    drop(iter);
+    txn.put(b"b", b"1");
+    let id = txn.prepare().unwrap();
+    drop(txn);
+
+    // the reads cannot be overwritten before the prepared txn commits
+    assert!(storage.put(b"a", b"2").is_err());
+    assert!(storage.put(b"c2", b"2").is_err());
+    let txn = storage.new_pessimistic_txn().unwrap();
+    txn.put(b"a", b"2").unwrap();
+    assert!(txn.commit().is_err());
+    drop(txn);
+    // another txn reading the writes of the prepared txn cannot be prepared
+    let txn = storage.new_txn().unwrap();
+    txn.get(b"b").unwrap();
+    txn.put(b"e", b"1");
+    assert!(txn.prepare().is_err());
+    storage.put(b"e", b"2").unwrap();
+
+    storage.commit_prepared(id).unwrap();
+    storage.put(b"a", b"2").unwrap();
+    storage.put(b"c2", b"2").unwrap();
+}
+
+#[test]
+fn test_prepared_reads_recovery() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    txn.get(b"a").unwrap();
+    let mut iter = txn
+        .scan(
+            std::ops::Bound::Excluded(b"c"),
+            std::ops::Bound::Included(b"d"),
+        )
+        .unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+    drop(iter);
+    txn.put(b"b", b"1");
+    let id = txn.prepare().unwrap();
+    drop(txn);
+    // the reads are logged again into the new WAL
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    drop(storage);
+
+    // the reads are still protected after a crash
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.prepared_txns(), vec![id]);
+    assert!(storage.put(b"a", b"2").is_err());
+    assert!(storage.put(b"d", b"2").is_err());
+    storage.put(b"c", b"2").unwrap();
+    storage.commit_prepared(id).unwrap();
+    storage.put(b"a", b"2").unwrap();
+    storage.put(b"d", b"2").unwrap();
+}
+
+#[test]
+fn test_prepare_without_wal() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a", b"1");
+    assert!(txn.prepare().is_err());
+    assert!(storage.prepared_txns().is_empty());
+    // the txn can still be committed
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+}
@@ -0,0 +1,78 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::mvcc::txn::TxnConflictError;
+
+fn open_serializable(
+    txn_key_set_memory_limit: Option<usize>,
+) -> (tempfile::TempDir, std::sync::Arc<MiniLsm>) {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    options.txn_key_set_memory_limit = txn_key_set_memory_limit;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    (dir, storage)
+}
+
+#[test]
+fn test_conflict_error_names_key() {
+    let (_dir, storage) = open_serializable(None);
+    storage.put(b"key1", b"1").unwrap();
+    storage.put(b"key2", b"1").unwrap();
+
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    txn1.get(b"key1").unwrap();
+    txn1.put(b"key2", b"2");
+    txn2.put(b"key1", b"2");
+    txn2.commit().unwrap();
+    let commit_ts = storage.inner.mvcc().latest_commit_ts();
+    let err = txn1.commit().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<TxnConflictError>(),
+        Some(&TxnConflictError {
+            key: Some(Bytes::from_static(b"key1")),
+            commit_ts,
+        })
+    );
+}
+
+#[test]
+fn test_memory_bounded_key_set() {
+    let (_dir, storage) = open_serializable(Some(16));
+    for i in 0..10 {
+        storage.put(format!("key{}", i).as_bytes(), b"1").unwrap();
+    }
+
+    // the read set of txn1 falls back to hashes, the write set of txn2 is still exact
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    for i in 0..10 {
+        txn1.get(format!("key{}", i).as_bytes()).unwrap();
+    }
+    txn1.put(b"sum", b"10");
+    txn2.put(b"key5", b"2");
+    txn2.commit().unwrap();
+    let commit_ts = storage.inner.mvcc().latest_commit_ts();
+    let err = txn1.commit().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<TxnConflictError>(),
+        Some(&TxnConflictError {
+            key: Some(Bytes::from_static(b"key5")),
+            commit_ts,
+        })
+    );
+
+    // no conflict when the hashed read set does not contain the written key
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    for i in 0..10 {
+        txn1.get(format!("key{}", i).as_bytes()).unwrap();
+    }
+    txn1.put(b"sum", b"11");
+    txn2.put(b"other", b"2");
+    txn2.commit().unwrap();
+    txn1.commit().unwrap();
+}
@@ -0,0 +1,202 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key_{:05}", i).into_bytes()
+}
+
+#[test]
+fn test_txn_spill_to_disk() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.txn_spill_threshold = Some(1024);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"key_00000", b"old").unwrap();
+    storage.put(b"other", b"old").unwrap();
+
+    let txn = storage.new_txn().unwrap();
+    for i in 0..1000 {
+        txn.put(&key_of(i), format!("value_{}", i).as_bytes());
+    }
+    let spilled_ids = txn
+        .spilled_runs
+        .lock()
+        .iter()
+        .map(|run| run.sst_id())
+        .collect::<Vec<_>>();
+    assert!(spilled_ids.len() > 1);
+    assert!(txn.local_storage.len() < 1000);
+    // overwrite and delete keys that have been spilled
+    txn.put(&key_of(1), b"new");
+    txn.delete(&key_of(2));
+    assert_eq!(
+        txn.get(&key_of(0)).unwrap(),
+        Some(Bytes::from_static(b"value_0"))
+    );
+    assert_eq!(
+        txn.get(&key_of(1)).unwrap(),
+        Some(Bytes::from_static(b"new"))
+    );
+    assert_eq!(txn.get(&key_of(2)).unwrap(), None);
+
+    let mut iter = txn
+        .scan(Bound::Excluded(&key_of(0)), Bound::Included(&key_of(4)))
+        .unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    drop(iter);
+    assert_eq!(
+        entries,
+        vec![
+            (key_of(1), b"new".to_vec()),
+            (key_of(3), b"value_3".to_vec()),
+            (key_of(4), b"value_4".to_vec()),
+        ]
+    );
+
+    txn.commit().unwrap();
+    drop(txn);
+    for id in spilled_ids {
+        assert!(!storage.inner.path_of_sst(id).exists());
+    }
+    assert_eq!(
+        storage.get(&key_of(0)).unwrap(),
+        Some(Bytes::from_static(b"value_0"))
+    );
+    assert_eq!(
+        storage.get(&key_of(1)).unwrap(),
+        Some(Bytes::from_static(b"new"))
+    );
+    assert_eq!(storage.get(&key_of(2)).unwrap(), None);
+    assert_eq!(
+        storage.get(&key_of(999)).unwrap(),
+        Some(Bytes::from_static(b"value_999"))
+    );
+    assert_eq!(
+        storage.get(b"other").unwrap(),
+        Some(Bytes::from_static(b"old"))
+    );
+}
+
+#[test]
+fn test_txn_spill_with_savepoint() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.serializable = true;
+    options.txn_spill_threshold = Some(64);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"before", b"value");
+    txn.set_savepoint();
+    for i in 0..100 {
+        txn.put(&key_of(i), b"value");
+    }
+    txn.set_savepoint();
+    for i in 50..150 {
+        txn.put(&key_of(i), b"overwritten");
+    }
+    txn.delete(b"before");
+    assert!(txn.spilled_runs.lock().len() > 2);
+
+    // the runs spilled after the inner savepoint are dropped
+    txn.rollback_to_savepoint().unwrap();
+    assert_eq!(
+        txn.get(b"before").unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+    for i in [0, 50, 99] {
+        assert_eq!(
+            txn.get(&key_of(i)).unwrap(),
+            Some(Bytes::from_static(b"value"))
+        );
+    }
+    assert_eq!(txn.get(&key_of(149)).unwrap(), None);
+
+    // and the writes kept in memory at the outer savepoint come back
+    txn.rollback_to_savepoint().unwrap();
+    assert!(txn.spilled_runs.lock().is_empty());
+    assert_eq!(
+        txn.get(b"before").unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+    assert_eq!(txn.get(&key_of(0)).unwrap(), None);
+    for i in 0..50 {
+        txn.put(&key_of(i), b"value");
+    }
+
+    // the keys written after the savepoints are not in the write set anymore
+    let reader = storage.new_txn().unwrap();
+    reader.get(&key_of(149)).unwrap();
+    reader.put(b"other", b"value");
+    txn.commit().unwrap();
+    reader.commit().unwrap();
+    assert_eq!(
+        storage.get(&key_of(49)).unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+    assert_eq!(storage.get(&key_of(50)).unwrap(), None);
+}
+
+#[test]
+fn test_txn_spill_error() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.txn_spill_threshold = Some(64);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let txn = storage.new_txn().unwrap();
+    // the spilled runs cannot be created without the directory
+    std::fs::remove_dir_all(&dir).unwrap();
+    for i in 0..100 {
+        txn.put(&key_of(i), b"value");
+    }
+    assert!(txn.spilled_runs.lock().is_empty());
+    assert_eq!(
+        txn.get(&key_of(0)).unwrap(),
+        Some(Bytes::from_static(b"value"))
+    );
+    assert!(txn.commit().is_err());
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+}
+
+#[test]
+fn test_txn_rollback_to_savepoint_after_spill() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.serializable = true;
+    options.txn_spill_threshold = Some(64);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let txn = storage.new_txn().unwrap();
+    for i in 0..10 {
+        txn.put(&key_of(i), b"spilled");
+    }
+    assert!(!txn.spilled_runs.lock().is_empty());
+
+    // the spilled writes come back after the rollback
+    txn.set_savepoint();
+    txn.put(&key_of(0), b"value");
+    txn.delete(&key_of(1));
+    txn.put(&key_of(100), b"value");
+    txn.rollback_to_savepoint().unwrap();
+    for i in 0..2 {
+        assert_eq!(
+            txn.get(&key_of(i)).unwrap(),
+            Some(Bytes::from_static(b"spilled"))
+        );
+    }
+    assert_eq!(txn.get(&key_of(100)).unwrap(), None);
+
+    // and they are still in the write set
+    let reader = storage.new_txn().unwrap();
+    reader.get(&key_of(0)).unwrap();
+    reader.put(b"other", b"value");
+    txn.commit().unwrap();
+    assert!(reader.commit().is_err());
+}
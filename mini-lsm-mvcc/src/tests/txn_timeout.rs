@@ -0,0 +1,109 @@
+use std::ops::Bound;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_txn_timeout() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+
+    let txn = storage
+        .new_txn_with_timeout(Duration::from_millis(200))
+        .unwrap();
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    assert_eq!(storage.inner.mvcc().watermark(), txn.read_ts);
+    txn.put(b"c", b"1");
+
+    std::thread::sleep(Duration::from_millis(300));
+    // the reader is released even though the txn is still alive
+    assert_eq!(
+        storage.inner.mvcc().watermark(),
+        storage.inner.mvcc().latest_commit_ts()
+    );
+    assert!(storage.active_readers().is_empty());
+    assert!(txn.get(b"a").is_err());
+    assert!(txn.scan(Bound::Unbounded, Bound::Unbounded).is_err());
+    assert!(iter.next().is_err());
+    drop(iter);
+    assert!(txn.commit().is_err());
+    drop(txn);
+    assert_eq!(storage.get(b"c").unwrap(), None);
+
+    // transactions without a timeout are not affected
+    let txn = storage.new_txn().unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(storage.inner.mvcc().watermark(), txn.read_ts);
+    txn.put(b"c", b"1");
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from_static(b"1")));
+}
+
+#[test]
+fn test_txn_timeout_option() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.txn_timeout = Some(Duration::from_millis(200));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+
+    let txn = storage.new_read_only_txn().unwrap();
+    assert_eq!(txn.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    let pessimistic_txn = storage.new_pessimistic_txn().unwrap();
+    pessimistic_txn.put(b"b", b"1").unwrap();
+    let snapshot = storage.snapshot();
+    let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(storage.active_readers().is_empty());
+    assert!(txn.get(b"a").is_err());
+    assert!(pessimistic_txn.get(b"a").is_err());
+    assert!(pessimistic_txn.put(b"c", b"1").is_err());
+    assert!(pessimistic_txn.commit().is_err());
+    assert!(snapshot.get(b"a").is_err());
+    assert!(iter.next().is_err());
+    drop(pessimistic_txn);
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    // the writes without a txn never expire
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"2")));
+}
+
+#[test]
+fn test_active_readers() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+
+    let (txn, txn_line) = (storage.new_txn().unwrap(), line!());
+    storage.put(b"a", b"2").unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    let (snapshot, snapshot_line) = (storage.snapshot(), line!());
+
+    let readers = storage.active_readers();
+    assert_eq!(readers.len(), 2);
+    // the oldest reader comes first
+    assert_eq!(readers[0].read_ts, txn.read_ts);
+    assert_eq!(readers[0].location.file(), file!());
+    assert_eq!(readers[0].location.line(), txn_line);
+    assert_eq!(readers[1].read_ts, snapshot.read_ts());
+    assert_eq!(readers[1].location.line(), snapshot_line);
+    assert!(readers[0].age > readers[1].age);
+
+    drop(txn);
+    let readers = storage.active_readers();
+    assert_eq!(readers.len(), 1);
+    assert_eq!(readers[0].read_ts, snapshot.read_ts());
+    drop(snapshot);
+    assert!(storage.active_readers().is_empty());
+}
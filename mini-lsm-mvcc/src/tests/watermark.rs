@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_watermark_concurrent_readers() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let storage = storage.clone();
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            let mut i = 0;
+            while !stop.load(Ordering::SeqCst) {
+                storage
+                    .put(format!("{}", i % 100).as_bytes(), b"v")
+                    .unwrap();
+                i += 1;
+            }
+        })
+    };
+    let readers = (0..8)
+        .map(|_| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                let mut txns = Vec::new();
+                for i in 0..2000 {
+                    let txn = storage.new_txn().unwrap();
+                    // the watermark never goes past an active reader
+                    assert!(storage.inner.mvcc().watermark() <= txn.read_ts);
+                    assert!(txn.read_ts <= storage.inner.mvcc().latest_commit_ts());
+                    txns.push(txn);
+                    if i % 3 == 0 {
+                        txns.remove(0);
+                    }
+                    for txn in &txns {
+                        assert!(storage.inner.mvcc().watermark() <= txn.read_ts);
+                    }
+                    if txns.len() > 10 {
+                        txns.clear();
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    stop.store(true, Ordering::SeqCst);
+    writer.join().unwrap();
+    assert_eq!(
+        storage.inner.mvcc().watermark(),
+        storage.inner.mvcc().latest_commit_ts()
+    );
+
+    // a snapshot in the past holds the watermark back
+    let txn = storage.new_txn().unwrap();
+    storage.put(b"a", b"1").unwrap();
+    let snapshot = storage
+        .inner
+        .mvcc()
+        .new_snapshot_at(storage.inner.clone(), txn.read_ts)
+        .unwrap();
+    drop(txn);
+    storage.put(b"a", b"2").unwrap();
+    assert_eq!(storage.inner.mvcc().watermark(), snapshot.read_ts());
+    drop(snapshot);
+    assert_eq!(
+        storage.inner.mvcc().watermark(),
+        storage.inner.mvcc().latest_commit_ts()
+    );
+}
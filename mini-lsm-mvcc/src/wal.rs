@@ -1,6 +1,7 @@
 use std::fs::{File, OpenOptions};
 use std::hash::Hasher;
-use std::io::{BufWriter, Read, Write};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -11,6 +12,174 @@ use parking_lot::Mutex;
 
 use crate::key::{KeyBytes, KeySlice};
 
+/// A record of a two-phase commit. It is stored as an entry with an empty key, which a write
+/// never has, with the transaction id as the ts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxnRecord {
+    /// A write of a prepared transaction, an empty value is a deletion.
+    Write {
+        id: u64,
+        key: Bytes,
+        value: Bytes,
+    },
+    /// A key read by a serializable transaction.
+    Read {
+        id: u64,
+        key: Bytes,
+    },
+    /// The hash of a key read by a serializable transaction whose read set fell back to hashes.
+    ReadHash {
+        id: u64,
+        hash: u32,
+    },
+    /// A range scanned by a serializable transaction.
+    ReadRange {
+        id: u64,
+        lower: Bound<Bytes>,
+        upper: Bound<Bytes>,
+    },
+    /// All the writes and reads of the transaction are logged before it.
+    Prepare {
+        id: u64,
+        read_ts: u64,
+    },
+    /// The commit decision, which is logged before the writes are applied at the commit ts.
+    Commit {
+        id: u64,
+        commit_ts: u64,
+    },
+    Rollback(u64),
+}
+
+const TXN_RECORD_WRITE: u8 = 0;
+const TXN_RECORD_PREPARE: u8 = 1;
+const TXN_RECORD_COMMIT: u8 = 2;
+const TXN_RECORD_ROLLBACK: u8 = 3;
+const TXN_RECORD_READ: u8 = 4;
+const TXN_RECORD_READ_HASH: u8 = 5;
+const TXN_RECORD_READ_RANGE: u8 = 6;
+
+const BOUND_UNBOUNDED: u8 = 0;
+const BOUND_INCLUDED: u8 = 1;
+const BOUND_EXCLUDED: u8 = 2;
+
+fn encode_bound(buf: &mut Vec<u8>, bound: &Bound<Bytes>) {
+    let (tag, key) = match bound {
+        Bound::Unbounded => {
+            buf.put_u8(BOUND_UNBOUNDED);
+            return;
+        }
+        Bound::Included(key) => (BOUND_INCLUDED, key),
+        Bound::Excluded(key) => (BOUND_EXCLUDED, key),
+    };
+    buf.put_u8(tag);
+    buf.put_u16(key.len() as u16);
+    buf.put_slice(key);
+}
+
+fn decode_bound(buf: &mut &[u8]) -> Result<Bound<Bytes>> {
+    let tag = buf.get_u8();
+    if tag == BOUND_UNBOUNDED {
+        return Ok(Bound::Unbounded);
+    }
+    let key_len = buf.get_u16() as usize;
+    let key = Bytes::copy_from_slice(&buf[..key_len]);
+    buf.advance(key_len);
+    match tag {
+        BOUND_INCLUDED => Ok(Bound::Included(key)),
+        BOUND_EXCLUDED => Ok(Bound::Excluded(key)),
+        tag => bail!("unknown bound {}", tag),
+    }
+}
+
+impl TxnRecord {
+    fn id(&self) -> u64 {
+        match self {
+            Self::Write { id, .. }
+            | Self::Read { id, .. }
+            | Self::ReadHash { id, .. }
+            | Self::ReadRange { id, .. }
+            | Self::Prepare { id, .. }
+            | Self::Commit { id, .. } => *id,
+            Self::Rollback(id) => *id,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Self::Write { key, value, .. } => {
+                buf.put_u8(TXN_RECORD_WRITE);
+                buf.put_u16(key.len() as u16);
+                buf.put_slice(key);
+                buf.put_slice(value);
+            }
+            Self::Read { key, .. } => {
+                buf.put_u8(TXN_RECORD_READ);
+                buf.put_slice(key);
+            }
+            Self::ReadHash { hash, .. } => {
+                buf.put_u8(TXN_RECORD_READ_HASH);
+                buf.put_u32(*hash);
+            }
+            Self::ReadRange { lower, upper, .. } => {
+                buf.put_u8(TXN_RECORD_READ_RANGE);
+                encode_bound(&mut buf, lower);
+                encode_bound(&mut buf, upper);
+            }
+            Self::Prepare { read_ts, .. } => {
+                buf.put_u8(TXN_RECORD_PREPARE);
+                buf.put_u64(*read_ts);
+            }
+            Self::Commit { commit_ts, .. } => {
+                buf.put_u8(TXN_RECORD_COMMIT);
+                buf.put_u64(*commit_ts);
+            }
+            Self::Rollback(_) => buf.put_u8(TXN_RECORD_ROLLBACK),
+        }
+        buf
+    }
+
+    fn decode(id: u64, mut buf: &[u8]) -> Result<Self> {
+        let record = match buf.get_u8() {
+            TXN_RECORD_WRITE => {
+                let key_len = buf.get_u16() as usize;
+                let key = Bytes::copy_from_slice(&buf[..key_len]);
+                buf.advance(key_len);
+                Self::Write {
+                    id,
+                    key,
+                    value: Bytes::copy_from_slice(buf),
+                }
+            }
+            TXN_RECORD_READ => Self::Read {
+                id,
+                key: Bytes::copy_from_slice(buf),
+            },
+            TXN_RECORD_READ_HASH => Self::ReadHash {
+                id,
+                hash: buf.get_u32(),
+            },
+            TXN_RECORD_READ_RANGE => Self::ReadRange {
+                id,
+                lower: decode_bound(&mut buf)?,
+                upper: decode_bound(&mut buf)?,
+            },
+            TXN_RECORD_PREPARE => Self::Prepare {
+                id,
+                read_ts: buf.get_u64(),
+            },
+            TXN_RECORD_COMMIT => Self::Commit {
+                id,
+                commit_ts: buf.get_u64(),
+            },
+            TXN_RECORD_ROLLBACK => Self::Rollback(id),
+            tag => bail!("unknown txn record {}", tag),
+        };
+        Ok(record)
+    }
+}
+
 pub struct Wal {
     file: Arc<Mutex<BufWriter<File>>>,
 }
@@ -29,7 +198,12 @@ impl Wal {
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
+    /// Recovers the writes into `skiplist`, and the two-phase commit records into `txn_records`.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<KeyBytes, Bytes>,
+        txn_records: &mut Vec<TxnRecord>,
+    ) -> Result<Self> {
         let path = path.as_ref();
         let mut file = OpenOptions::new()
             .read(true)
@@ -40,30 +214,59 @@ impl Wal {
         file.read_to_end(&mut buf)?;
         let mut rbuf: &[u8] = buf.as_slice();
         while rbuf.has_remaining() {
-            let mut hasher = crc32fast::Hasher::new();
-            let key_len = rbuf.get_u16() as usize;
-            hasher.write_u16(key_len as u16);
-            let key = Bytes::copy_from_slice(&rbuf[..key_len]);
-            hasher.write(&key);
-            rbuf.advance(key_len);
-            let ts = rbuf.get_u64();
-            hasher.write_u64(ts);
-            let value_len = rbuf.get_u16() as usize;
-            hasher.write_u16(value_len as u16);
-            let value = Bytes::copy_from_slice(&rbuf[..value_len]);
-            hasher.write(&value);
-            rbuf.advance(value_len);
-            let checksum = rbuf.get_u32();
-            if hasher.finalize() != checksum {
-                bail!("checksum mismatch");
+            let (key, ts, value) = Self::decode_entry(&mut rbuf)?;
+            if key.is_empty() {
+                txn_records.push(TxnRecord::decode(ts, &value)?);
+            } else {
+                skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
             }
-            skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
         }
         Ok(Self {
             file: Arc::new(Mutex::new(BufWriter::new(file))),
         })
     }
 
+    /// Reads the writes of a WAL that is no longer written, starting at `offset`. Each write
+    /// comes with the offset after it, where a later read can continue from. The two-phase
+    /// commit records are skipped.
+    pub fn read_from(path: impl AsRef<Path>, offset: u64) -> Result<Vec<(KeyBytes, Bytes, u64)>> {
+        let mut file = File::open(path).context("failed to read WAL")?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let mut rbuf: &[u8] = buf.as_slice();
+        let mut entries = Vec::new();
+        while rbuf.has_remaining() {
+            let (key, ts, value) = Self::decode_entry(&mut rbuf)?;
+            if !key.is_empty() {
+                let end = offset + (buf.len() - rbuf.remaining()) as u64;
+                entries.push((KeyBytes::from_bytes_with_ts(key, ts), value, end));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn decode_entry(rbuf: &mut &[u8]) -> Result<(Bytes, u64, Bytes)> {
+        let mut hasher = crc32fast::Hasher::new();
+        let key_len = rbuf.get_u16() as usize;
+        hasher.write_u16(key_len as u16);
+        let key = Bytes::copy_from_slice(&rbuf[..key_len]);
+        hasher.write(&key);
+        rbuf.advance(key_len);
+        let ts = rbuf.get_u64();
+        hasher.write_u64(ts);
+        let value_len = rbuf.get_u16() as usize;
+        hasher.write_u16(value_len as u16);
+        let value = Bytes::copy_from_slice(&rbuf[..value_len]);
+        hasher.write(&value);
+        rbuf.advance(value_len);
+        let checksum = rbuf.get_u32();
+        if hasher.finalize() != checksum {
+            bail!("checksum mismatch");
+        }
+        Ok((key, ts, value))
+    }
+
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
         let mut file = self.file.lock();
         let mut buf: Vec<u8> =
@@ -85,6 +288,10 @@ impl Wal {
         Ok(())
     }
 
+    pub fn put_txn_record(&self, record: &TxnRecord) -> Result<()> {
+        self.put(KeySlice::from_slice(b"", record.id()), &record.encode())
+    }
+
     pub fn sync(&self) -> Result<()> {
         let mut file = self.file.lock();
         file.flush()?;
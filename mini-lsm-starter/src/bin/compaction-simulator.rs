@@ -110,12 +110,13 @@ impl MockStorage {
         id
     }
 
-    pub fn flush_sst_to_new_tier(&mut self) {
+    pub fn flush_sst_to_new_tier(&mut self) -> usize {
         let id = self.generate_sst_id();
         self.snapshot.levels.insert(0, (id, vec![id]));
         self.file_list.insert(id, id);
         self.total_flushes += 1;
         self.total_writes += 1;
+        id
     }
 
     pub fn remove(&mut self, files_to_remove: &[usize]) {
@@ -339,17 +340,32 @@ fn main() {
             min_merge_width,
             iterations,
         } => {
+            // the options of the mvcc crate have more fields
+            #[allow(clippy::needless_update)]
             let controller = TieredCompactionController::new(TieredCompactionOptions {
                 num_tiers: level0_file_num_compaction_trigger,
                 max_size_amplification_percent,
                 size_ratio,
                 min_merge_width,
+                ..Default::default()
             });
             let mut storage = MockStorage::new();
             let mut max_space = 0;
             for i in 0..iterations {
                 println!("=== Iteration {i} ===");
-                storage.flush_sst_to_new_tier();
+                let id = storage.flush_sst_to_new_tier();
+                // the tiered compaction of the mvcc crate looks at the sizes and key ranges of
+                // the SSTs, so every SST has the same size
+                let (first_key, last_key) = generate_random_key_range();
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(SsTable::create_meta_only(
+                        id,
+                        1024 * 1024,
+                        first_key,
+                        last_key,
+                    )),
+                );
                 println!("--- After Flush ---");
                 if dump_real_id {
                     storage.dump_real_id(false, false);
@@ -362,14 +378,40 @@ fn main() {
                     println!("--- Compaction Task ---");
                     controller.generate_compaction_task(&storage.snapshot)
                 } {
+                    let input_files = task
+                        .tiers
+                        .iter()
+                        .flat_map(|(_, files)| files)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    let begin = input_files
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].first_key().clone())
+                        .min()
+                        .unwrap();
+                    let end = input_files
+                        .iter()
+                        .map(|id| storage.snapshot.sstables[id].last_key().clone())
+                        .max()
+                        .unwrap();
+                    let splits = generate_random_split(begin, end, input_files.len());
                     let mut sst_ids = Vec::new();
+                    for (idx, file) in input_files.iter().enumerate() {
+                        let new_sst_id = storage.generate_sst_id();
+                        sst_ids.push(new_sst_id);
+                        storage.file_list.insert(new_sst_id, *file);
+                        storage.total_writes += 1;
+                        storage.snapshot.sstables.insert(
+                            new_sst_id,
+                            Arc::new(SsTable::create_meta_only(
+                                new_sst_id,
+                                1024 * 1024,
+                                splits[idx].0.clone(),
+                                splits[idx].1.clone(),
+                            )),
+                        );
+                    }
                     for (tier_id, files) in &task.tiers {
-                        for file in files {
-                            let new_sst_id = storage.generate_sst_id();
-                            sst_ids.push(new_sst_id);
-                            storage.file_list.insert(new_sst_id, *file);
-                            storage.total_writes += 1;
-                        }
                         print!("L{} {:?} ", tier_id, files);
                     }
                     println!("-> {:?}", sst_ids);
@@ -430,11 +472,14 @@ fn main() {
             iterations,
             sst_size_mb,
         } => {
+            // the options of the mvcc crate have more fields
+            #[allow(clippy::needless_update)]
             let controller = LeveledCompactionController::new(LeveledCompactionOptions {
                 level0_file_num_compaction_trigger,
                 level_size_multiplier,
                 max_levels,
                 base_level_size_mb,
+                ..Default::default()
             });
 
             let mut storage = MockStorage::new();
@@ -311,6 +311,8 @@ impl ReplBuilder {
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    // the options of the mvcc crate have more fields
+    #[allow(clippy::needless_update)]
     let lsm = MiniLsm::open(
         args.path,
         LsmStorageOptions {
@@ -331,6 +333,7 @@ fn main() -> Result<()> {
                     max_size_amplification_percent: 200,
                     size_ratio: 1,
                     min_merge_width: 2,
+                    ..Default::default()
                 }),
                 CompactionStrategy::Leveled => {
                     CompactionOptions::Leveled(LeveledCompactionOptions {
@@ -338,11 +341,13 @@ fn main() -> Result<()> {
                         max_levels: 4,
                         base_level_size_mb: 128,
                         level_size_multiplier: 2,
+                        ..Default::default()
                     })
                 }
             },
             enable_wal: args.enable_wal,
             serializable: args.serializable,
+            ..LsmStorageOptions::default_for_week1_test()
         },
     )?;
 
@@ -8,6 +8,8 @@ use crate::{
 use super::harness::{check_compaction_ratio, compaction_bench};
 
 #[test]
+// the options of the mvcc crate have more fields
+#[allow(clippy::needless_update)]
 fn test_integration() {
     let dir = tempdir().unwrap();
     let storage = MiniLsm::open(
@@ -18,6 +20,7 @@ fn test_integration() {
                 max_size_amplification_percent: 200,
                 size_ratio: 1,
                 min_merge_width: 2,
+                ..Default::default()
             },
         )),
     )